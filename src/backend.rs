@@ -0,0 +1,252 @@
+use anyhow::Result;
+use async_trait::async_trait;
+use chrono::{DateTime, Utc};
+use google_calendar3::api::{CalendarListEntry, Colors, Event};
+
+/// The calendar operations the sync pipeline needs. Implemented for Google Calendar in
+/// `calendar::GoogleCalendar`; other providers only need to speak Google's `Event` shape.
+#[async_trait]
+pub trait CalendarBackend: Send + Sync {
+    /// All calendars visible to the authenticated user
+    async fn list_calendars(&self) -> Result<Vec<CalendarListEntry>>;
+
+    /// The calendar and event color palettes
+    async fn list_colors(&self) -> Result<Colors>;
+
+    /// Events overlapping `[time_min, time_max)`. A recurring event is returned once, as its
+    /// series (with `recurrence` set), plus any occurrences that were changed on their own.
+    async fn list_events(
+        &self,
+        calendar_id: &str,
+        time_min: DateTime<Utc>,
+        time_max: DateTime<Utc>,
+    ) -> Result<Vec<Event>>;
+
+    /// Create an event, returning it as stored (with its new ID)
+    async fn insert_event(&self, calendar_id: &str, event: Event) -> Result<Event>;
+
+    /// Overwrite the fields set in `event` on an existing event
+    async fn patch_event(&self, calendar_id: &str, event_id: &str, event: Event) -> Result<Event>;
+
+    async fn delete_event(&self, calendar_id: &str, event_id: &str) -> Result<()>;
+
+    /// Create several events, returning one result per event in order. The default inserts
+    /// them one at a time; backends with a bulk API (Google's batch endpoint) override it.
+    async fn insert_events(&self, calendar_id: &str, events: Vec<Event>) -> Vec<Result<Event>> {
+        let mut results = Vec::with_capacity(events.len());
+        for event in events {
+            results.push(self.insert_event(calendar_id, event).await);
+        }
+        results
+    }
+
+    /// Delete several events, returning one result per ID in order
+    async fn delete_events(&self, calendar_id: &str, event_ids: &[String]) -> Vec<Result<()>> {
+        let mut results = Vec::with_capacity(event_ids.len());
+        for event_id in event_ids {
+            results.push(self.delete_event(calendar_id, event_id).await);
+        }
+        results
+    }
+
+    /// Retries and throttling of the backend's requests so far, for the run summary
+    fn request_summary(&self) -> Option<String> {
+        None
+    }
+}
+
+#[cfg(test)]
+pub use memory::{concert, listed_event, BackendCall, MemoryCalendar};
+
+#[cfg(test)]
+mod memory {
+    use super::*;
+    use crate::calendar::{self, ConvertOptions};
+    use crate::event::CalendarEvent;
+    use anyhow::Context;
+    use chrono::{NaiveDate, NaiveTime};
+    use std::collections::HashMap;
+    use std::sync::Mutex;
+
+    /// A mutating call made against a `MemoryCalendar`
+    #[derive(Debug, Clone, PartialEq, Eq)]
+    pub enum BackendCall {
+        Insert { calendar_id: String, summary: String },
+        Patch { calendar_id: String, event_id: String },
+        Delete { calendar_id: String, event_id: String },
+    }
+
+    /// In-memory calendar for tests: stores events per calendar and records every write
+    #[derive(Default)]
+    pub struct MemoryCalendar {
+        events: Mutex<HashMap<String, Vec<Event>>>,
+        calls: Mutex<Vec<BackendCall>>,
+        next_id: Mutex<usize>,
+        failing: Vec<String>,
+    }
+
+    impl MemoryCalendar {
+        pub fn new() -> Self {
+            Self::default()
+        }
+
+        /// Seed an event directly, bypassing the call log
+        pub fn with_event(self, calendar_id: &str, mut event: Event) -> Self {
+            if event.id.is_none() {
+                event.id = Some(self.allocate_id());
+            }
+            self.events.lock().unwrap().entry(calendar_id.to_string()).or_default().push(event);
+            self
+        }
+
+        /// Make writes fail for events with this summary (inserts) or ID (patches and deletes)
+        pub fn failing_on(mut self, key: &str) -> Self {
+            self.failing.push(key.to_string());
+            self
+        }
+
+        fn check_failure(&self, key: &str) -> Result<()> {
+            if self.failing.iter().any(|k| k == key) {
+                anyhow::bail!("injected failure for {}", key);
+            }
+            Ok(())
+        }
+
+        pub fn events(&self, calendar_id: &str) -> Vec<Event> {
+            self.events.lock().unwrap().get(calendar_id).cloned().unwrap_or_default()
+        }
+
+        pub fn calls(&self) -> Vec<BackendCall> {
+            self.calls.lock().unwrap().clone()
+        }
+
+        fn allocate_id(&self) -> String {
+            let mut next_id = self.next_id.lock().unwrap();
+            *next_id += 1;
+            format!("evt{}", next_id)
+        }
+    }
+
+    fn start_instant(event: &Event) -> Option<DateTime<Utc>> {
+        let start = event.start.as_ref()?;
+        start
+            .date_time
+            .or_else(|| start.date.and_then(|d| d.and_hms_opt(0, 0, 0)).map(|dt| dt.and_utc()))
+    }
+
+    #[async_trait]
+    impl CalendarBackend for MemoryCalendar {
+        async fn list_calendars(&self) -> Result<Vec<CalendarListEntry>> {
+            let events = self.events.lock().unwrap();
+            Ok(events
+                .keys()
+                .map(|id| CalendarListEntry {
+                    id: Some(id.clone()),
+                    summary: Some(id.clone()),
+                    ..Default::default()
+                })
+                .collect())
+        }
+
+        async fn list_colors(&self) -> Result<Colors> {
+            Ok(Colors::default())
+        }
+
+        async fn list_events(
+            &self,
+            calendar_id: &str,
+            time_min: DateTime<Utc>,
+            time_max: DateTime<Utc>,
+        ) -> Result<Vec<Event>> {
+            Ok(self
+                .events(calendar_id)
+                .into_iter()
+                // A series is assumed to have occurrences in any range after its first one
+                .filter(|e| {
+                    start_instant(e).is_some_and(|t| {
+                        t < time_max && (t >= time_min || crate::calendar::is_series(e))
+                    })
+                })
+                .collect())
+        }
+
+        async fn insert_event(&self, calendar_id: &str, mut event: Event) -> Result<Event> {
+            self.check_failure(event.summary.as_deref().unwrap_or_default())?;
+            self.calls.lock().unwrap().push(BackendCall::Insert {
+                calendar_id: calendar_id.to_string(),
+                summary: event.summary.clone().unwrap_or_default(),
+            });
+            event.id = Some(self.allocate_id());
+            self.events
+                .lock()
+                .unwrap()
+                .entry(calendar_id.to_string())
+                .or_default()
+                .push(event.clone());
+            Ok(event)
+        }
+
+        async fn patch_event(&self, calendar_id: &str, event_id: &str, event: Event) -> Result<Event> {
+            self.check_failure(event_id)?;
+            self.calls.lock().unwrap().push(BackendCall::Patch {
+                calendar_id: calendar_id.to_string(),
+                event_id: event_id.to_string(),
+            });
+            let mut events = self.events.lock().unwrap();
+            let existing = events
+                .get_mut(calendar_id)
+                .and_then(|list| list.iter_mut().find(|e| e.id.as_deref() == Some(event_id)))
+                .with_context(|| format!("No event {} in {}", event_id, calendar_id))?;
+
+            // Patch semantics: only fields present in the request are replaced
+            macro_rules! merge {
+                ($($field:ident),*) => { $(if event.$field.is_some() { existing.$field = event.$field; })* };
+            }
+            merge!(summary, description, location, color_id, start, end, recurrence, reminders, attendees, extended_properties);
+            Ok(existing.clone())
+        }
+
+        async fn delete_event(&self, calendar_id: &str, event_id: &str) -> Result<()> {
+            self.check_failure(event_id)?;
+            self.calls.lock().unwrap().push(BackendCall::Delete {
+                calendar_id: calendar_id.to_string(),
+                event_id: event_id.to_string(),
+            });
+            let mut events = self.events.lock().unwrap();
+            let list = events.get_mut(calendar_id).context("Unknown calendar")?;
+            let before = list.len();
+            list.retain(|e| e.id.as_deref() != Some(event_id));
+            anyhow::ensure!(list.len() < before, "No event {} in {}", event_id, calendar_id);
+            Ok(())
+        }
+    }
+
+    /// A 19:30 concert on `day` of March 2026, stamped with row `source_id` of `csv:season.csv`
+    pub fn concert(title: &str, day: u32, source_id: Option<&str>) -> CalendarEvent {
+        let date = NaiveDate::from_ymd_opt(2026, 3, day).unwrap();
+        CalendarEvent {
+            title: title.to_string(),
+            description: None,
+            location: Some("Davies Hall".to_string()),
+            organization: None,
+            purchased: true,
+            start_date: date,
+            start_time: NaiveTime::from_hms_opt(19, 30, 0),
+            end_date: date,
+            end_time: NaiveTime::from_hms_opt(22, 0, 0),
+            timezone: None,
+            source: source_id.map(|_| "csv:season.csv".to_string()),
+            source_id: source_id.map(str::to_string),
+            recurrence: None,
+            reminders: None,
+            attendees: Vec::new(),
+        }
+    }
+
+    /// `event` as a backend would list it once created, under `id`
+    pub fn listed_event(id: &str, event: &CalendarEvent) -> Event {
+        let mut gcal = calendar::convert_to_google_event(event, &ConvertOptions::default()).unwrap();
+        gcal.id = Some(id.to_string());
+        gcal
+    }
+}
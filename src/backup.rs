@@ -0,0 +1,157 @@
+//! Calendar backups: Google events saved as JSON, exactly as the API listed them.
+//!
+//! `import --delete` and `sync --prune` back up the events they are about to delete before
+//! deleting anything, and `backup` saves any calendar and date range on demand. `restore
+//! --from` re-creates the events of a backup that are missing from the calendar.
+
+use anyhow::{Context, Result};
+use chrono::{DateTime, NaiveDate, Utc};
+use google_calendar3::api::Event;
+use serde::{Deserialize, Serialize};
+use std::collections::HashSet;
+use std::path::{Path, PathBuf};
+
+use crate::calendar;
+use crate::config;
+
+/// Backups are kept in `$XDG_STATE_HOME/calendar-sync/backups` (see `config::state_dir`)
+pub fn backups_dir() -> PathBuf {
+    config::state_dir("backups")
+}
+
+/// A snapshot of some of a calendar's events
+#[derive(Debug, Serialize, Deserialize)]
+pub struct Backup {
+    pub calendar_id: String,
+    pub taken_at: DateTime<Utc>,
+    /// What the backup was taken for, e.g. "sync --prune"
+    pub reason: String,
+    /// The dates the backup covers
+    pub start_date: NaiveDate,
+    pub end_date: NaiveDate,
+    pub events: Vec<Event>,
+}
+
+impl Backup {
+    /// A backup of `events`, covering `range` or else the span of their dates
+    pub fn new(calendar_id: &str, reason: &str, range: Option<(NaiveDate, NaiveDate)>, events: Vec<Event>) -> Self {
+        let dates = || events.iter().filter_map(calendar::extract_event_date);
+        let (start_date, end_date) = range.unwrap_or_else(|| {
+            let today = Utc::now().date_naive();
+            (dates().min().unwrap_or(today), dates().max().unwrap_or(today))
+        });
+        Self {
+            calendar_id: calendar_id.to_string(),
+            taken_at: Utc::now(),
+            reason: reason.to_string(),
+            start_date,
+            end_date,
+            events,
+        }
+    }
+
+    pub fn load(path: &Path) -> Result<Self> {
+        let contents = std::fs::read_to_string(path)
+            .with_context(|| format!("Failed to read backup {}", path.display()))?;
+        serde_json::from_str(&contents).with_context(|| format!("Failed to parse backup {}", path.display()))
+    }
+
+    /// Write the backup to `path`, or by default to a file in `backups_dir()` named after the
+    /// time and calendar. Returns where it went.
+    pub fn save(&self, path: Option<&Path>) -> Result<PathBuf> {
+        let path = match path {
+            Some(path) => path.to_path_buf(),
+            None => self.default_path(),
+        };
+        if let Some(dir) = path.parent().filter(|dir| !dir.as_os_str().is_empty()) {
+            std::fs::create_dir_all(dir)
+                .with_context(|| format!("Failed to create directory {}", dir.display()))?;
+        }
+        let json = serde_json::to_string_pretty(self).context("Failed to serialize the backup")?;
+        std::fs::write(&path, json + "\n").with_context(|| format!("Failed to write backup {}", path.display()))?;
+        Ok(path)
+    }
+
+    /// e.g. `20261017-201530-concerts_group.calendar.google.com.json`
+    fn default_path(&self) -> PathBuf {
+        let calendar: String = self
+            .calendar_id
+            .chars()
+            .map(|c| if c.is_ascii_alphanumeric() || matches!(c, '-' | '.') { c } else { '_' })
+            .collect();
+        let stem = format!("{}-{}", self.taken_at.format("%Y%m%d-%H%M%S"), calendar);
+        let dir = backups_dir();
+        let mut path = dir.join(format!("{}.json", stem));
+        let mut n = 2;
+        while path.exists() {
+            path = dir.join(format!("{}-{}.json", stem, n));
+            n += 1;
+        }
+        path
+    }
+
+    /// The events to re-create: those whose ID and source stamp are both absent from
+    /// `existing`. Changed occurrences of a recurring event are left out; they come back with
+    /// their series, not on their own.
+    pub fn missing_from(&self, existing: &[Event]) -> Vec<&Event> {
+        let ids: HashSet<&str> = existing.iter().filter_map(|e| e.id.as_deref()).collect();
+        let stamps: HashSet<(&str, &str)> = existing.iter().filter_map(calendar::source_key).collect();
+        self.events
+            .iter()
+            .filter(|event| !calendar::is_instance(event))
+            .filter(|event| !event.id.as_deref().is_some_and(|id| ids.contains(id)))
+            .filter(|event| !calendar::source_key(event).is_some_and(|stamp| stamps.contains(&stamp)))
+            .collect()
+    }
+}
+
+/// Save the events a run is about to delete, so they can be restored; nothing is saved
+/// when there are none
+pub fn before_delete(calendar_id: &str, reason: &str, events: Vec<Event>) -> Result<Option<PathBuf>> {
+    if events.is_empty() {
+        return Ok(None);
+    }
+    let count = events.len();
+    let path = Backup::new(calendar_id, reason, None, events)
+        .save(None)
+        .context("Failed to back up the events to be deleted; nothing was deleted")?;
+    tracing::info!("Backed up {} events to {}", count, path.display());
+    Ok(Some(path))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::backend::{concert, listed_event};
+
+    #[test]
+    fn test_backup_round_trip_and_missing_events() {
+        let events = vec![
+            listed_event("evt1", &concert("Mahler 9", 14, Some("1"))),
+            listed_event("evt2", &concert("Piano Recital", 16, None)),
+            listed_event("evt3", &concert("Brahms 4", 15, Some("3"))),
+        ];
+        let backup = Backup::new("concerts@group.calendar.google.com", "sync --prune", None, events);
+        assert_eq!((backup.start_date.to_string(), backup.end_date.to_string()), ("2026-03-14".into(), "2026-03-16".into()));
+
+        let path = std::env::temp_dir().join(format!("calendar-sync-backup-{}.json", std::process::id()));
+        backup.save(Some(&path)).unwrap();
+        let loaded = Backup::load(&path).unwrap();
+        std::fs::remove_file(&path).unwrap();
+        assert_eq!(loaded.events.len(), 3);
+        assert_eq!(calendar::source_key(&loaded.events[0]), Some(("csv:season.csv", "1")));
+
+        // evt1 is still there; evt3 was re-created under a new ID but keeps its source stamp
+        let existing = [
+            listed_event("evt1", &concert("Mahler 9", 14, Some("1"))),
+            listed_event("evt9", &concert("Brahms 4", 15, Some("3"))),
+        ];
+        let missing: Vec<&str> = loaded.missing_from(&existing).iter().filter_map(|e| e.summary.as_deref()).collect();
+        assert_eq!(missing, ["Piano Recital"]);
+
+        assert!(backup.default_path().ends_with(format!(
+            "{}-concerts_group.calendar.google.com.json",
+            backup.taken_at.format("%Y%m%d-%H%M%S")
+        )));
+    }
+}
@@ -0,0 +1,537 @@
+//! Google's batch endpoint: up to 50 Calendar API calls in one `multipart/mixed` HTTP request.
+//!
+//! Each call is sent as an `application/http` part tagged with a `Content-ID`, and comes back
+//! as a part of the response tagged `response-<id>`, in any order. Batching saves round trips,
+//! but Google still counts every inner call against the quota, so each one waits its turn at
+//! the rate limiter. Inner calls that fail with a retryable status are re-sent in a smaller
+//! batch; the ones that succeeded are not repeated.
+//!
+//! A batch that may have reached Google without an answer coming back (a timeout, or a
+//! connection dropped mid-request) is only re-sent if every call in it is safe to repeat.
+//! Re-sending an insert could create its event twice.
+
+use anyhow::{Context, Result};
+use std::collections::HashMap;
+use std::fmt::Write as _;
+use std::time::Duration;
+
+use crate::retry::{self, RequestPolicy};
+
+/// Google Calendar accepts at most 50 calls per batch
+pub const MAX_BATCH_SIZE: usize = 50;
+
+const BATCH_URL: &str = "https://www.googleapis.com/batch/calendar/v3";
+
+/// How long to wait for a batch's response
+const BATCH_TIMEOUT: Duration = Duration::from_secs(120);
+
+/// One API call inside a batch
+#[derive(Debug, Clone, PartialEq)]
+pub struct BatchRequest {
+    pub method: &'static str,
+    /// Path and query, e.g. `/calendar/v3/calendars/primary/events?sendUpdates=all`
+    pub path: String,
+    pub body: Option<serde_json::Value>,
+}
+
+impl BatchRequest {
+    /// Whether sending the call twice has the same effect as sending it once. Deletes are
+    /// (a repeat gets 410 Gone); inserts are not.
+    pub fn is_idempotent(&self) -> bool {
+        self.method != "POST"
+    }
+}
+
+/// The response to one call inside a batch
+#[derive(Debug, Clone, PartialEq)]
+pub struct BatchResponse {
+    pub status: u16,
+    pub retry_after: Option<Duration>,
+    pub body: String,
+}
+
+impl BatchResponse {
+    pub fn is_success(&self) -> bool {
+        (200..300).contains(&self.status)
+    }
+
+    /// The error as Google describes it, e.g. "HTTP 404: Not Found"
+    pub fn error_message(&self) -> String {
+        let message = serde_json::from_str::<serde_json::Value>(&self.body)
+            .ok()
+            .and_then(|v| v.pointer("/error/message").and_then(|m| m.as_str()).map(str::to_string));
+        match message {
+            Some(message) => format!("HTTP {}: {}", self.status, message),
+            None => format!("HTTP {}", self.status),
+        }
+    }
+}
+
+/// Percent-encode a URL path segment, e.g. a calendar ID containing `@` or `#`
+pub fn encode_segment(segment: &str) -> String {
+    let mut encoded = String::with_capacity(segment.len());
+    for byte in segment.bytes() {
+        match byte {
+            b'A'..=b'Z' | b'a'..=b'z' | b'0'..=b'9' | b'-' | b'.' | b'_' | b'~' => encoded.push(byte as char),
+            _ => write!(encoded, "%{:02X}", byte).unwrap(),
+        }
+    }
+    encoded
+}
+
+/// The multipart body for `requests`, each given with the index used as its `Content-ID`
+pub fn encode(requests: &[(usize, &BatchRequest)], boundary: &str) -> String {
+    let mut body = String::new();
+    for (index, request) in requests {
+        write!(body, "--{}\r\n", boundary).unwrap();
+        body.push_str("Content-Type: application/http\r\n");
+        write!(body, "Content-ID: <item{}>\r\n\r\n", index).unwrap();
+        write!(body, "{} {} HTTP/1.1\r\n", request.method, request.path).unwrap();
+        match &request.body {
+            Some(json) => {
+                let json = json.to_string();
+                body.push_str("Content-Type: application/json\r\n");
+                write!(body, "Content-Length: {}\r\n\r\n{}\r\n", json.len(), json).unwrap();
+            }
+            None => body.push_str("\r\n"),
+        }
+    }
+    write!(body, "--{}--\r\n", boundary).unwrap();
+    body
+}
+
+/// Split a response into its parts' `BatchResponse`s, keyed by the index each request was
+/// sent with
+pub fn parse(content_type: &str, body: &str) -> Result<HashMap<usize, BatchResponse>> {
+    let boundary = content_type
+        .split(';')
+        .find_map(|param| param.trim().strip_prefix("boundary="))
+        .map(|b| b.trim_matches('"'))
+        .with_context(|| format!("Batch response has no boundary: {}", content_type))?;
+    let delimiter = format!("--{}", boundary);
+
+    let mut responses = HashMap::new();
+    for part in body.split(delimiter.as_str()).skip(1) {
+        if part.starts_with("--") {
+            break;
+        }
+        let (headers, http) = split_head(part.trim_start_matches(['\r', '\n']))
+            .context("Batch response part has no body")?;
+        let index = header(headers, "Content-ID")
+            .and_then(|id| id.trim_matches(['<', '>']).strip_prefix("response-item"))
+            .and_then(|i| i.parse::<usize>().ok())
+            .with_context(|| format!("Batch response part has no usable Content-ID:\n{}", headers))?;
+
+        let (head, content) = split_head(http).unwrap_or((http, ""));
+        let status = head
+            .lines()
+            .next()
+            .and_then(|line| line.split_whitespace().nth(1))
+            .and_then(|code| code.parse::<u16>().ok())
+            .with_context(|| format!("Batch response part {} has no status line", index))?;
+        let retry_after = header(head, "Retry-After").and_then(retry::parse_retry_after);
+        responses.insert(index, BatchResponse {
+            status,
+            retry_after,
+            body: content.trim_end_matches(['\r', '\n']).to_string(),
+        });
+    }
+    Ok(responses)
+}
+
+/// Split at the first blank line into (headers, rest)
+fn split_head(s: &str) -> Option<(&str, &str)> {
+    let crlf = s.find("\r\n\r\n").map(|i| (i, 4));
+    let lf = s.find("\n\n").map(|i| (i, 2));
+    let (at, len) = match (crlf, lf) {
+        (Some(a), Some(b)) => a.min(b),
+        (a, b) => a.or(b)?,
+    };
+    Some((&s[..at], &s[at + len..]))
+}
+
+/// A header's value from a block of `Name: value` lines, matching the name case-insensitively
+fn header<'a>(headers: &'a str, name: &str) -> Option<&'a str> {
+    headers.lines().find_map(|line| {
+        let (key, value) = line.split_once(':')?;
+        key.trim().eq_ignore_ascii_case(name).then(|| value.trim())
+    })
+}
+
+/// Sends batches to Google's batch endpoint
+pub struct BatchClient {
+    client: reqwest::Client,
+    url: String,
+    timeout: Duration,
+}
+
+impl BatchClient {
+    /// The URL can be overridden with `GOOGLE_CALENDAR_BATCH_URL` (e.g. to point at a local
+    /// mock server)
+    pub fn new() -> Self {
+        let url = std::env::var("GOOGLE_CALENDAR_BATCH_URL").unwrap_or_else(|_| BATCH_URL.to_string());
+        Self::with_url(url)
+    }
+
+    pub fn with_url(url: String) -> Self {
+        Self { client: reqwest::Client::new(), url, timeout: BATCH_TIMEOUT }
+    }
+
+    /// Give up on a batch's response after `timeout` instead of `BATCH_TIMEOUT`
+    #[cfg(test)]
+    pub fn with_timeout(mut self, timeout: Duration) -> Self {
+        self.timeout = timeout;
+        self
+    }
+
+    /// Run `requests` in batches of up to `MAX_BATCH_SIZE`, returning one response per
+    /// request in order. Calls that fail with a retryable status are re-sent on their own, as
+    /// often as `policy` allows; whatever they last returned is reported. An error means the
+    /// call never got a response.
+    pub async fn execute(
+        &self,
+        token: &str,
+        requests: &[BatchRequest],
+        policy: &RequestPolicy,
+    ) -> Vec<Result<BatchResponse>> {
+        let mut results = Vec::with_capacity(requests.len());
+        for (chunk_index, chunk) in requests.chunks(MAX_BATCH_SIZE).enumerate() {
+            let offset = chunk_index * MAX_BATCH_SIZE;
+            let indexed: Vec<(usize, &BatchRequest)> =
+                chunk.iter().enumerate().map(|(i, r)| (offset + i, r)).collect();
+            results.extend(self.execute_batch(token, indexed, policy).await);
+        }
+        results
+    }
+
+    async fn execute_batch(
+        &self,
+        token: &str,
+        requests: Vec<(usize, &BatchRequest)>,
+        policy: &RequestPolicy,
+    ) -> Vec<Result<BatchResponse>> {
+        let count = requests.len();
+        let first = requests.first().map_or(0, |(i, _)| *i);
+        let mut results: Vec<Option<Result<BatchResponse>>> = (0..count).map(|_| None).collect();
+        let mut pending = requests;
+        let mut attempt = 0;
+
+        while !pending.is_empty() {
+            for _ in &pending {
+                policy.throttle().await;
+            }
+            let (reason, retry_after) = match self.send(token, &pending).await {
+                Ok(mut responses) => {
+                    let mut retry = Vec::new();
+                    let mut retry_after = None;
+                    for (index, request) in pending {
+                        match responses.remove(&index) {
+                            Some(response) if retry::is_retryable_response(response.status, &response.body) => {
+                                retry_after = retry_after.max(response.retry_after);
+                                results[index - first] = Some(Ok(response));
+                                retry.push((index, request));
+                            }
+                            Some(response) => results[index - first] = Some(Ok(response)),
+                            None => {
+                                results[index - first] = Some(Err(anyhow::anyhow!("No response in the batch")));
+                                if request.is_idempotent() {
+                                    retry.push((index, request));
+                                }
+                            }
+                        }
+                    }
+                    pending = retry;
+                    if pending.is_empty() {
+                        break;
+                    }
+                    (format!("{} of {} batched requests failed", pending.len(), count), retry_after)
+                }
+                Err(BatchError { error, retryable, retry_after }) => {
+                    let reason = format!("{:#}", error);
+                    if !retryable {
+                        for (index, _) in &pending {
+                            results[index - first] = Some(Err(anyhow::anyhow!("{}", reason)));
+                        }
+                        break;
+                    }
+                    for (index, _) in &pending {
+                        results[index - first].get_or_insert_with(|| Err(anyhow::anyhow!("{}", reason)));
+                    }
+                    (reason, retry_after)
+                }
+            };
+
+            attempt += 1;
+            match policy.retry_after(attempt, &reason, retry_after) {
+                Some(delay) => tokio::time::sleep(delay).await,
+                None => break,
+            }
+        }
+
+        results
+            .into_iter()
+            .map(|r| r.unwrap_or_else(|| Err(anyhow::anyhow!("Request was not sent"))))
+            .collect()
+    }
+
+    /// POST one batch and parse its parts
+    async fn send(&self, token: &str, requests: &[(usize, &BatchRequest)]) -> Result<HashMap<usize, BatchResponse>, BatchError> {
+        let boundary = format!("batch_calendar_sync_{}", requests.first().map_or(0, |(i, _)| *i));
+        // Once the batch may have been sent, only a batch of idempotent calls can be repeated
+        let idempotent = requests.iter().all(|(_, request)| request.is_idempotent());
+        let response = self
+            .client
+            .post(&self.url)
+            .bearer_auth(token)
+            .header(reqwest::header::CONTENT_TYPE, format!("multipart/mixed; boundary={}", boundary))
+            .body(encode(requests, &boundary))
+            .timeout(self.timeout)
+            .send()
+            .await
+            .map_err(|e| BatchError {
+                retryable: e.is_connect() || (idempotent && (e.is_timeout() || e.is_request())),
+                error: anyhow::Error::new(e).context("Failed to send batch request"),
+                retry_after: None,
+            })?;
+
+        let status = response.status();
+        let retry_after = response
+            .headers()
+            .get(reqwest::header::RETRY_AFTER)
+            .and_then(|v| v.to_str().ok())
+            .and_then(retry::parse_retry_after);
+        let content_type = response
+            .headers()
+            .get(reqwest::header::CONTENT_TYPE)
+            .and_then(|v| v.to_str().ok())
+            .unwrap_or_default()
+            .to_string();
+        let body = response.text().await.map_err(|e| BatchError {
+            retryable: idempotent,
+            error: anyhow::Error::new(e).context("Failed to read batch response"),
+            retry_after: None,
+        })?;
+
+        if !status.is_success() {
+            return Err(BatchError {
+                retryable: retry::is_retryable_response(status.as_u16(), &body),
+                error: anyhow::anyhow!("Batch request failed ({}): {}", status, body),
+                retry_after,
+            });
+        }
+        parse(&content_type, &body).map_err(|error| BatchError { error, retryable: false, retry_after: None })
+    }
+}
+
+/// A batch that got no usable response at all
+struct BatchError {
+    error: anyhow::Error,
+    retryable: bool,
+    retry_after: Option<Duration>,
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::retry::RetryPolicy;
+    use tokio::io::{AsyncReadExt, AsyncWriteExt};
+    use tokio::net::TcpListener;
+
+    fn insert(title: &str) -> BatchRequest {
+        BatchRequest {
+            method: "POST",
+            path: format!("/calendar/v3/calendars/{}/events", encode_segment("team@group.calendar.google.com")),
+            body: Some(serde_json::json!({ "summary": title })),
+        }
+    }
+
+    #[test]
+    fn test_encode_batch() {
+        let delete = BatchRequest {
+            method: "DELETE",
+            path: "/calendar/v3/calendars/primary/events/evt1".to_string(),
+            body: None,
+        };
+        let insert = insert("Mahler 9");
+        let body = encode(&[(0, &insert), (7, &delete)], "b1");
+        assert_eq!(
+            body,
+            "--b1\r\n\
+             Content-Type: application/http\r\n\
+             Content-ID: <item0>\r\n\
+             \r\n\
+             POST /calendar/v3/calendars/team%40group.calendar.google.com/events HTTP/1.1\r\n\
+             Content-Type: application/json\r\n\
+             Content-Length: 22\r\n\
+             \r\n\
+             {\"summary\":\"Mahler 9\"}\r\n\
+             --b1\r\n\
+             Content-Type: application/http\r\n\
+             Content-ID: <item7>\r\n\
+             \r\n\
+             DELETE /calendar/v3/calendars/primary/events/evt1 HTTP/1.1\r\n\
+             \r\n\
+             --b1--\r\n"
+        );
+    }
+
+    #[test]
+    fn test_parse_batch_response() {
+        // Parts come back in any order, each with its own status
+        let body = "--batch_xyz\r\n\
+            Content-Type: application/http\r\n\
+            Content-ID: <response-item1>\r\n\
+            \r\n\
+            HTTP/1.1 429 Too Many Requests\r\n\
+            Retry-After: 3\r\n\
+            Content-Type: application/json\r\n\
+            \r\n\
+            {\"error\": {\"code\": 429, \"message\": \"Rate Limit Exceeded\"}}\r\n\
+            --batch_xyz\r\n\
+            Content-Type: application/http\r\n\
+            Content-ID: <response-item0>\r\n\
+            \r\n\
+            HTTP/1.1 200 OK\r\n\
+            Content-Type: application/json; charset=UTF-8\r\n\
+            \r\n\
+            {\"id\": \"evt1\", \"summary\": \"Mahler 9\"}\r\n\
+            --batch_xyz\r\n\
+            Content-Type: application/http\r\n\
+            Content-ID: <response-item2>\r\n\
+            \r\n\
+            HTTP/1.1 204 No Content\r\n\
+            \r\n\
+            \r\n\
+            --batch_xyz--\r\n";
+        let responses = parse("multipart/mixed; boundary=batch_xyz", body).unwrap();
+        assert_eq!(responses.len(), 3);
+
+        let created = &responses[&0];
+        assert!(created.is_success());
+        let event: google_calendar3::api::Event = serde_json::from_str(&created.body).unwrap();
+        assert_eq!(event.id.as_deref(), Some("evt1"));
+
+        let limited = &responses[&1];
+        assert_eq!((limited.status, limited.retry_after), (429, Some(Duration::from_secs(3))));
+        assert_eq!(limited.error_message(), "HTTP 429: Rate Limit Exceeded");
+
+        assert_eq!((responses[&2].status, responses[&2].body.as_str()), (204, ""));
+
+        assert!(parse("multipart/mixed", body).is_err());
+    }
+
+    /// Answer each part of each batch with `respond(round, content_id)`, and hand back the
+    /// request bodies
+    async fn mock_batch_server(
+        respond: fn(usize, usize) -> &'static str,
+        batches: usize,
+    ) -> (String, tokio::task::JoinHandle<Vec<String>>) {
+        let listener = TcpListener::bind("127.0.0.1:0").await.unwrap();
+        let url = format!("http://{}/batch/calendar/v3", listener.local_addr().unwrap());
+        let handle = tokio::spawn(async move {
+            let mut bodies = Vec::new();
+            for round in 0..batches {
+                let (mut socket, _) = listener.accept().await.unwrap();
+                let mut buf = Vec::new();
+                let mut chunk = vec![0u8; 8192];
+                let body = loop {
+                    let n = socket.read(&mut chunk).await.unwrap();
+                    buf.extend_from_slice(&chunk[..n]);
+                    let text = String::from_utf8_lossy(&buf).into_owned();
+                    if let Some((head, body)) = text.split_once("\r\n\r\n") {
+                        let length = header(head, "Content-Length").and_then(|l| l.parse::<usize>().ok()).unwrap_or(0);
+                        if body.len() >= length || n == 0 {
+                            break body.to_string();
+                        }
+                    }
+                };
+                let ids: Vec<usize> = body
+                    .lines()
+                    .filter_map(|l| l.strip_prefix("Content-ID: <item"))
+                    .filter_map(|l| l.trim_end_matches('>').parse().ok())
+                    .collect();
+                let mut response_body = String::new();
+                for id in ids {
+                    write!(
+                        response_body,
+                        "--resp\r\nContent-Type: application/http\r\nContent-ID: <response-item{}>\r\n\r\n{}\r\n",
+                        id,
+                        respond(round, id)
+                    )
+                    .unwrap();
+                }
+                response_body.push_str("--resp--\r\n");
+                let response = format!(
+                    "HTTP/1.1 200 OK\r\nContent-Type: multipart/mixed; boundary=resp\r\nContent-Length: {}\r\nConnection: close\r\n\r\n{}",
+                    response_body.len(),
+                    response_body
+                );
+                socket.write_all(response.as_bytes()).await.unwrap();
+                bodies.push(body);
+            }
+            bodies
+        });
+        (url, handle)
+    }
+
+    #[tokio::test]
+    async fn test_execute_retries_only_failed_requests() {
+        const CREATED: &str = "HTTP/1.1 200 OK\r\nContent-Type: application/json\r\n\r\n{\"id\": \"evt\"}";
+        const UNAVAILABLE: &str = "HTTP/1.1 503 Service Unavailable\r\nRetry-After: 0\r\n\r\n";
+        const NOT_FOUND: &str =
+            "HTTP/1.1 404 Not Found\r\n\r\n{\"error\": {\"code\": 404, \"message\": \"Not Found\"}}";
+        let (url, bodies) = mock_batch_server(
+            |round, id| match (round, id) {
+                (0, 1) => UNAVAILABLE,
+                (_, 2) => NOT_FOUND,
+                _ => CREATED,
+            },
+            2,
+        )
+        .await;
+
+        let client = BatchClient::with_url(url);
+        let policy = RequestPolicy::new("Test", RetryPolicy { base_delay: Duration::from_millis(1), ..Default::default() }, None);
+        let requests = vec![insert("Mahler 9"), insert("Piano Recital"), insert("Brahms 4")];
+        let results = client.execute("token", &requests, &policy).await;
+
+        let statuses: Vec<u16> = results.iter().map(|r| r.as_ref().unwrap().status).collect();
+        assert_eq!(statuses, [200, 200, 404]);
+        assert_eq!(results[2].as_ref().unwrap().error_message(), "HTTP 404: Not Found");
+        assert_eq!(policy.summary().as_deref(), Some("Test: 1 retry"));
+
+        // The second batch only carried the request that had failed
+        let bodies = bodies.await.unwrap();
+        assert_eq!(bodies[0].matches("Content-ID").count(), 3);
+        assert_eq!(bodies[1].matches("Content-ID").count(), 1);
+        assert!(bodies[1].contains("Piano Recital"));
+    }
+
+    #[tokio::test]
+    async fn test_execute_does_not_resend_inserts_after_a_timeout() {
+        // The server reads the batch (and may have applied it) but never answers
+        let listener = TcpListener::bind("127.0.0.1:0").await.unwrap();
+        let url = format!("http://{}/batch/calendar/v3", listener.local_addr().unwrap());
+        let server = tokio::spawn(async move {
+            let mut batches = 0;
+            let mut sockets = Vec::new();
+            while let Ok(Ok((mut socket, _))) =
+                tokio::time::timeout(Duration::from_millis(300), listener.accept()).await
+            {
+                let mut chunk = vec![0u8; 8192];
+                let _ = socket.read(&mut chunk).await;
+                batches += 1;
+                sockets.push(socket);
+            }
+            batches
+        });
+
+        let client = BatchClient::with_url(url).with_timeout(Duration::from_millis(50));
+        let policy = RequestPolicy::new("Test", RetryPolicy { base_delay: Duration::from_millis(1), ..Default::default() }, None);
+        let results = client.execute("token", &[insert("Mahler 9"), insert("Piano Recital")], &policy).await;
+
+        assert!(results.iter().all(Result::is_err));
+        assert_eq!(server.await.unwrap(), 1);
+        assert_eq!(policy.summary(), None);
+    }
+}
@@ -1,22 +1,53 @@
 use anyhow::{Context, Result};
-use chrono::{NaiveDate, TimeZone, Utc};
-use chrono_tz::America::Los_Angeles;
+use async_trait::async_trait;
+use chrono::{DateTime, Duration, NaiveDate, TimeZone, Utc};
+use chrono_tz::Tz;
+use google_calendar3::api::CalendarListEntry;
+use google_calendar3::api::{ColorDefinition, Colors};
 use google_calendar3::api::Event;
+use google_calendar3::api::EventAttendee;
 use google_calendar3::api::EventDateTime;
+use google_calendar3::api::EventExtendedProperties;
+use google_calendar3::api::{EventReminder, EventReminders};
 use google_calendar3::CalendarHub;
 use hyper::client::HttpConnector;
 use hyper_rustls::HttpsConnector;
+use serde::Deserialize;
+use futures::{Stream, StreamExt};
+use std::collections::HashMap;
+use std::fmt;
+use std::future::Future;
 use std::path::PathBuf;
 
-use crate::event::CalendarEvent;
+use crate::backend::CalendarBackend;
+use crate::batch::{self, BatchClient, BatchRequest, BatchResponse, MAX_BATCH_SIZE};
+use crate::event::{CalendarEvent, DstPolicy, DEFAULT_TIMEZONE};
+use crate::retry::{RequestOptions, RequestPolicy};
 
 type Hub = CalendarHub<HttpsConnector<HttpConnector>>;
 
 const CREDENTIALS_FILE: &str = "credentials.json";
 const TOKEN_CACHE_FILE: &str = "token_cache.json";
 
-pub async fn create_calendar_hub() -> Result<Hub> {
-    let credentials_path = get_credentials_path()?;
+/// Keys in `extendedProperties.private` identifying where an event came from
+const SOURCE_PROPERTY: &str = "calendarSyncSource";
+const SOURCE_ID_PROPERTY: &str = "calendarSyncId";
+
+/// yup-oauth2 brings its own hyper-rustls version, so name its connector explicitly
+pub type Authenticator = yup_oauth2::authenticator::Authenticator<
+    yup_oauth2::hyper_rustls::HttpsConnector<HttpConnector>,
+>;
+
+const CALENDAR_SCOPE: &str = "https://www.googleapis.com/auth/calendar";
+
+/// OAuth scopes requested up front by `auth`, so one consent covers every command
+pub const AUTH_SCOPES: &[&str] = &[CALENDAR_SCOPE, crate::sheets::SHEETS_SCOPE];
+
+/// Build the installed-app OAuth flow shared by the Calendar and Sheets clients.
+/// Tokens are cached on disk, so the browser is only opened when no cached token covers
+/// the requested scopes.
+pub async fn create_authenticator(paths: &AuthPaths) -> Result<Authenticator> {
+    let credentials_path = paths.credentials_path()?;
     
     let secret = yup_oauth2::read_application_secret(&credentials_path)
         .await
@@ -29,16 +60,20 @@ pub async fn create_calendar_hub() -> Result<Hub> {
             )
         })?;
 
-    let token_cache_path = get_token_cache_path()?;
+    let token_cache_path = paths.token_cache_path()?;
     
-    let auth = yup_oauth2::InstalledFlowAuthenticator::builder(
+    yup_oauth2::InstalledFlowAuthenticator::builder(
         secret,
         yup_oauth2::InstalledFlowReturnMethod::HTTPRedirect,
     )
     .persist_tokens_to_disk(&token_cache_path)
     .build()
     .await
-    .context("Failed to create authenticator")?;
+    .context("Failed to create authenticator")
+}
+
+async fn create_calendar_hub(paths: &AuthPaths) -> Result<Hub> {
+    let auth = create_authenticator(paths).await?;
 
     let client = hyper::Client::builder().build(
         hyper_rustls::HttpsConnectorBuilder::new()
@@ -52,52 +87,565 @@ pub async fn create_calendar_hub() -> Result<Hub> {
     Ok(CalendarHub::new(client, auth))
 }
 
-pub async fn list_calendars(hub: &Hub) -> Result<()> {
-    let (_, calendar_list) = hub
-        .calendar_list()
-        .list()
-        .doit()
-        .await
-        .context("Failed to list calendars")?;
+/// Default for `--jobs`: enough parallel requests to hide latency at the default rate limit
+pub const DEFAULT_JOBS: usize = 4;
+
+/// Google Calendar, accessed through the generated `CalendarHub` client
+pub struct GoogleCalendar {
+    hub: Hub,
+    send_updates: Option<SendUpdates>,
+    requests: RequestPolicy,
+    /// Bulk inserts and deletes go through Google's batch endpoint
+    batch: BatchClient,
+}
+
+/// Authenticate with Google and return a backend for the Calendar API
+pub async fn connect(paths: &AuthPaths) -> Result<GoogleCalendar> {
+    Ok(GoogleCalendar {
+        hub: create_calendar_hub(paths).await?,
+        send_updates: None,
+        requests: google_requests(RequestOptions::default()),
+        batch: BatchClient::new(),
+    })
+}
+
+fn google_requests(options: RequestOptions) -> RequestPolicy {
+    RequestPolicy::new("Google Calendar", options.retry, Some(options.rate_limit))
+}
+
+impl GoogleCalendar {
+    /// Retry and rate-limit requests as `--max-retries` and `--rate-limit` say
+    pub fn with_requests(mut self, options: RequestOptions) -> Self {
+        self.requests = google_requests(options);
+        self
+    }
+
+    /// Who Google should email when events with guests are created, changed or deleted
+    /// (Google's default is nobody)
+    pub fn with_send_updates(mut self, send_updates: Option<SendUpdates>) -> Self {
+        self.send_updates = send_updates;
+        self
+    }
+
+    /// Run `requests` through the batch endpoint, authenticated as the hub is
+    async fn execute_batch(&self, requests: &[BatchRequest]) -> Vec<Result<BatchResponse>> {
+        let token = match self.hub.auth.get_token(&[CALENDAR_SCOPE]).await {
+            Ok(Some(token)) => token,
+            Ok(None) => return batch_failed(requests.len(), "No access token for Google Calendar"),
+            Err(e) => return batch_failed(requests.len(), &format!("Failed to get an access token: {}", e)),
+        };
+        self.batch.execute(&token, requests, &self.requests).await
+    }
+
+    /// `/calendar/v3/calendars/{id}/events{suffix}`, with `sendUpdates` if set
+    fn events_path(&self, calendar_id: &str, suffix: &str) -> String {
+        let mut path = format!("/calendar/v3/calendars/{}/events{}", batch::encode_segment(calendar_id), suffix);
+        if let Some(send_updates) = self.send_updates {
+            path.push_str("?sendUpdates=");
+            path.push_str(send_updates.as_str());
+        }
+        path
+    }
+}
+
+fn batch_failed(count: usize, reason: &str) -> Vec<Result<BatchResponse>> {
+    (0..count).map(|_| Err(anyhow::anyhow!("{}", reason))).collect()
+}
+
+/// The Calendar API's `sendUpdates` setting for writes to events with guests
+#[derive(Debug, Clone, Copy, PartialEq, Eq, clap::ValueEnum, Deserialize)]
+pub enum SendUpdates {
+    /// Notify all guests
+    #[value(name = "all")]
+    #[serde(rename = "all")]
+    All,
+    /// Notify only guests outside your Google Workspace domain
+    #[value(name = "externalOnly")]
+    #[serde(rename = "externalOnly")]
+    ExternalOnly,
+    /// Notify nobody
+    #[value(name = "none")]
+    #[serde(rename = "none")]
+    None,
+}
+
+impl SendUpdates {
+    pub fn as_str(self) -> &'static str {
+        match self {
+            SendUpdates::All => "all",
+            SendUpdates::ExternalOnly => "externalOnly",
+            SendUpdates::None => "none",
+        }
+    }
+}
+
+#[async_trait]
+impl CalendarBackend for GoogleCalendar {
+    async fn list_calendars(&self) -> Result<Vec<CalendarListEntry>> {
+        self.requests.throttle().await;
+        let (_, calendar_list) = self
+            .hub
+            .calendar_list()
+            .list()
+            .delegate(&mut self.requests.delegate())
+            .doit()
+            .await
+            .context("Failed to list calendars")?;
+        Ok(calendar_list.items.unwrap_or_default())
+    }
+
+    async fn list_colors(&self) -> Result<Colors> {
+        self.requests.throttle().await;
+        let (_, colors) = self
+            .hub
+            .colors()
+            .get()
+            .delegate(&mut self.requests.delegate())
+            .doit()
+            .await
+            .context("Failed to fetch the color palette")?;
+        Ok(colors)
+    }
+
+    async fn list_events(
+        &self,
+        calendar_id: &str,
+        time_min: DateTime<Utc>,
+        time_max: DateTime<Utc>,
+    ) -> Result<Vec<Event>> {
+        let mut all_gcal_events = Vec::new();
+        let mut page_token: Option<String> = None;
+
+        loop {
+            let mut request = self
+                .hub
+                .events()
+                .list(calendar_id)
+                .time_min(time_min)
+                .time_max(time_max)
+                // Recurring events come back once, as their series, not as one event per instance
+                .single_events(false)
+                .max_results(2500);
+
+            if let Some(token) = &page_token {
+                request = request.page_token(token);
+            }
+
+            self.requests.throttle().await;
+            let (_, event_list) = request
+                .delegate(&mut self.requests.delegate())
+                .doit()
+                .await
+                .context("Failed to list calendar events")?;
+
+            if let Some(items) = event_list.items {
+                all_gcal_events.extend(items);
+            }
+
+            page_token = event_list.next_page_token;
+            if page_token.is_none() {
+                break;
+            }
+        }
+
+        Ok(all_gcal_events)
+    }
+
+    async fn insert_event(&self, calendar_id: &str, event: Event) -> Result<Event> {
+        let mut request = self.hub.events().insert(event, calendar_id);
+        if let Some(send_updates) = self.send_updates {
+            request = request.send_updates(send_updates.as_str());
+        }
+        self.requests.throttle().await;
+        let (_, created) = request.delegate(&mut self.requests.delegate()).doit().await?;
+        Ok(created)
+    }
+
+    async fn patch_event(&self, calendar_id: &str, event_id: &str, event: Event) -> Result<Event> {
+        let mut request = self.hub.events().patch(event, calendar_id, event_id);
+        if let Some(send_updates) = self.send_updates {
+            request = request.send_updates(send_updates.as_str());
+        }
+        self.requests.throttle().await;
+        let (_, patched) = request.delegate(&mut self.requests.delegate()).doit().await?;
+        Ok(patched)
+    }
+
+    async fn delete_event(&self, calendar_id: &str, event_id: &str) -> Result<()> {
+        let mut request = self.hub.events().delete(calendar_id, event_id);
+        if let Some(send_updates) = self.send_updates {
+            request = request.send_updates(send_updates.as_str());
+        }
+        self.requests.throttle().await;
+        match request.delegate(&mut self.requests.delegate()).doit().await {
+            // Already gone, e.g. an earlier attempt succeeded but its response was lost
+            Err(google_calendar3::Error::BadRequest(error))
+                if error.pointer("/error/code").and_then(|c| c.as_u64()) == Some(410) =>
+            {
+                tracing::debug!("Event {} was already deleted", event_id);
+                Ok(())
+            }
+            result => result.map(|_| ()).map_err(Into::into),
+        }
+    }
+
+    async fn insert_events(&self, calendar_id: &str, events: Vec<Event>) -> Vec<Result<Event>> {
+        let path = self.events_path(calendar_id, "");
+        let requests: Vec<BatchRequest> = events
+            .into_iter()
+            .map(|event| {
+                // Serialized the way the generated client does it, without nulls
+                let mut body = serde_json::to_value(event).expect("events serialize to JSON");
+                google_calendar3::client::remove_json_null_values(&mut body);
+                BatchRequest { method: "POST", path: path.clone(), body: Some(body) }
+            })
+            .collect();
+        self.execute_batch(&requests)
+            .await
+            .into_iter()
+            .map(|response| {
+                let response = response?;
+                if !response.is_success() {
+                    anyhow::bail!("{}", response.error_message());
+                }
+                serde_json::from_str(&response.body).context("Failed to parse the created event")
+            })
+            .collect()
+    }
+
+    async fn delete_events(&self, calendar_id: &str, event_ids: &[String]) -> Vec<Result<()>> {
+        let requests: Vec<BatchRequest> = event_ids
+            .iter()
+            .map(|event_id| BatchRequest {
+                method: "DELETE",
+                path: self.events_path(calendar_id, &format!("/{}", batch::encode_segment(event_id))),
+                body: None,
+            })
+            .collect();
+        self.execute_batch(&requests)
+            .await
+            .into_iter()
+            .zip(event_ids)
+            .map(|(response, event_id)| {
+                let response = response?;
+                if response.status == 410 {
+                    tracing::debug!("Event {} was already deleted", event_id);
+                } else if !response.is_success() {
+                    anyhow::bail!("{}", response.error_message());
+                }
+                Ok(())
+            })
+            .collect()
+    }
+
+    fn request_summary(&self) -> Option<String> {
+        self.requests.summary()
+    }
+}
+
+pub async fn list_calendars(backend: &dyn CalendarBackend) -> Result<()> {
+    let calendars = backend.list_calendars().await?;
 
     println!("\nAvailable Calendars:");
     println!("{:-<60}", "");
     
-    if let Some(items) = calendar_list.items {
-        for calendar in items {
-            let id = calendar.id.unwrap_or_default();
-            let summary = calendar.summary.unwrap_or_else(|| "(No name)".to_string());
-            let primary = if calendar.primary.unwrap_or(false) { " [PRIMARY]" } else { "" };
-            println!("  {} {}", summary, primary);
-            println!("    ID: {}", id);
-        }
+    for calendar in calendars {
+        let id = calendar.id.unwrap_or_default();
+        let summary = calendar.summary.unwrap_or_else(|| "(No name)".to_string());
+        let primary = if calendar.primary.unwrap_or(false) { " [PRIMARY]" } else { "" };
+        println!("  {} {}", summary, primary);
+        println!("    ID: {}", id);
     }
 
     Ok(())
 }
 
-pub async fn create_events(hub: &Hub, calendar_id: &str, events: &[CalendarEvent]) -> Result<()> {
-    for event in events {
-        let google_event = convert_to_google_event(event);
-        
-        hub.events()
-            .insert(google_event, calendar_id)
-            .doit()
-            .await
-            .with_context(|| format!("Failed to create event: {}", event.title))?;
+/// Print the event color palette, for choosing `color_id`s in color rules
+pub async fn list_colors(backend: &dyn CalendarBackend) -> Result<()> {
+    let colors = backend.list_colors().await?;
+    let mut palette: Vec<(String, ColorDefinition)> = colors.event.unwrap_or_default().into_iter().collect();
+    palette.sort_by_key(|(id, _)| id.parse::<u32>().unwrap_or(u32::MAX));
 
-        tracing::info!("Created event: {}", event.title);
+    println!("\nEvent Colors (use the ID as color_id in [[colors]] rules):");
+    println!("{:-<60}", "");
+    for (id, color) in palette {
+        let name = id
+            .parse::<usize>()
+            .ok()
+            .and_then(|i| EVENT_COLOR_NAMES.get(i.wrapping_sub(1)))
+            .copied()
+            .unwrap_or("");
+        println!("  {:>3}  {:<10} {}  (text {})",
+            id,
+            name,
+            color.background.as_deref().unwrap_or("-"),
+            color.foreground.as_deref().unwrap_or("-"),
+        );
     }
 
     Ok(())
 }
 
-fn convert_to_google_event(event: &CalendarEvent) -> Event {
-    let mut google_event = Event::default();
-    
-    google_event.summary = Some(event.title.clone());
-    google_event.description = event.description.clone();
-    google_event.location = event.location.clone();
+/// Create each event, in batches of up to `MAX_BATCH_SIZE` with up to `jobs` batches in
+/// flight. Results (logged as each batch completes) are in the order of `events`; a failed
+/// event doesn't stop the others.
+pub async fn create_events(
+    backend: &dyn CalendarBackend,
+    calendar_id: &str,
+    events: &[CalendarEvent],
+    options: &ConvertOptions,
+    jobs: usize,
+) -> Vec<Result<Event>> {
+    let titles: Vec<&str> = events.iter().map(|e| e.title.as_str()).collect();
+    let google_events = events.iter().map(|event| convert_to_google_event(event, options)).collect();
+    insert_events(backend, calendar_id, &titles, google_events, jobs).await
+}
+
+/// Insert Google events the way `create_events` does. An `Err` in `events` (e.g. an event
+/// that failed to convert) is passed through as that event's result.
+pub async fn insert_events(
+    backend: &dyn CalendarBackend,
+    calendar_id: &str,
+    titles: &[&str],
+    events: Vec<Result<Event>>,
+    jobs: usize,
+) -> Vec<Result<Event>> {
+    let mut results: Vec<Option<Result<Event>>> = Vec::with_capacity(events.len());
+    let mut ready = Vec::new();
+    for (index, event) in events.into_iter().enumerate() {
+        match event {
+            Ok(google_event) => {
+                ready.push((index, google_event));
+                results.push(None);
+            }
+            Err(e) => results.push(Some(Err(e))),
+        }
+    }
+
+    let mut stream = std::pin::pin!(run_jobs(in_batches(ready), jobs, |batch| async move {
+        let (indices, google_events): (Vec<usize>, Vec<Event>) = batch.into_iter().unzip();
+        indices.into_iter().zip(backend.insert_events(calendar_id, google_events).await)
+    }));
+    let mut logged = log_ready(&mut results, titles, ["create", "Created"]);
+    while let Some(batch) = stream.next().await {
+        for (index, result) in batch {
+            results[index] = Some(result);
+        }
+        logged += log_ready(&mut results[logged..], &titles[logged..], ["create", "Created"]);
+    }
+    finish(results)
+}
+
+/// `items` as a list of batches of up to `MAX_BATCH_SIZE`
+fn in_batches<T>(items: Vec<T>) -> Vec<Vec<T>> {
+    let mut batches = Vec::new();
+    let mut items = items.into_iter().peekable();
+    while items.peek().is_some() {
+        batches.push(items.by_ref().take(MAX_BATCH_SIZE).collect());
+    }
+    batches
+}
+
+/// Log the results at the front of `results` that are in, adding the event's name to any
+/// error, and return how many that was. `verbs` is e.g. `["create", "Created"]`.
+fn log_ready<T>(results: &mut [Option<Result<T>>], names: &[&str], verbs: [&str; 2]) -> usize {
+    let mut count = 0;
+    for (slot, name) in results.iter_mut().zip(names) {
+        let Some(result) = slot.take() else { break };
+        let result = result.with_context(|| format!("Failed to {} event: {}", verbs[0], name));
+        match &result {
+            Ok(_) => tracing::info!("{} event: {}", verbs[1], name),
+            Err(e) => tracing::warn!("{:#}", e),
+        }
+        *slot = Some(result);
+        count += 1;
+    }
+    count
+}
+
+/// Every slot is filled once all batches are in
+fn finish<T>(results: Vec<Option<Result<T>>>) -> Vec<Result<T>> {
+    results
+        .into_iter()
+        .map(|r| r.unwrap_or_else(|| Err(anyhow::anyhow!("No result from the batch"))))
+        .collect()
+}
+
+/// Run `op` on each item with up to `jobs` calls in flight, yielding results in input order.
+/// Every call still waits its turn at the backend's rate limiter, so more jobs only hide
+/// request latency; they can't exceed `--rate-limit`.
+pub fn run_jobs<I, F, Fut>(items: I, jobs: usize, op: F) -> impl Stream<Item = Fut::Output>
+where
+    I: IntoIterator,
+    F: FnMut(I::Item) -> Fut,
+    Fut: Future,
+{
+    futures::stream::iter(items).map(op).buffered(jobs.max(1))
+}
+
+/// An event a batch failed to write; the rest of the batch still ran
+#[derive(Debug)]
+pub struct EventFailure {
+    pub title: String,
+    pub error: anyhow::Error,
+}
+
+impl fmt::Display for EventFailure {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "{}: {:#}", self.title, self.error)
+    }
+}
+
+/// Settings that control how a `CalendarEvent` becomes a Google Calendar event
+#[derive(Debug, Clone, Default)]
+pub struct ConvertOptions {
+    /// How to resolve local times that fall in a DST gap or overlap
+    pub dst_policy: DstPolicy,
+    /// Event color rules from the config file; the first match wins
+    pub colors: Vec<ColorRule>,
+}
+
+impl ConvertOptions {
+    /// Color ID of the first rule the event matches, if any
+    pub fn color_for(&self, event: &CalendarEvent) -> Option<u8> {
+        self.colors.iter().find(|rule| rule.matches(event)).map(|rule| rule.color_id)
+    }
+}
+
+/// Google Calendar's fixed event palette, by color ID (as named in the Calendar web UI)
+pub const EVENT_COLOR_NAMES: [&str; 11] = [
+    "Lavender", "Sage", "Grape", "Flamingo", "Banana", "Tangerine", "Peacock", "Graphite", "Blueberry",
+    "Basil", "Tomato",
+];
+
+/// Assigns an event color to events that meet every condition given (a rule without
+/// conditions matches everything). Configured as `[[colors]]` tables:
+///
+/// ```toml
+/// [[colors]]
+/// organization = "SF Symphony"
+/// color_id = 9
+/// ```
+#[derive(Debug, Clone, PartialEq, Eq, Deserialize)]
+#[serde(deny_unknown_fields)]
+pub struct ColorRule {
+    /// Organization, compared case-insensitively
+    pub organization: Option<String>,
+    /// Location, compared case-insensitively
+    pub location: Option<String>,
+    pub purchased: Option<bool>,
+    /// Event color ID, 1 to 11 (see `list-colors`)
+    pub color_id: u8,
+}
+
+impl ColorRule {
+    pub fn matches(&self, event: &CalendarEvent) -> bool {
+        let same = |want: &Option<String>, have: &Option<String>| match (want, have) {
+            (Some(want), Some(have)) => want.eq_ignore_ascii_case(have.trim()),
+            (Some(_), None) => false,
+            (None, _) => true,
+        };
+        same(&self.organization, &event.organization)
+            && same(&self.location, &event.location)
+            && self.purchased.is_none_or(|purchased| purchased == event.purchased)
+    }
+
+    pub fn validate(&self) -> Result<()> {
+        anyhow::ensure!(
+            (1..=EVENT_COLOR_NAMES.len() as u8).contains(&self.color_id),
+            "color_id {} is not an event color; use 1 to {} (see list-colors)",
+            self.color_id,
+            EVENT_COLOR_NAMES.len()
+        );
+        Ok(())
+    }
+}
+
+impl std::fmt::Display for ColorRule {
+    /// e.g. `organization = "SF Symphony", purchased -> 9 (Blueberry)`
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        let mut conditions = Vec::new();
+        if let Some(organization) = &self.organization {
+            conditions.push(format!("organization = \"{}\"", organization));
+        }
+        if let Some(location) = &self.location {
+            conditions.push(format!("location = \"{}\"", location));
+        }
+        match self.purchased {
+            Some(true) => conditions.push("purchased".to_string()),
+            Some(false) => conditions.push("not purchased".to_string()),
+            None => {}
+        }
+        if conditions.is_empty() {
+            conditions.push("any event".to_string());
+        }
+        write!(f, "{} -> {}", conditions.join(", "), describe_color(self.color_id))
+    }
+}
+
+/// e.g. "9 (Blueberry)"
+pub fn describe_color(color_id: u8) -> String {
+    match EVENT_COLOR_NAMES.get((color_id as usize).wrapping_sub(1)) {
+        Some(name) => format!("{} ({})", color_id, name),
+        None => color_id.to_string(),
+    }
+}
+
+/// Build the Google Calendar event for a source event. Fails only if a time falls in a DST
+/// transition and the policy is `DstPolicy::Reject`.
+pub fn convert_to_google_event(event: &CalendarEvent, options: &ConvertOptions) -> Result<Event> {
+    let mut google_event = Event {
+        summary: Some(event.title.clone()),
+        description: event.description.clone(),
+        location: event.location.clone(),
+        color_id: options.color_for(event).map(|id| id.to_string()),
+        ..Default::default()
+    };
+
+    if let Some(recurrence) = &event.recurrence {
+        google_event.recurrence = Some(recurrence.to_lines(event.start_time, event.timezone()));
+    }
+
+    if !event.attendees.is_empty() {
+        google_event.attendees = Some(
+            event
+                .attendees
+                .iter()
+                .map(|a| EventAttendee {
+                    email: Some(a.email.clone()),
+                    optional: Some(a.optional),
+                    ..Default::default()
+                })
+                .collect(),
+        );
+    }
+
+    if let Some(reminders) = &event.reminders {
+        google_event.reminders = Some(EventReminders {
+            overrides: Some(
+                reminders
+                    .0
+                    .iter()
+                    .map(|r| EventReminder {
+                        method: Some(r.method.as_str().to_string()),
+                        minutes: Some(r.minutes as i32),
+                    })
+                    .collect(),
+            ),
+            use_default: Some(false),
+        });
+    }
+
+    // Stamp the source identity so later runs can find this event even after a rename or move
+    if let (Some(source), Some(source_id)) = (&event.source, &event.source_id) {
+        google_event.extended_properties = Some(EventExtendedProperties {
+            private: Some(HashMap::from([
+                (SOURCE_PROPERTY.to_string(), source.clone()),
+                (SOURCE_ID_PROPERTY.to_string(), source_id.clone()),
+            ])),
+            shared: None,
+        });
+    }
 
     if event.is_all_day() {
         // All-day event - use date only
@@ -115,35 +663,27 @@ fn convert_to_google_event(event: &CalendarEvent) -> Event {
             time_zone: None,
         });
     } else {
-        // Timed event - interpret naive datetime as Pacific time, convert to UTC
-        let start_dt = event.start_datetime();
-        let end_dt = event.end_datetime();
-
-        // Interpret the naive datetime as Pacific time and convert to UTC
-        let start_pacific = Los_Angeles.from_local_datetime(&start_dt)
-            .single()
-            .unwrap_or_else(|| Los_Angeles.from_local_datetime(&start_dt).latest().unwrap());
-        let end_pacific = Los_Angeles.from_local_datetime(&end_dt)
-            .single()
-            .unwrap_or_else(|| Los_Angeles.from_local_datetime(&end_dt).latest().unwrap());
+        // Timed event - interpret naive datetime in the event's time zone, convert to UTC
+        let tz = event.timezone();
+        let (start_local, end_local, _) = event.resolve_times(options.dst_policy)?;
 
-        let start_utc = start_pacific.with_timezone(&Utc);
-        let end_utc = end_pacific.with_timezone(&Utc);
+        let start_utc = start_local.with_timezone(&Utc);
+        let end_utc = end_local.with_timezone(&Utc);
 
         google_event.start = Some(EventDateTime {
             date: None,
             date_time: Some(start_utc),
-            time_zone: Some("America/Los_Angeles".to_string()),
+            time_zone: Some(tz.name().to_string()),
         });
 
         google_event.end = Some(EventDateTime {
             date: None,
             date_time: Some(end_utc),
-            time_zone: Some("America/Los_Angeles".to_string()),
+            time_zone: Some(tz.name().to_string()),
         });
     }
 
-    google_event
+    Ok(google_event)
 }
 
 /// Represents a Google Calendar event that was found
@@ -153,11 +693,86 @@ pub struct FoundCalendarEvent {
     pub title: String,
     pub date: NaiveDate,
     pub location: Option<String>,
+    /// True for a recurring series; deleting it deletes every occurrence
+    pub series: bool,
+    /// The event as listed, so a deletion can be undone
+    pub event: Event,
+}
+
+/// A copy of a listed event that can be inserted again: the ID and other fields Google
+/// assigns are cleared, so the copy is a new event with the same content
+pub fn reinsertable(event: &Event) -> Event {
+    Event {
+        id: None,
+        etag: None,
+        html_link: None,
+        i_cal_uid: None,
+        created: None,
+        updated: None,
+        creator: None,
+        organizer: None,
+        sequence: None,
+        status: None,
+        kind: None,
+        hangout_link: None,
+        conference_data: None,
+        ..event.clone()
+    }
+}
+
+/// Read the (source, source ID) stamp written by `convert_to_google_event`, if any
+pub fn source_key(event: &Event) -> Option<(&str, &str)> {
+    let private = event.extended_properties.as_ref()?.private.as_ref()?;
+    let source = private.get(SOURCE_PROPERTY)?;
+    let source_id = private.get(SOURCE_ID_PROPERTY)?;
+    Some((source.as_str(), source_id.as_str()))
 }
 
-/// Find Google Calendar events that match the given CalendarEvents (by title and date)
+/// Returns true for a recurring series (as listed with `single_events(false)`)
+pub fn is_series(event: &Event) -> bool {
+    event.recurrence.as_ref().is_some_and(|r| !r.is_empty())
+}
+
+/// Returns true for one occurrence of a series that was changed on its own (moved, renamed).
+/// These belong to their series and are never matched, updated or pruned separately.
+pub fn is_instance(event: &Event) -> bool {
+    event.recurring_event_id.is_some()
+}
+
+/// Returns true if the Google event is stamped with the same source and source ID
+pub fn matches_by_source_key(event: &CalendarEvent, gcal_event: &Event) -> bool {
+    if is_instance(gcal_event) {
+        return false;
+    }
+    match (&event.source, &event.source_id, source_key(gcal_event)) {
+        (Some(source), Some(source_id), Some((gcal_source, gcal_id))) => {
+            source == gcal_source && source_id == gcal_id
+        }
+        _ => false,
+    }
+}
+
+/// Returns true if the Google event has the same title (case-insensitive) and start date,
+/// and is a series exactly when the source event repeats.
+/// Events already stamped with an ID from the same source never match this way, so two
+/// distinct rows that share a title and date are not confused with each other.
+pub fn matches_by_title_and_date(event: &CalendarEvent, gcal_event: &Event) -> bool {
+    if is_instance(gcal_event) || event.recurrence.is_some() != is_series(gcal_event) {
+        return false;
+    }
+    if let (Some(source), Some((gcal_source, _))) = (&event.source, source_key(gcal_event)) {
+        if source == gcal_source {
+            return false;
+        }
+    }
+    let gcal_title = gcal_event.summary.as_deref().unwrap_or("");
+    gcal_title.to_lowercase() == event.title.to_lowercase()
+        && extract_event_date(gcal_event) == Some(event.start_date)
+}
+
+/// Find Google Calendar events that match the given CalendarEvents (by source ID, then by title and date)
 pub async fn find_matching_events(
-    hub: &Hub,
+    backend: &dyn CalendarBackend,
     calendar_id: &str,
     events: &[CalendarEvent],
 ) -> Result<Vec<(CalendarEvent, FoundCalendarEvent)>> {
@@ -169,68 +784,32 @@ pub async fn find_matching_events(
     let min_date = events.iter().map(|e| e.start_date).min().unwrap();
     let max_date = events.iter().map(|e| e.start_date).max().unwrap();
 
-    // Fetch all calendar events in the date range
-    let time_min = Utc.from_utc_datetime(&min_date.and_hms_opt(0, 0, 0).unwrap());
-    let time_max = Utc.from_utc_datetime(&max_date.succ_opt().unwrap().and_hms_opt(0, 0, 0).unwrap());
-
-    let mut all_gcal_events = Vec::new();
-    let mut page_token: Option<String> = None;
-
-    loop {
-        let mut request = hub
-            .events()
-            .list(calendar_id)
-            .time_min(time_min)
-            .time_max(time_max)
-            .single_events(true)
-            .max_results(2500);
-
-        if let Some(token) = &page_token {
-            request = request.page_token(token);
-        }
-
-        let (_, event_list) = request
-            .doit()
-            .await
-            .context("Failed to list calendar events")?;
-
-        if let Some(items) = event_list.items {
-            all_gcal_events.extend(items);
-        }
-
-        page_token = event_list.next_page_token;
-        if page_token.is_none() {
-            break;
-        }
-    }
+    let all_gcal_events = list_events_in_range(backend, calendar_id, min_date, max_date).await?;
 
     tracing::info!("Found {} events in Google Calendar within date range", all_gcal_events.len());
 
-    // Match Coda events to Google Calendar events by title and date
+    // Match source events to Google Calendar events by source ID or by title and date
     let mut matches = Vec::new();
 
-    for coda_event in events {
+    for source_event in events {
         for gcal_event in &all_gcal_events {
-            let gcal_title = gcal_event.summary.as_deref().unwrap_or("");
-            let gcal_date = extract_event_date(gcal_event);
-
-            if let Some(date) = gcal_date {
-                // Match by title (case-insensitive) and date
-                if gcal_title.to_lowercase() == coda_event.title.to_lowercase() 
-                    && date == coda_event.start_date 
-                {
-                    if let Some(id) = &gcal_event.id {
-                        matches.push((
-                            coda_event.clone(),
-                            FoundCalendarEvent {
-                                id: id.clone(),
-                                title: gcal_title.to_string(),
-                                date,
-                                location: gcal_event.location.clone(),
-                            },
-                        ));
-                    }
-                }
+            if !matches_by_source_key(source_event, gcal_event)
+                && !matches_by_title_and_date(source_event, gcal_event)
+            {
+                continue;
+            }
+            if let (Some(id), Some(date)) = (&gcal_event.id, extract_event_date(gcal_event)) {
+                matches.push((
+                    source_event.clone(),
+                    FoundCalendarEvent {
+                        id: id.clone(),
+                        title: gcal_event.summary.clone().unwrap_or_default(),
+                        date,
+                        location: gcal_event.location.clone(),
+                        series: is_series(gcal_event),
+                        event: gcal_event.clone(),
+                    },
+                ));
             }
         }
     }
@@ -238,56 +817,230 @@ pub async fn find_matching_events(
     Ok(matches)
 }
 
-/// Extract the date from a Google Calendar event
-fn extract_event_date(event: &Event) -> Option<NaiveDate> {
+/// Fetch all Google Calendar events that start between `min_date` and `max_date` (inclusive)
+pub async fn list_events_in_range(
+    backend: &dyn CalendarBackend,
+    calendar_id: &str,
+    min_date: NaiveDate,
+    max_date: NaiveDate,
+) -> Result<Vec<Event>> {
+    // Pad the window by a day on each side: event dates are local, the query bounds are UTC
+    let padded_min = min_date.pred_opt().unwrap_or(min_date);
+    let padded_max = max_date + Duration::days(2);
+    let time_min = Utc.from_utc_datetime(&padded_min.and_hms_opt(0, 0, 0).unwrap());
+    let time_max = Utc.from_utc_datetime(&padded_max.and_hms_opt(0, 0, 0).unwrap());
+
+    let mut all_gcal_events = backend.list_events(calendar_id, time_min, time_max).await?;
+
+    // Drop events from the padding days so callers see exactly the requested range. A series
+    // is listed because it has occurrences in the range, even if its first one is earlier.
+    all_gcal_events.retain(|e| {
+        is_series(e) || extract_event_date(e).is_some_and(|d| d >= min_date && d <= max_date)
+    });
+
+    Ok(all_gcal_events)
+}
+
+/// Extract the (local) start date from a Google Calendar event
+pub fn extract_event_date(event: &Event) -> Option<NaiveDate> {
     if let Some(start) = &event.start {
         // Try date first (all-day events)
         if let Some(date) = start.date {
             return Some(date);
         }
-        // Try date_time (timed events)
+        // Try date_time (timed events). The API hands these back as UTC, so convert
+        // to the event's own time zone before taking the date.
         if let Some(dt) = &start.date_time {
-            return Some(dt.date_naive());
+            let tz: Tz = start
+                .time_zone
+                .as_deref()
+                .and_then(|name| name.parse().ok())
+                .unwrap_or(DEFAULT_TIMEZONE);
+            return Some(dt.with_timezone(&tz).date_naive());
         }
     }
     None
 }
 
-/// Delete events from Google Calendar
+/// Delete each event, in batches of up to `MAX_BATCH_SIZE` with up to `jobs` batches in
+/// flight, with results in the order of `event_ids`
 pub async fn delete_events(
-    hub: &Hub,
+    backend: &dyn CalendarBackend,
     calendar_id: &str,
     event_ids: &[String],
-) -> Result<usize> {
-    let mut deleted = 0;
-    for event_id in event_ids {
-        hub.events()
-            .delete(calendar_id, event_id)
-            .doit()
-            .await
-            .with_context(|| format!("Failed to delete event: {}", event_id))?;
-        deleted += 1;
-        tracing::info!("Deleted event: {}", event_id);
+    jobs: usize,
+) -> Vec<Result<()>> {
+    let mut results: Vec<Option<Result<()>>> = event_ids.iter().map(|_| None).collect();
+    let batches = event_ids.chunks(MAX_BATCH_SIZE).enumerate();
+    let mut stream = std::pin::pin!(run_jobs(batches, jobs, |(number, batch)| async move {
+        (number * MAX_BATCH_SIZE, backend.delete_events(calendar_id, batch).await)
+    }));
+    let ids: Vec<&str> = event_ids.iter().map(String::as_str).collect();
+    let mut logged = 0;
+    while let Some((offset, batch)) = stream.next().await {
+        for (slot, result) in results[offset..].iter_mut().zip(batch) {
+            *slot = Some(result);
+        }
+        logged += log_ready(&mut results[logged..], &ids[logged..], ["delete", "Deleted"]);
     }
-    Ok(deleted)
+    finish(results)
 }
 
-fn get_credentials_path() -> Result<PathBuf> {
-    // Check for env var first, then fall back to current directory
-    if let Ok(path) = std::env::var("GOOGLE_CREDENTIALS_PATH") {
-        return Ok(PathBuf::from(path));
+/// Where the OAuth client credentials and cached tokens live, as set in the config file.
+/// The `GOOGLE_CREDENTIALS_PATH` and `GOOGLE_TOKEN_CACHE_PATH` env vars take precedence;
+/// otherwise both default to the current directory.
+#[derive(Debug, Clone, Default, Deserialize)]
+#[serde(default, deny_unknown_fields)]
+pub struct AuthPaths {
+    pub credentials_path: Option<PathBuf>,
+    pub token_cache_path: Option<PathBuf>,
+}
+
+impl AuthPaths {
+    pub fn credentials_path(&self) -> Result<PathBuf> {
+        resolve_path("GOOGLE_CREDENTIALS_PATH", &self.credentials_path, CREDENTIALS_FILE)
+    }
+
+    pub fn token_cache_path(&self) -> Result<PathBuf> {
+        resolve_path("GOOGLE_TOKEN_CACHE_PATH", &self.token_cache_path, TOKEN_CACHE_FILE)
     }
-    
-    let path = std::env::current_dir()?.join(CREDENTIALS_FILE);
-    Ok(path)
 }
 
-fn get_token_cache_path() -> Result<PathBuf> {
-    // Check for env var first, then fall back to current directory
-    if let Ok(path) = std::env::var("GOOGLE_TOKEN_CACHE_PATH") {
+fn resolve_path(env_var: &str, configured: &Option<PathBuf>, file_name: &str) -> Result<PathBuf> {
+    if let Ok(path) = std::env::var(env_var) {
         return Ok(PathBuf::from(path));
     }
+    if let Some(path) = configured {
+        return Ok(path.clone());
+    }
+    Ok(std::env::current_dir()?.join(file_name))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::backend::{concert, BackendCall, MemoryCalendar};
+
+    #[test]
+    fn test_extract_event_date_uses_event_time_zone() {
+        // 19:30 in Los Angeles is already the next day in UTC
+        let date = NaiveDate::from_ymd_opt(2026, 3, 14).unwrap();
+        let event = convert_to_google_event(&concert("Mahler 9", 14, Some("1")), &ConvertOptions::default()).unwrap();
+        assert_eq!(extract_event_date(&event), Some(date));
+    }
+
+    #[test]
+    fn test_convert_uses_event_time_zone() {
+        let mut event = concert("Mahler 9", 14, Some("1"));
+        event.timezone = Some(chrono_tz::America::New_York);
+
+        let google_event = convert_to_google_event(&event, &ConvertOptions::default()).unwrap();
+        let start = google_event.start.unwrap();
+        assert_eq!(start.time_zone.as_deref(), Some("America/New_York"));
+        // 19:30 EDT (DST started 2026-03-08) is 23:30 UTC
+        assert_eq!(start.date_time.unwrap().to_rfc3339(), "2026-03-14T23:30:00+00:00");
+    }
+
+    #[test]
+    fn test_convert_sets_reminder_overrides() {
+        let mut event = concert("Mahler 9", 14, Some("1"));
+        assert!(convert_to_google_event(&event, &ConvertOptions::default()).unwrap().reminders.is_none());
+
+        event.reminders = Some("popup:120,email:1440".parse().unwrap());
+        let reminders = convert_to_google_event(&event, &ConvertOptions::default()).unwrap().reminders.unwrap();
+        assert_eq!(reminders.use_default, Some(false));
+        let overrides: Vec<(String, i32)> = reminders
+            .overrides
+            .unwrap()
+            .into_iter()
+            .map(|r| (r.method.unwrap(), r.minutes.unwrap()))
+            .collect();
+        assert_eq!(overrides, [("popup".to_string(), 120), ("email".to_string(), 1440)]);
+    }
+
+    #[test]
+    fn test_convert_sets_attendees() {
+        let mut event = concert("Mahler 9", 14, Some("1"));
+        assert!(convert_to_google_event(&event, &ConvertOptions::default()).unwrap().attendees.is_none());
+
+        event.attendees = crate::event::parse_attendees("sam@example.com; optional:alex@example.org").unwrap();
+        let attendees: Vec<(String, bool)> = convert_to_google_event(&event, &ConvertOptions::default())
+            .unwrap()
+            .attendees
+            .unwrap()
+            .into_iter()
+            .map(|a| (a.email.unwrap(), a.optional.unwrap()))
+            .collect();
+        assert_eq!(attendees, [("sam@example.com".to_string(), false), ("alex@example.org".to_string(), true)]);
+    }
+
+    #[test]
+    fn test_color_rules_first_match_wins() {
+        let rule = |organization: Option<&str>, purchased: Option<bool>, color_id| ColorRule {
+            organization: organization.map(str::to_string),
+            location: None,
+            purchased,
+            color_id,
+        };
+        let options = ConvertOptions {
+            colors: vec![rule(Some("sf symphony"), None, 9), rule(None, Some(true), 10)],
+            ..Default::default()
+        };
+        let mut event = concert("Mahler 9", 14, Some("1"));
 
-    let path = std::env::current_dir()?.join(TOKEN_CACHE_FILE);
-    Ok(path)
+        event.organization = Some("SF Symphony".to_string());
+        assert_eq!(convert_to_google_event(&event, &options).unwrap().color_id.as_deref(), Some("9"));
+
+        event.organization = Some("SF Opera".to_string());
+        assert_eq!(convert_to_google_event(&event, &options).unwrap().color_id.as_deref(), Some("10"));
+
+        event.purchased = false;
+        assert_eq!(convert_to_google_event(&event, &options).unwrap().color_id, None);
+    }
+
+    #[tokio::test]
+    async fn test_create_then_find_and_delete() {
+        let backend = MemoryCalendar::new();
+        let events = vec![
+            concert("Mahler 9", 14, Some("1")),
+            concert("Piano Recital", 15, Some("2")),
+        ];
+
+        let created = create_events(&backend, "primary", &events, &ConvertOptions::default(), 2).await;
+        assert!(created.iter().all(Result::is_ok));
+        assert_eq!(backend.events("primary").len(), 2);
+
+        let matches = find_matching_events(&backend, "primary", &events[..1]).await.unwrap();
+        assert_eq!(matches.len(), 1);
+        assert_eq!(matches[0].1.title, "Mahler 9");
+
+        let ids: Vec<String> = matches.iter().map(|(_, g)| g.id.clone()).collect();
+        let deleted = delete_events(&backend, "primary", &ids, 2).await;
+        assert!(matches!(deleted[..], [Ok(())]));
+
+        let remaining = backend.events("primary");
+        assert_eq!(remaining.len(), 1);
+        assert_eq!(remaining[0].summary.as_deref(), Some("Piano Recital"));
+        assert!(matches!(
+            backend.calls().last(),
+            Some(BackendCall::Delete { event_id, .. }) if *event_id == ids[0]
+        ));
+    }
+
+    #[tokio::test]
+    async fn test_find_matching_events_ignores_other_dates_and_calendars() {
+        let date = NaiveDate::from_ymd_opt(2026, 3, 14).unwrap();
+        let event = concert("Mahler 9", 14, Some("1"));
+        let mut moved = event.clone();
+        moved.source = None;
+        moved.start_date = date.succ_opt().unwrap();
+        moved.end_date = moved.start_date;
+
+        let backend = MemoryCalendar::new()
+            .with_event("primary", convert_to_google_event(&moved, &ConvertOptions::default()).unwrap())
+            .with_event("work", convert_to_google_event(&event, &ConvertOptions::default()).unwrap());
+
+        let matches = find_matching_events(&backend, "primary", &[event]).await.unwrap();
+        assert!(matches.is_empty());
+    }
 }
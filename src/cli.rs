@@ -1,103 +1,497 @@
 use chrono::NaiveDate;
-use clap::{Parser, Subcommand};
+use chrono_tz::Tz;
+use clap::{Args, Parser, Subcommand};
 use std::path::PathBuf;
 
+use crate::calendar::{SendUpdates, DEFAULT_JOBS};
+use crate::event::{self, DstPolicy, Reminders};
+use crate::mapping::{self, CsvEncoding};
+
+pub const DEFAULT_CALENDAR_ID: &str = "primary";
+pub const DEFAULT_SHEETS_RANGE: &str = "Sheet1";
+/// More parallel writes than this gain nothing under Google's per-user quota
+pub const MAX_JOBS: usize = 32;
+
 #[derive(Parser)]
 #[command(name = "calendar-sync")]
 #[command(author, version, about = "Sync calendar events from CSV/Google Sheets to Google Calendar")]
 pub struct Cli {
+    /// Time zone for event times that don't specify one (IANA name, e.g. America/New_York).
+    /// Defaults to the source's own time zone if it has one, else America/Los_Angeles.
+    #[arg(long, global = true, value_parser = parse_timezone)]
+    pub timezone: Option<Tz>,
+
+    /// How to handle event times that fall in a daylight-saving gap or overlap [default: shift-forward]
+    #[arg(long, global = true, value_enum)]
+    pub dst_policy: Option<DstPolicy>,
+
+    /// Reminders for events whose source row sets none, e.g. "popup:120,email:1440" (minutes
+    /// before the start), or "none". Defaults to the calendar's own default reminders.
+    #[arg(long, global = true, value_parser = parse_reminders)]
+    pub reminders: Option<Reminders>,
+
+    /// How many times to retry a request that failed with a rate-limit, server or network
+    /// error, backing off exponentially between tries [default: 5]
+    #[arg(long, global = true)]
+    pub max_retries: Option<u32>,
+
+    /// Most Google Calendar requests to send per second [default: 5]
+    #[arg(long, global = true, value_parser = parse_rate_limit)]
+    pub rate_limit: Option<f64>,
+
+    /// Named profile from the config file supplying defaults for any option not given
+    #[arg(long, global = true)]
+    pub profile: Option<String>,
+
+    /// Config file to use instead of ./calendar-sync.toml or ~/.config/calendar-sync/calendar-sync.toml
+    #[arg(long, global = true)]
+    pub config: Option<PathBuf>,
+
     #[command(subcommand)]
     pub command: Commands,
 }
 
 #[derive(Subcommand)]
 pub enum Commands {
-    /// Import events from a CSV file to Google Calendar (use --dry-run to preview)
+    /// Import events from a CSV or iCalendar file to Google Calendar (use --dry-run to preview)
     Import {
-        /// Path to the CSV file containing events
-        #[arg(short, long)]
-        file: PathBuf,
-
-        /// Google Calendar ID to add events to (use 'primary' for main calendar)
-        #[arg(short, long, default_value = "primary")]
-        calendar_id: String,
-
-        /// Preview events without creating them in Google Calendar
-        #[arg(short = 'n', long)]
-        dry_run: bool,
-
-        /// Show statistics (total events, by organization, by venue)
+        /// Path to the CSV or iCalendar (.ics) file containing events
         #[arg(short, long)]
-        stats: bool,
-
-        /// Only include events on or after this date (YYYY-MM-DD)
-        #[arg(long, value_parser = parse_date)]
-        start_date: Option<NaiveDate>,
+        file: Option<PathBuf>,
 
-        /// Only include events on or before this date (YYYY-MM-DD)
-        #[arg(long, value_parser = parse_date)]
-        end_date: Option<NaiveDate>,
+        #[command(flatten)]
+        mapping: MappingArgs,
 
-        /// Only include events where Purchased == Yes
-        #[arg(short, long)]
-        purchased: bool,
+        #[command(flatten)]
+        format: CsvFormatArgs,
 
-        /// Delete matching events from Google Calendar instead of creating them
-        #[arg(long)]
-        delete: bool,
+        #[command(flatten)]
+        args: ImportArgs,
     },
 
     /// Import events from a Coda.io table to Google Calendar (use --dry-run to preview)
     CodaImport {
         /// Coda document ID (from the doc URL)
         #[arg(short, long)]
-        doc_id: String,
+        doc_id: Option<String>,
 
         /// Coda table ID or name
         #[arg(short, long)]
-        table_id: String,
+        table_id: Option<String>,
+
+        #[command(flatten)]
+        mapping: MappingArgs,
 
-        /// Google Calendar ID to add events to (use 'primary' for main calendar)
-        #[arg(short, long, default_value = "primary")]
-        calendar_id: String,
+        #[command(flatten)]
+        args: ImportArgs,
+    },
 
-        /// Preview events without creating them in Google Calendar
-        #[arg(short = 'n', long)]
-        dry_run: bool,
+    /// Import events from a Google Sheets range to Google Calendar (use --dry-run to preview)
+    SheetsImport {
+        /// Spreadsheet ID (from the sheet URL)
+        #[arg(short = 'S', long)]
+        spreadsheet_id: Option<String>,
 
-        /// Show statistics (total events, by organization, by venue)
+        /// Range to read, in A1 notation; the first row must hold the column headers [default: Sheet1]
         #[arg(short, long)]
-        stats: bool,
+        range: Option<String>,
+
+        #[command(flatten)]
+        mapping: MappingArgs,
 
-        /// Only include events on or after this date (YYYY-MM-DD)
-        #[arg(long, value_parser = parse_date)]
-        start_date: Option<NaiveDate>,
+        #[command(flatten)]
+        args: ImportArgs,
+    },
 
-        /// Only include events on or before this date (YYYY-MM-DD)
-        #[arg(long, value_parser = parse_date)]
-        end_date: Option<NaiveDate>,
+    /// Sync events to Google Calendar: create new events, update changed ones, and
+    /// (with --prune) delete ones no longer in the source. Safe to re-run.
+    Sync {
+        #[command(flatten)]
+        source: SourceArgs,
 
-        /// Only include events where Purchased == Yes
-        #[arg(short, long)]
-        purchased: bool,
+        #[command(flatten)]
+        args: SyncArgs,
+    },
+
+    /// Export events to an iCalendar (.ics) file for calendar apps other than Google Calendar
+    ExportIcs {
+        #[command(flatten)]
+        source: SourceArgs,
+
+        #[command(flatten)]
+        args: ExportArgs,
+    },
 
-        /// Delete matching events from Google Calendar instead of creating them
-        #[arg(long)]
-        delete: bool,
+    /// Undo a run: delete the events it created and re-create the events it deleted
+    Undo {
+        #[command(flatten)]
+        args: UndoArgs,
+    },
+
+    /// Save a calendar's events in a date range to a JSON backup file
+    Backup {
+        #[command(flatten)]
+        args: BackupArgs,
+    },
+
+    /// Re-create the events in a backup file that are missing from the calendar
+    Restore {
+        #[command(flatten)]
+        args: RestoreArgs,
+    },
+
+    /// Inspect the run history
+    History {
+        #[command(subcommand)]
+        command: HistoryCommand,
     },
 
     /// List tables in a Coda document (helps find table IDs)
     ListCodaTables {
         /// Coda document ID (from the doc URL)
         #[arg(short, long)]
-        doc_id: String,
+        doc_id: Option<String>,
     },
 
     /// List available calendars
     ListCalendars,
 
+    /// Show the event color palette (the IDs used by color rules in the config file)
+    ListColors,
+
     /// Authenticate with Google Calendar (stores credentials for future use)
     Auth,
+
+    /// Inspect the config file
+    Config {
+        #[command(subcommand)]
+        command: ConfigCommand,
+    },
+}
+
+#[derive(Subcommand)]
+pub enum ConfigCommand {
+    /// Print the settings a command would run with, after applying the profile and flags
+    Show,
+}
+
+#[derive(Subcommand)]
+pub enum HistoryCommand {
+    /// List recent runs, newest first, with what each one changed
+    List {
+        /// How many runs to show
+        #[arg(short = 'n', long, default_value_t = 20)]
+        limit: usize,
+    },
+}
+
+/// Where to read events from, for commands that accept any source
+#[derive(Args)]
+pub struct SourceArgs {
+    /// Path to a CSV or iCalendar (.ics) file containing events
+    #[arg(short, long, conflicts_with_all = ["doc_id", "table_id", "spreadsheet_id"])]
+    pub file: Option<PathBuf>,
+
+    /// Coda document ID (from the doc URL)
+    #[arg(short, long, conflicts_with = "spreadsheet_id")]
+    pub doc_id: Option<String>,
+
+    /// Coda table ID or name
+    #[arg(short, long, conflicts_with = "spreadsheet_id")]
+    pub table_id: Option<String>,
+
+    /// Google Sheets spreadsheet ID (from the sheet URL)
+    #[arg(short = 'S', long)]
+    pub spreadsheet_id: Option<String>,
+
+    /// Google Sheets range to read, in A1 notation [default: Sheet1]
+    #[arg(short, long)]
+    pub range: Option<String>,
+
+    #[command(flatten)]
+    pub mapping: MappingArgs,
+
+    #[command(flatten)]
+    pub format: CsvFormatArgs,
+}
+
+/// How source columns map to event fields
+#[derive(Args)]
+pub struct MappingArgs {
+    /// TOML file mapping source columns to event fields (see README)
+    #[arg(short, long)]
+    pub mapping: Option<PathBuf>,
+
+    /// Map an event field to a column, e.g. --map start_date=Date --map location=Venue.
+    /// Overrides the mapping file; repeat for each field.
+    #[arg(long = "map", value_name = "FIELD=COLUMN", value_parser = parse_field_map)]
+    pub fields: Vec<(String, String)>,
+}
+
+/// How to read a CSV file; these override the `[csv]` table of the mapping file
+#[derive(Args)]
+pub struct CsvFormatArgs {
+    /// CSV field separator, e.g. ';' or "tab" (detected from the header line by default)
+    #[arg(long, value_parser = parse_delimiter)]
+    pub delimiter: Option<u8>,
+
+    /// CSV text encoding [default: utf-8]
+    #[arg(long, value_enum)]
+    pub encoding: Option<CsvEncoding>,
+}
+
+/// Event filters shared by every command that reads from a source
+#[derive(Args)]
+pub struct FilterArgs {
+    /// Only include events on or after this date (YYYY-MM-DD)
+    #[arg(long, value_parser = parse_date)]
+    pub start_date: Option<NaiveDate>,
+
+    /// Only include events on or before this date (YYYY-MM-DD)
+    #[arg(long, value_parser = parse_date)]
+    pub end_date: Option<NaiveDate>,
+
+    /// Only include events where Purchased == Yes
+    #[arg(short, long)]
+    pub purchased: bool,
+}
+
+impl FilterArgs {
+    pub fn is_active(&self) -> bool {
+        self.start_date.is_some() || self.end_date.is_some() || self.purchased
+    }
+
+    /// Whether rows are left out for anything other than their dates. The date window also
+    /// bounds which calendar events are compared; other filters don't.
+    pub fn skips_rows(&self) -> bool {
+        self.purchased
+    }
+}
+
+#[derive(Args)]
+pub struct ImportArgs {
+    /// Google Calendar ID to add events to (use 'primary' for main calendar) [default: primary]
+    #[arg(short, long)]
+    pub calendar_id: Option<String>,
+
+    /// Preview events without creating them in Google Calendar
+    #[arg(short = 'n', long)]
+    pub dry_run: bool,
+
+    /// Show statistics (total events, by organization, by venue)
+    #[arg(short, long)]
+    pub stats: bool,
+
+    #[command(flatten)]
+    pub filter: FilterArgs,
+
+    /// Delete matching events from Google Calendar instead of creating them
+    #[arg(long)]
+    pub delete: bool,
+
+    /// Who Google emails about events with attendees [default: none]
+    #[arg(long, value_enum)]
+    pub send_updates: Option<SendUpdates>,
+
+    /// How many requests to Google Calendar to run at once; new events and deletions go in
+    /// batches of up to 50, each counting as one [default: 4]
+    #[arg(short, long, value_parser = parse_jobs)]
+    pub jobs: Option<usize>,
+
+    /// Where to write the run journal, a JSON record of each planned change and how it went
+    /// [default: a file named after the run in ~/.local/state/calendar-sync/runs]
+    #[arg(long, value_name = "FILE")]
+    pub journal: Option<PathBuf>,
+
+    /// Continue the run recorded in this journal, skipping the changes it records as done.
+    /// Give the same source and options as the original run.
+    #[arg(long, value_name = "JOURNAL", conflicts_with_all = ["journal", "dry_run"])]
+    pub resume: Option<PathBuf>,
+}
+
+#[derive(Args)]
+pub struct SyncArgs {
+    /// Google Calendar ID to sync events into (use 'primary' for main calendar) [default: primary]
+    #[arg(short, long)]
+    pub calendar_id: Option<String>,
+
+    /// Show the sync plan without changing Google Calendar
+    #[arg(short = 'n', long)]
+    pub dry_run: bool,
+
+    #[command(flatten)]
+    pub filter: FilterArgs,
+
+    /// Also delete calendar events in the date range that are not in the source
+    #[arg(long)]
+    pub prune: bool,
+
+    /// Who Google emails about events with attendees [default: none]
+    #[arg(long, value_enum)]
+    pub send_updates: Option<SendUpdates>,
+
+    /// How many requests to Google Calendar to run at once; new events and deletions go in
+    /// batches of up to 50, each counting as one [default: 4]
+    #[arg(short, long, value_parser = parse_jobs)]
+    pub jobs: Option<usize>,
+
+    /// Where to write the run journal, a JSON record of each planned change and how it went
+    /// [default: a file named after the run in ~/.local/state/calendar-sync/runs]
+    #[arg(long, value_name = "FILE")]
+    pub journal: Option<PathBuf>,
+
+    /// Continue the run recorded in this journal, skipping the changes it records as done.
+    /// Give the same source and options as the original run.
+    #[arg(long, value_name = "JOURNAL", conflicts_with_all = ["journal", "dry_run"])]
+    pub resume: Option<PathBuf>,
+}
+
+#[derive(Args)]
+pub struct UndoArgs {
+    /// Run to undo: an ID from `history list`, or a journal file [default: the latest run
+    /// with anything left to undo]
+    pub run: Option<String>,
+
+    /// Show what would be undone without changing Google Calendar
+    #[arg(short = 'n', long)]
+    pub dry_run: bool,
+
+    /// Who Google emails about events with attendees [default: none]
+    #[arg(long, value_enum)]
+    pub send_updates: Option<SendUpdates>,
+
+    /// How many requests to Google Calendar to run at once [default: 4]
+    #[arg(short, long, value_parser = parse_jobs)]
+    pub jobs: Option<usize>,
+}
+
+#[derive(Args)]
+pub struct BackupArgs {
+    /// Google Calendar ID to back up (use 'primary' for main calendar) [default: primary]
+    #[arg(short, long)]
+    pub calendar_id: Option<String>,
+
+    /// First day to back up (YYYY-MM-DD)
+    #[arg(long, value_parser = parse_date)]
+    pub start_date: Option<NaiveDate>,
+
+    /// Last day to back up (YYYY-MM-DD)
+    #[arg(long, value_parser = parse_date)]
+    pub end_date: Option<NaiveDate>,
+
+    /// File to write [default: a file named after the time and calendar in
+    /// ~/.local/state/calendar-sync/backups]
+    #[arg(short, long)]
+    pub output: Option<PathBuf>,
+}
+
+#[derive(Args)]
+pub struct RestoreArgs {
+    /// Backup file to restore, as written by `backup` or before a delete
+    #[arg(long, value_name = "BACKUP")]
+    pub from: PathBuf,
+
+    /// Google Calendar ID to restore into [default: the calendar the backup was taken from]
+    #[arg(short, long)]
+    pub calendar_id: Option<String>,
+
+    /// Show which events would be re-created without changing Google Calendar
+    #[arg(short = 'n', long)]
+    pub dry_run: bool,
+
+    /// Who Google emails about events with attendees [default: none]
+    #[arg(long, value_enum)]
+    pub send_updates: Option<SendUpdates>,
+
+    /// How many requests to Google Calendar to run at once [default: 4]
+    #[arg(short, long, value_parser = parse_jobs)]
+    pub jobs: Option<usize>,
+}
+
+#[derive(Args)]
+pub struct ExportArgs {
+    /// .ics file to write
+    #[arg(short, long)]
+    pub output: PathBuf,
+
+    #[command(flatten)]
+    pub filter: FilterArgs,
+}
+
+impl ImportArgs {
+    pub fn calendar_id(&self) -> &str {
+        self.calendar_id.as_deref().unwrap_or(DEFAULT_CALENDAR_ID)
+    }
+
+    pub fn jobs(&self) -> usize {
+        self.jobs.unwrap_or(DEFAULT_JOBS)
+    }
+}
+
+impl BackupArgs {
+    pub fn calendar_id(&self) -> &str {
+        self.calendar_id.as_deref().unwrap_or(DEFAULT_CALENDAR_ID)
+    }
+}
+
+impl RestoreArgs {
+    pub fn jobs(&self) -> usize {
+        self.jobs.unwrap_or(DEFAULT_JOBS)
+    }
+}
+
+impl UndoArgs {
+    pub fn jobs(&self) -> usize {
+        self.jobs.unwrap_or(DEFAULT_JOBS)
+    }
+}
+
+impl SyncArgs {
+    pub fn calendar_id(&self) -> &str {
+        self.calendar_id.as_deref().unwrap_or(DEFAULT_CALENDAR_ID)
+    }
+
+    pub fn jobs(&self) -> usize {
+        self.jobs.unwrap_or(DEFAULT_JOBS)
+    }
+}
+
+fn parse_timezone(s: &str) -> Result<Tz, String> {
+    event::parse_timezone(s).map_err(|e| e.to_string())
+}
+
+fn parse_reminders(s: &str) -> Result<Reminders, String> {
+    s.parse().map_err(|e: anyhow::Error| e.to_string())
+}
+
+/// Whether `rate` works as a rate limit: a positive, finite number of requests per second
+pub fn valid_rate_limit(rate: f64) -> bool {
+    rate > 0.0 && rate.is_finite()
+}
+
+fn parse_rate_limit(s: &str) -> Result<f64, String> {
+    match s.parse::<f64>() {
+        Ok(rate) if valid_rate_limit(rate) => Ok(rate),
+        _ => Err(format!("Invalid rate limit '{}'. Use a positive number of requests per second", s)),
+    }
+}
+
+fn parse_jobs(s: &str) -> Result<usize, String> {
+    match s.parse::<usize>() {
+        Ok(jobs) if (1..=MAX_JOBS).contains(&jobs) => Ok(jobs),
+        _ => Err(format!("Invalid jobs '{}'. Use a number from 1 to {}", s, MAX_JOBS)),
+    }
+}
+
+fn parse_field_map(s: &str) -> Result<(String, String), String> {
+    let (field, column) = s
+        .split_once('=')
+        .ok_or_else(|| format!("Invalid mapping '{}'. Use FIELD=COLUMN", s))?;
+    Ok((field.trim().to_string(), column.trim().to_string()))
+}
+
+fn parse_delimiter(s: &str) -> Result<u8, String> {
+    mapping::parse_delimiter(s).map_err(|e| e.to_string())
 }
 
 fn parse_date(s: &str) -> Result<NaiveDate, String> {
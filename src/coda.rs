@@ -1,12 +1,18 @@
 use anyhow::{Context, Result};
+use async_trait::async_trait;
 use chrono::{Duration, NaiveDate, NaiveDateTime, NaiveTime};
+use chrono_tz::Tz;
+use serde::de::DeserializeOwned;
 use serde::Deserialize;
 use std::collections::HashMap;
 
-use crate::event::CalendarEvent;
+use crate::event::{parse_attendees, parse_timezone, CalendarEvent, Reminders};
+use crate::mapping::{self, CodaMapping};
+use crate::recurrence::Recurrence;
+use crate::retry::{RequestPolicy, RetryPolicy};
+use crate::source::{EventSource, FetchOptions, RowDiagnostic, SourceEvents, SyncedEvent};
 
 const CODA_API_BASE: &str = "https://coda.io/apis/v1";
-const DEFAULT_EVENT_DURATION_MINUTES: i64 = 150; // 2.5 hours
 
 #[derive(Debug, Deserialize)]
 struct CodaRowsResponse {
@@ -17,6 +23,7 @@ struct CodaRowsResponse {
 
 #[derive(Debug, Deserialize)]
 struct CodaRow {
+    id: String,
     values: HashMap<String, serde_json::Value>,
 }
 
@@ -33,29 +40,77 @@ pub struct CodaTable {
     pub table_type: String,
 }
 
+#[derive(Debug, Deserialize)]
+struct CodaColumnsResponse {
+    items: Vec<CodaColumn>,
+    #[serde(rename = "nextPageToken")]
+    next_page_token: Option<String>,
+}
+
+#[derive(Debug, Deserialize)]
+struct CodaColumn {
+    id: String,
+    name: String,
+    #[serde(default)]
+    format: CodaColumnFormat,
+}
+
+#[derive(Debug, Default, Deserialize)]
+struct CodaColumnFormat {
+    /// e.g. "text", "checkbox", "dateTime"
+    #[serde(rename = "type", default)]
+    kind: String,
+}
+
 pub struct CodaClient {
     client: reqwest::Client,
     api_token: String,
+    base_url: String,
+    requests: RequestPolicy,
 }
 
 impl CodaClient {
+    /// Create a client for the Coda v1 API. The base URL can be overridden with
+    /// `CODA_API_BASE` (e.g. to point at a local mock server).
     pub fn new(api_token: String) -> Self {
+        let base_url = std::env::var("CODA_API_BASE")
+            .unwrap_or_else(|_| CODA_API_BASE.to_string());
+        Self::with_base_url(api_token, base_url)
+    }
+
+    pub fn with_base_url(api_token: String, base_url: String) -> Self {
         Self {
             client: reqwest::Client::new(),
             api_token,
+            base_url: base_url.trim_end_matches('/').to_string(),
+            requests: coda_requests(RetryPolicy::default()),
         }
     }
 
-    pub async fn list_tables(&self, doc_id: &str) -> Result<Vec<CodaTable>> {
-        let url = format!("{}/docs/{}/tables", CODA_API_BASE, doc_id);
+    /// Retry failed requests as `--max-retries` says
+    pub fn with_retry(mut self, retry: RetryPolicy) -> Self {
+        self.requests = coda_requests(retry);
+        self
+    }
+
+    /// Retries so far, for the run summary
+    pub fn request_summary(&self) -> Option<String> {
+        self.requests.summary()
+    }
 
+    /// GET a path under the API base and parse the JSON response
+    async fn get_json<T: DeserializeOwned>(&self, path: &str, query: &[(&str, String)], what: &str) -> Result<T> {
+        let url = format!("{}{}", self.base_url, path);
         let response = self
-            .client
-            .get(&url)
-            .header("Authorization", format!("Bearer {}", self.api_token))
-            .send()
+            .requests
+            .send(|| {
+                self.client
+                    .get(&url)
+                    .query(query)
+                    .header("Authorization", format!("Bearer {}", self.api_token))
+            })
             .await
-            .context("Failed to fetch tables from Coda")?;
+            .with_context(|| format!("Failed to fetch {} from Coda", what))?;
 
         if !response.status().is_success() {
             let status = response.status();
@@ -63,57 +118,77 @@ impl CodaClient {
             anyhow::bail!("Coda API error ({}): {}", status, body);
         }
 
-        let tables_response: CodaTablesResponse = response
+        response
             .json()
             .await
-            .context("Failed to parse Coda tables response")?;
+            .with_context(|| format!("Failed to parse Coda {} response", what))
+    }
 
+    pub async fn list_tables(&self, doc_id: &str) -> Result<Vec<CodaTable>> {
+        let tables_response: CodaTablesResponse =
+            self.get_json(&format!("/docs/{}/tables", doc_id), &[], "tables").await?;
         Ok(tables_response.items)
     }
 
+    async fn list_columns(&self, doc_id: &str, table_id: &str) -> Result<Vec<CodaColumn>> {
+        let path = format!("/docs/{}/tables/{}/columns", doc_id, table_id);
+        let mut columns = Vec::new();
+        let mut page_token: Option<String> = None;
+        loop {
+            let mut query = vec![("limit", "100".to_string())];
+            query.extend(page_token.take().map(|token| ("pageToken", token)));
+            let response: CodaColumnsResponse = self.get_json(&path, &query, "columns").await?;
+            columns.extend(response.items);
+            page_token = response.next_page_token;
+            if page_token.is_none() {
+                return Ok(columns);
+            }
+        }
+    }
+
+    /// Fetch and parse every row of a table. Rows without a `timezone` value are
+    /// interpreted in `default_tz`. With `purchased_only`, Coda is asked for purchased rows
+    /// only where it can filter them itself; the caller still filters the result.
     pub async fn fetch_events(
         &self,
         doc_id: &str,
         table_id: &str,
-    ) -> Result<Vec<CalendarEvent>> {
-        let mut all_events = Vec::new();
-        let mut page_token: Option<String> = None;
-
-        loop {
-            let mut url = format!(
-                "{}/docs/{}/tables/{}/rows?useColumnNames=true",
-                CODA_API_BASE, doc_id, table_id
-            );
-
-            if let Some(token) = &page_token {
-                url.push_str(&format!("&pageToken={}", token));
+        mapping: &CodaMapping,
+        default_tz: Tz,
+        purchased_only: bool,
+    ) -> Result<SourceEvents> {
+        let source = source_name(doc_id, table_id);
+        let path = format!("/docs/{}/tables/{}/rows", doc_id, table_id);
+        let purchased_column = match (purchased_only, &mapping.purchased) {
+            (true, Some(column)) => {
+                let columns = self.list_columns(doc_id, table_id).await?;
+                checkbox_column(&columns, column).map(|c| c.id.clone())
             }
+            _ => None,
+        };
+        let query = row_query(mapping, purchased_column.as_deref());
 
-            let response = self
-                .client
-                .get(&url)
-                .header("Authorization", format!("Bearer {}", self.api_token))
-                .send()
-                .await
-                .context("Failed to fetch rows from Coda")?;
-
-            if !response.status().is_success() {
-                let status = response.status();
-                let body = response.text().await.unwrap_or_default();
-                anyhow::bail!("Coda API error ({}): {}", status, body);
-            }
+        let mut fetched = SourceEvents::default();
+        let mut row_num = 0;
+        let mut page_token: Option<String> = None;
 
-            let rows_response: CodaRowsResponse = response
-                .json()
-                .await
-                .context("Failed to parse Coda response")?;
+        loop {
+            let mut query = query.clone();
+            query.extend(page_token.take().map(|token| ("pageToken", token)));
+            let rows_response: CodaRowsResponse = self.get_json(&path, &query, "rows").await?;
 
             for row in rows_response.items {
-                match parse_coda_row(&row.values) {
-                    Ok(event) => all_events.push(event),
-                    Err(e) => {
-                        tracing::warn!("Skipping row due to parse error: {}", e);
+                row_num += 1;
+                match parse_coda_row(&row.values, mapping, default_tz) {
+                    Ok(mut event) => {
+                        event.source = Some(source.clone());
+                        event.source_id = Some(row.id);
+                        fetched.events.push(event);
                     }
+                    Err(e) => fetched.diagnostics.push(RowDiagnostic {
+                        row: row_num,
+                        message: format!("{} ({:#})", row.id, e),
+                    }),
                 }
             }
 
@@ -123,42 +198,238 @@ impl CodaClient {
             }
         }
 
-        Ok(all_events)
+        Ok(fetched)
+    }
+
+    /// Set cells of one row, given as (column name or ID, value) pairs. Coda applies the
+    /// change asynchronously, usually within a few seconds.
+    pub async fn update_row(
+        &self,
+        doc_id: &str,
+        table_id: &str,
+        row_id: &str,
+        cells: &[(String, String)],
+    ) -> Result<()> {
+        let url = format!("{}/docs/{}/tables/{}/rows/{}", self.base_url, doc_id, table_id, row_id);
+        let cells: Vec<serde_json::Value> = cells
+            .iter()
+            .map(|(column, value)| serde_json::json!({ "column": column, "value": value }))
+            .collect();
+
+        let body = serde_json::json!({ "row": { "cells": cells } });
+        let response = self
+            .requests
+            .send(|| {
+                self.client
+                    .put(&url)
+                    .header("Authorization", format!("Bearer {}", self.api_token))
+                    .json(&body)
+            })
+            .await
+            .context("Failed to update row in Coda")?;
+
+        if !response.status().is_success() {
+            let status = response.status();
+            let body = response.text().await.unwrap_or_default();
+            anyhow::bail!("Coda API error ({}): {}", status, body);
+        }
+
+        Ok(())
     }
 }
 
-fn parse_coda_row(values: &HashMap<String, serde_json::Value>) -> Result<CalendarEvent> {
-    // Extract Display -> title
-    let title = get_string_value(values, "Display")
-        .context("Missing 'Display' column")?;
+/// Coda's limits are generous for reads, so requests are only retried, not paced
+fn coda_requests(retry: RetryPolicy) -> RequestPolicy {
+    RequestPolicy::new("Coda", retry, None)
+}
 
-    // Extract performanceDate -> start_date, start_time
-    let performance_date_str = get_string_value(values, "performanceDate")
-        .context("Missing 'performanceDate' column")?;
-    
-    let (start_date, start_time) = parse_coda_datetime(&performance_date_str)
-        .with_context(|| format!("Invalid performanceDate: '{}'", performance_date_str))?;
+/// Query parameters for listing rows. Coda's `query` only matches one column against one
+/// value, so the date window can't be pushed down, and the API has no way to return only some
+/// columns; a checkbox purchased column is the one filter it can apply for us.
+fn row_query(mapping: &CodaMapping, purchased_column: Option<&str>) -> Vec<(&'static str, String)> {
+    let mut query = vec![
+        ("useColumnNames", "true".to_string()),
+        ("valueFormat", "rich".to_string()),
+        // Table order, so diagnostics' row numbers match the doc
+        ("sortBy", "natural".to_string()),
+    ];
+    if mapping.visible_only {
+        query.push(("visibleOnly", "true".to_string()));
+    }
+    if let Some(column) = purchased_column {
+        query.push(("query", format!("\"{}\":true", column)));
+    }
+    query
+}
+
+/// The column a bare (non-template) mapping names, if it is a checkbox
+fn checkbox_column<'a>(columns: &'a [CodaColumn], spec: &str) -> Option<&'a CodaColumn> {
+    columns
+        .iter()
+        .find(|c| c.name == spec || c.id == spec)
+        .filter(|c| c.format.kind == "checkbox")
+}
 
-    // Calculate end time (start + 2.5 hours)
-    let end_time = start_time.map(|t| {
-        let start_dt = NaiveDateTime::new(start_date, t);
-        let end_dt = start_dt + Duration::minutes(DEFAULT_EVENT_DURATION_MINUTES);
-        end_dt.time()
-    });
+/// Events read from a table in a Coda doc
+pub struct CodaSource {
+    client: CodaClient,
+    doc_id: String,
+    table_id: String,
+    mapping: CodaMapping,
+}
 
-    // Extract Organization
-    let organization = get_string_value(values, "Organization").ok();
+impl CodaSource {
+    pub fn new(client: CodaClient, doc_id: String, table_id: String, mapping: CodaMapping) -> Self {
+        Self { client, doc_id, table_id, mapping }
+    }
+}
+
+#[async_trait]
+impl EventSource for CodaSource {
+    fn name(&self) -> String {
+        source_name(&self.doc_id, &self.table_id)
+    }
+
+    async fn fetch(&self, options: &FetchOptions) -> Result<SourceEvents> {
+        self.client
+            .fetch_events(
+                &self.doc_id,
+                &self.table_id,
+                &self.mapping,
+                options.default_timezone(None),
+                options.purchased_only,
+            )
+            .await
+    }
+
+    fn request_summary(&self) -> Option<String> {
+        self.client.request_summary()
+    }
+
+    /// Write each row's event ID, link and sync time into the mapping's write-back columns
+    async fn write_back(&self, synced: &[SyncedEvent]) -> Result<()> {
+        let synced_at = chrono::Utc::now().to_rfc3339_opts(chrono::SecondsFormat::Secs, true);
+        let (mut recorded, mut failed) = (0, 0);
+        for event in synced {
+            // e.g. only a link column is mapped and this event has no link
+            let cells = self.mapping.write_back_cells(event, &synced_at);
+            if cells.is_empty() {
+                continue;
+            }
+            match self.client.update_row(&self.doc_id, &self.table_id, &event.source_id, &cells).await {
+                Ok(()) => recorded += 1,
+                Err(e) => {
+                    tracing::warn!("Failed to record event in Coda row {}: {:#}", event.source_id, e);
+                    failed += 1;
+                }
+            }
+        }
+        if failed > 0 {
+            anyhow::bail!("Failed to record {} of {} events in Coda", failed, recorded + failed);
+        }
+        if recorded > 0 {
+            tracing::info!("Recorded {} events in Coda", recorded);
+        }
+        Ok(())
+    }
+}
+
+fn source_name(doc_id: &str, table_id: &str) -> String {
+    format!("coda:{}/{}", doc_id, table_id)
+}
+
+fn parse_coda_row(
+    values: &HashMap<String, serde_json::Value>,
+    mapping: &CodaMapping,
+    default_tz: Tz,
+) -> Result<CalendarEvent> {
+    let field = |spec: &str| mapping::render(spec, |column| get_string_value(values, column).ok());
+    let optional_field = |spec: &Option<String>| spec.as_deref().and_then(field);
+
+    let title = field(&mapping.title)
+        .with_context(|| format!("Missing title ('{}')", mapping.title))?;
+
+    // Extract timezone, falling back to the run's default
+    let timezone = match optional_field(&mapping.timezone) {
+        Some(tz) => parse_timezone(&tz)?,
+        None => default_tz,
+    };
+
+    // Extract start -> start_date, start_time
+    let start_str = get_string_value(values, &mapping.start)
+        .with_context(|| format!("Missing '{}' column", mapping.start))?;
+    
+    let (start_date, start_time) = parse_coda_datetime(&start_str, timezone)
+        .with_context(|| format!("Invalid {}: '{}'", mapping.start, start_str))?;
+
+    let organization = optional_field(&mapping.organization);
 
     // Extract Purchased (check if value is "Yes" or truthy)
-    let purchased = get_string_value(values, "Purchased")
+    let purchased = optional_field(&mapping.purchased)
         .map(|v| v.to_lowercase() == "yes" || v.to_lowercase() == "true")
         .unwrap_or(false);
 
-    // Extract venue -> location
-    let location = get_string_value(values, "venue").ok();
+    let location = optional_field(&mapping.location);
+
+    // End comes from the end column, else start + duration column, else start + the default
+    // duration for the organization or venue
+    let (end_date, end_time) = match optional_field(&mapping.end) {
+        Some(end_str) => {
+            let (end_date, end_time) = match (parse_coda_time(&end_str), start_time) {
+                // A bare time ends on the start date, or the next day if it is earlier than the start
+                (Some(end), Some(start)) if end < start => (next_day(start_date), Some(end)),
+                (Some(end), _) => (start_date, Some(end)),
+                (None, _) => parse_coda_datetime(&end_str, timezone)
+                    .with_context(|| format!("Invalid end: '{}'", end_str))?,
+            };
+            if end_date.and_time(end_time.unwrap_or(NaiveTime::MIN))
+                < start_date.and_time(start_time.unwrap_or(NaiveTime::MIN))
+            {
+                anyhow::bail!("End '{}' is before start '{}'", end_str, start_str);
+            }
+            (end_date, end_time)
+        }
+        None => {
+            let minutes = match optional_field(&mapping.duration) {
+                Some(d) => d
+                    .trim()
+                    .parse::<f64>()
+                    .map_err(anyhow::Error::from)
+                    .and_then(|minutes| mapping::check_minutes(minutes as i64))
+                    .with_context(|| format!("Invalid duration: '{}'", d))?,
+                None => mapping.default_duration(organization.as_deref(), location.as_deref()),
+            };
+            match start_time {
+                // Late shows roll over to the next day
+                Some(t) => {
+                    let end = (start_date.and_time(t))
+                        .checked_add_signed(Duration::minutes(minutes))
+                        .with_context(|| format!("Start '{}' plus {} minutes is out of range", start_str, minutes))?;
+                    (end.date(), Some(end.time()))
+                }
+                None => (start_date, None),
+            }
+        }
+    };
+
+    let recurrence = match optional_field(&mapping.recurrence) {
+        Some(rule) => Some(Recurrence::parse(&rule, optional_field(&mapping.exdates).as_deref())?),
+        None => None,
+    };
 
-    // Build description: kenticoURL\nartists\nworks
-    let description = build_description(values);
+    let reminders = match optional_field(&mapping.reminders) {
+        Some(spec) => Some(spec.parse::<Reminders>().context("Invalid reminders")?),
+        None => None,
+    };
+
+    let attendees = match optional_field(&mapping.attendees) {
+        Some(spec) => parse_attendees(&spec).context("Invalid attendees")?,
+        None => Vec::new(),
+    };
+
+    // Build description from the non-empty description lines
+    let lines: Vec<String> = mapping.description.iter().filter_map(|spec| field(spec)).collect();
+    let description = (!lines.is_empty()).then(|| lines.join("\n"));
 
     Ok(CalendarEvent {
         title,
@@ -168,48 +439,68 @@ fn parse_coda_row(values: &HashMap<String, serde_json::Value>) -> Result<Calenda
         purchased,
         start_date,
         start_time,
-        end_date: start_date,
+        end_date,
         end_time,
+        timezone: Some(timezone),
+        source: None,
+        source_id: None,
+        recurrence,
+        reminders,
+        attendees,
     })
 }
 
 fn get_string_value(values: &HashMap<String, serde_json::Value>, key: &str) -> Result<String> {
     values
         .get(key)
-        .and_then(|v| {
-            match v {
-                serde_json::Value::String(s) => Some(s.clone()),
-                serde_json::Value::Null => None,
-                other => Some(other.to_string().trim_matches('"').to_string()),
-            }
-        })
+        .and_then(cell_text)
         .filter(|s| !s.is_empty())
         .context(format!("Missing or empty value for '{}'", key))
 }
 
-fn build_description(values: &HashMap<String, serde_json::Value>) -> Option<String> {
-    let mut parts = Vec::new();
-
-    if let Ok(url) = get_string_value(values, "kenticoUrl") {
-        parts.push(url);
-    }
-
-    if let Ok(artists) = get_string_value(values, "artists") {
-        parts.push(artists);
+/// Flatten a cell to text. Rows are fetched with `valueFormat=rich`, which wraps plain text
+/// in ``` and returns people, links and row references as JSON-LD objects; lists (e.g.
+/// several people) are joined with commas.
+fn cell_text(value: &serde_json::Value) -> Option<String> {
+    use serde_json::Value;
+    match value {
+        Value::Null => None,
+        Value::String(s) => {
+            let s = s.strip_prefix("```").and_then(|s| s.strip_suffix("```")).unwrap_or(s);
+            Some(s.to_string())
+        }
+        Value::Array(items) => {
+            let parts: Vec<String> = items.iter().filter_map(cell_text).filter(|s| !s.is_empty()).collect();
+            (!parts.is_empty()).then(|| parts.join(", "))
+        }
+        Value::Object(object) => {
+            let field = match object.get("@type").and_then(Value::as_str) {
+                Some("Person") => object.get("email").or_else(|| object.get("name")),
+                Some("WebPage") | Some("ImageObject") => object.get("url"),
+                Some("MonetaryAmount") => object.get("amount"),
+                _ => object.get("name"),
+            };
+            field.and_then(cell_text)
+        }
+        other => Some(other.to_string()),
     }
+}
 
-    if let Ok(works) = get_string_value(values, "works") {
-        parts.push(works);
-    }
+/// Parse a time-of-day value with no date, as Coda returns for time columns
+fn parse_coda_time(s: &str) -> Option<NaiveTime> {
+    let s = s.trim().to_uppercase();
+    ["%H:%M:%S", "%H:%M", "%I:%M:%S %p", "%I:%M %p"]
+        .iter()
+        .find_map(|fmt| NaiveTime::parse_from_str(&s, fmt).ok())
+}
 
-    if parts.is_empty() {
-        None
-    } else {
-        Some(parts.join("\n"))
-    }
+fn next_day(date: NaiveDate) -> NaiveDate {
+    date.succ_opt().unwrap_or(date)
 }
 
-fn parse_coda_datetime(s: &str) -> Result<(NaiveDate, Option<NaiveTime>)> {
+/// Parse a Coda date or datetime into a local date and time in `tz`. Values carrying a UTC
+/// offset are converted to `tz` so the instant is preserved.
+fn parse_coda_datetime(s: &str, tz: Tz) -> Result<(NaiveDate, Option<NaiveTime>)> {
     // Coda datetime formats can vary. Try common formats:
     // ISO 8601 with timezone: "2024-07-17T19:30:00.000-07:00"
     // ISO 8601: "2024-02-15T19:30:00"
@@ -220,12 +511,14 @@ fn parse_coda_datetime(s: &str) -> Result<(NaiveDate, Option<NaiveTime>)> {
 
     // Try ISO 8601 with timezone offset (e.g., "2024-07-17T19:30:00.000-07:00")
     if let Ok(dt) = chrono::DateTime::parse_from_rfc3339(s) {
-        return Ok((dt.date_naive(), Some(dt.time())));
+        let local = dt.with_timezone(&tz).naive_local();
+        return Ok((local.date(), Some(local.time())));
     }
 
     // Try ISO 8601 with timezone but without fractional seconds
     if let Ok(dt) = chrono::DateTime::parse_from_str(s, "%Y-%m-%dT%H:%M:%S%:z") {
-        return Ok((dt.date_naive(), Some(dt.time())));
+        let local = dt.with_timezone(&tz).naive_local();
+        return Ok((local.date(), Some(local.time())));
     }
 
     // Try ISO datetime with T separator (no timezone)
@@ -271,3 +564,273 @@ pub fn get_api_token() -> Result<String> {
     std::env::var("CODA_API_TOKEN")
         .context("CODA_API_TOKEN environment variable not set. Get your token from https://coda.io/account")
 }
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::mapping::MappingFile;
+    use tokio::io::{AsyncReadExt, AsyncWriteExt};
+    use tokio::net::TcpListener;
+
+    #[test]
+    fn test_parse_coda_datetime_preserves_instant() {
+        // 19:30 Pacific is 22:30 in New York
+        let (date, time) =
+            parse_coda_datetime("2024-07-17T19:30:00.000-07:00", chrono_tz::America::New_York).unwrap();
+        assert_eq!(date, NaiveDate::from_ymd_opt(2024, 7, 17).unwrap());
+        assert_eq!(time, NaiveTime::from_hms_opt(22, 30, 0));
+
+        // Crossing midnight moves the date too
+        let (date, time) =
+            parse_coda_datetime("2024-07-17T19:30:00-07:00", chrono_tz::Europe::Berlin).unwrap();
+        assert_eq!(date, NaiveDate::from_ymd_opt(2024, 7, 18).unwrap());
+        assert_eq!(time, NaiveTime::from_hms_opt(4, 30, 0));
+    }
+
+    fn row(values: &[(&str, serde_json::Value)]) -> HashMap<String, serde_json::Value> {
+        values.iter().map(|(k, v)| (k.to_string(), v.clone())).collect()
+    }
+
+    #[test]
+    fn test_parse_coda_row_default_mapping() {
+        let values = row(&[
+            ("Display", "Mahler 9".into()),
+            ("performanceDate", "2026-03-14T19:30:00.000-07:00".into()),
+            ("Organization", "SF Symphony".into()),
+            ("Purchased", true.into()),
+            ("venue", "Davies Hall".into()),
+            ("kenticoUrl", "https://www.sfsymphony.org".into()),
+            ("works", "Symphony No. 9".into()),
+        ]);
+        let event = parse_coda_row(&values, &CodaMapping::default(), chrono_tz::America::Los_Angeles).unwrap();
+
+        assert_eq!(event.title, "Mahler 9");
+        assert!(event.purchased);
+        assert_eq!(event.location.as_deref(), Some("Davies Hall"));
+        assert_eq!(event.description.as_deref(), Some("https://www.sfsymphony.org\nSymphony No. 9"));
+        assert_eq!(event.end_time, NaiveTime::from_hms_opt(22, 0, 0));
+    }
+
+    #[test]
+    fn test_parse_coda_row_custom_mapping() {
+        let mapping: MappingFile = toml::from_str(
+            r#"
+            [coda]
+            title = "{Company}: {Show}"
+            start = "Curtain"
+            duration = "Minutes"
+            location = "Theatre"
+            description = ["Cast: {Cast}", "Notes: {Notes}"]
+            "#,
+        )
+        .unwrap();
+        let values = row(&[
+            ("Show", "Tosca".into()),
+            ("Company", "SF Opera".into()),
+            ("Curtain", "2026-06-05 19:30".into()),
+            ("Minutes", 190.into()),
+            ("Theatre", "War Memorial Opera House".into()),
+            ("Cast", "Netrebko".into()),
+            ("Notes", "".into()),
+        ]);
+        let event = parse_coda_row(&values, &mapping.coda, chrono_tz::America::Los_Angeles).unwrap();
+
+        assert_eq!(event.title, "SF Opera: Tosca");
+        assert_eq!(event.location.as_deref(), Some("War Memorial Opera House"));
+        assert_eq!(event.description.as_deref(), Some("Cast: Netrebko"));
+        assert_eq!(event.end_time, NaiveTime::from_hms_opt(22, 40, 0));
+        assert!(!event.purchased);
+    }
+
+    #[test]
+    fn test_parse_coda_row_durations_and_midnight() {
+        let mapping: MappingFile = toml::from_str(
+            r#"
+            [coda]
+            title = "Show"
+            start = "Curtain"
+            end = "Ends"
+            organization = "Company"
+            location = "Theatre"
+
+            [[coda.durations]]
+            organization = "sf opera"
+            minutes = 210
+
+            [[coda.durations]]
+            location = "Black Cat"
+            minutes = 90
+            "#,
+        )
+        .unwrap();
+        let tz = chrono_tz::America::Los_Angeles;
+        let show = |company: &str, theatre: &str, curtain: &str, ends: &str| {
+            let values = row(&[
+                ("Show", "Show".into()),
+                ("Company", company.into()),
+                ("Theatre", theatre.into()),
+                ("Curtain", curtain.into()),
+                ("Ends", ends.into()),
+            ]);
+            parse_coda_row(&values, &mapping.coda, tz)
+        };
+        let date = |d| NaiveDate::from_ymd_opt(2026, 6, d).unwrap();
+
+        // Organization rule
+        let event = show("SF Opera", "War Memorial", "2026-06-05 19:30", "").unwrap();
+        assert_eq!((event.end_date, event.end_time), (date(5), NaiveTime::from_hms_opt(23, 0, 0)));
+
+        // Venue rule, crossing midnight
+        let event = show("Jazz Trio", "Black Cat", "2026-06-05 23:00", "").unwrap();
+        assert_eq!((event.end_date, event.end_time), (date(6), NaiveTime::from_hms_opt(0, 30, 0)));
+
+        // No rule: the mapping's default
+        let event = show("Jazz Trio", "SFJAZZ", "2026-06-05 19:30", "").unwrap();
+        assert_eq!(event.end_time, NaiveTime::from_hms_opt(22, 0, 0));
+
+        // A time-only end earlier than the start is on the next day
+        let event = show("Jazz Trio", "Black Cat", "2026-06-05 22:00", "1:15 AM").unwrap();
+        assert_eq!((event.end_date, event.end_time), (date(6), NaiveTime::from_hms_opt(1, 15, 0)));
+
+        // A full end datetime wins over every default
+        let event = show("SF Opera", "War Memorial", "2026-06-05 14:00", "2026-06-05 18:45").unwrap();
+        assert_eq!((event.end_date, event.end_time), (date(5), NaiveTime::from_hms_opt(18, 45, 0)));
+
+        assert!(show("SF Opera", "War Memorial", "2026-06-05 19:30", "2026-06-04 22:00").is_err());
+    }
+
+    #[test]
+    fn test_parse_coda_row_rejects_unusable_durations() {
+        let mut mapping = CodaMapping::default();
+        mapping.set("title", "Show").unwrap();
+        mapping.set("start", "Curtain").unwrap();
+        mapping.set("duration", "Length").unwrap();
+        let show = |length: &str| {
+            let values = row(&[
+                ("Show", "Show".into()),
+                ("Curtain", "2026-06-05 19:30".into()),
+                ("Length", length.into()),
+            ]);
+            parse_coda_row(&values, &mapping, chrono_tz::America::Los_Angeles)
+        };
+
+        assert_eq!(show("90").unwrap().end_time, NaiveTime::from_hms_opt(21, 0, 0));
+        for length in ["0", "-30", "1e30", "NaN", "inf"] {
+            let error = show(length).unwrap_err();
+            assert!(format!("{:#}", error).contains("Invalid duration"), "{}: {:#}", length, error);
+        }
+    }
+
+    /// Serve canned JSON responses, one per connection, and hand back the raw request texts
+    async fn mock_server(bodies: Vec<serde_json::Value>) -> (String, tokio::task::JoinHandle<Vec<String>>) {
+        let listener = TcpListener::bind("127.0.0.1:0").await.unwrap();
+        let base_url = format!("http://{}", listener.local_addr().unwrap());
+        let handle = tokio::spawn(async move {
+            let mut requests = Vec::new();
+            for body in bodies {
+                let body = body.to_string();
+                let (mut socket, _) = listener.accept().await.unwrap();
+                let mut buf = vec![0u8; 8192];
+                let n = socket.read(&mut buf).await.unwrap();
+                let response = format!(
+                    "HTTP/1.1 200 OK\r\nContent-Type: application/json\r\nContent-Length: {}\r\nConnection: close\r\n\r\n{}",
+                    body.len(),
+                    body
+                );
+                socket.write_all(response.as_bytes()).await.unwrap();
+                requests.push(String::from_utf8_lossy(&buf[..n]).into_owned());
+            }
+            requests
+        });
+        (base_url, handle)
+    }
+
+    #[tokio::test]
+    async fn test_fetch_events_pushes_purchased_filter_and_reads_rich_values() {
+        let (base_url, requests) = mock_server(vec![
+            serde_json::json!({ "items": [
+                { "id": "c-title", "name": "Display", "format": { "type": "text" } },
+                { "id": "c-bought", "name": "Purchased", "format": { "type": "checkbox" } },
+            ]}),
+            serde_json::json!({ "items": [{ "id": "i-1", "values": {
+                "Display": "```Mahler 9```",
+                "performanceDate": "2026-03-14T19:30:00.000-07:00",
+                "Purchased": true,
+                "kenticoUrl": { "@context": "http://schema.org/", "@type": "WebPage", "url": "https://www.sfsymphony.org" },
+                "Guests": [
+                    { "@context": "http://schema.org/", "@type": "Person", "name": "Sam", "email": "sam@example.com" },
+                    { "@context": "http://schema.org/", "@type": "Person", "name": "Alex", "email": "alex@example.org" },
+                ],
+            }}]}),
+        ])
+        .await;
+        let client = CodaClient::with_base_url("test-token".to_string(), base_url);
+        let mut mapping = CodaMapping::default();
+        mapping.set("attendees", "Guests").unwrap();
+        mapping.set("visible_only", "true").unwrap();
+
+        let fetched = client
+            .fetch_events("doc1", "grid-1", &mapping, chrono_tz::America::Los_Angeles, true)
+            .await
+            .unwrap();
+        assert!(fetched.diagnostics.is_empty());
+        let event = &fetched.events[0];
+        assert_eq!(event.title, "Mahler 9");
+        assert_eq!(event.description.as_deref(), Some("https://www.sfsymphony.org"));
+        let emails: Vec<&str> = event.attendees.iter().map(|a| a.email.as_str()).collect();
+        assert_eq!(emails, ["sam@example.com", "alex@example.org"]);
+
+        let requests = requests.await.unwrap();
+        assert!(requests[0].starts_with("GET /docs/doc1/tables/grid-1/columns?"));
+        let rows = requests[1].lines().next().unwrap();
+        assert!(rows.starts_with("GET /docs/doc1/tables/grid-1/rows?"));
+        for param in ["valueFormat=rich", "sortBy=natural", "visibleOnly=true", "query=%22c-bought%22%3Atrue"] {
+            assert!(rows.contains(param), "{} missing from {}", param, rows);
+        }
+    }
+
+    #[tokio::test]
+    async fn test_write_back_skips_rows_with_nothing_to_write() {
+        let (base_url, requests) = mock_server(vec![serde_json::json!({ "id": "i-2" })]).await;
+        let mut mapping = CodaMapping::default();
+        mapping.set("event_link_column", "Calendar Link").unwrap();
+        let client = CodaClient::with_base_url("test-token".to_string(), base_url);
+        let source = CodaSource::new(client, "doc1".to_string(), "grid-1".to_string(), mapping);
+
+        let synced = |row: &str, link: Option<&str>| SyncedEvent {
+            source_id: row.to_string(),
+            event_id: format!("evt-{}", row),
+            html_link: link.map(str::to_string),
+        };
+        // The first event has no link to record; the second one still gets its row updated
+        source
+            .write_back(&[synced("i-1", None), synced("i-2", Some("https://calendar.google.com/event?eid=2"))])
+            .await
+            .unwrap();
+
+        let requests = requests.await.unwrap();
+        assert_eq!(requests.len(), 1);
+        assert!(requests[0].starts_with("PUT /docs/doc1/tables/grid-1/rows/i-2 "));
+        assert!(requests[0].contains("Calendar Link"));
+    }
+
+    #[test]
+    fn test_row_query_skips_non_checkbox_purchased_column() {
+        let columns = vec![CodaColumn {
+            id: "c-bought".to_string(),
+            name: "Purchased".to_string(),
+            format: CodaColumnFormat { kind: "text".to_string() },
+        }];
+        assert!(checkbox_column(&columns, "Purchased").is_none());
+        let query = row_query(&CodaMapping::default(), None);
+        assert!(!query.iter().any(|(name, _)| *name == "query" || *name == "visibleOnly"));
+    }
+
+    #[test]
+    fn test_parse_coda_datetime_keeps_naive_values() {
+        let (date, time) =
+            parse_coda_datetime("2024-02-15T19:30:00", chrono_tz::Europe::London).unwrap();
+        assert_eq!(date, NaiveDate::from_ymd_opt(2024, 2, 15).unwrap());
+        assert_eq!(time, NaiveTime::from_hms_opt(19, 30, 0));
+    }
+}
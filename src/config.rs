@@ -0,0 +1,523 @@
+use anyhow::{Context, Result};
+use chrono::NaiveDate;
+use chrono_tz::Tz;
+use clap::ValueEnum;
+use serde::Deserialize;
+use std::collections::BTreeMap;
+use std::path::{Path, PathBuf};
+
+use crate::calendar::{AuthPaths, ColorRule, SendUpdates, DEFAULT_JOBS};
+use crate::cli::{self, Cli, Commands, FilterArgs, MappingArgs, SourceArgs, DEFAULT_CALENDAR_ID, DEFAULT_SHEETS_RANGE};
+use crate::event::{DstPolicy, Reminders, DEFAULT_TIMEZONE};
+use crate::pipeline::RunOptions;
+
+pub const CONFIG_FILE: &str = "calendar-sync.toml";
+
+/// The project config file (`calendar-sync.toml`):
+///
+/// ```toml
+/// default_profile = "season-2026"
+///
+/// [google]
+/// credentials_path = "~/secrets/credentials.json"
+///
+/// [profiles.season-2026]
+/// doc_id = "AbCdEf"
+/// table_id = "Performances"
+/// calendar_id = "concerts@group.calendar.google.com"
+/// start_date = "2026-09-01"
+/// purchased = true
+/// ```
+#[derive(Debug, Clone, Default, Deserialize)]
+#[serde(default, deny_unknown_fields)]
+pub struct ConfigFile {
+    /// Profile used when `--profile` is not given
+    pub default_profile: Option<String>,
+    pub google: AuthPaths,
+    /// Event color rules for every profile (see `calendar::ColorRule`)
+    pub colors: Vec<ColorRule>,
+    pub profiles: BTreeMap<String, Profile>,
+}
+
+/// Defaults for one kind of run. Every field is optional and only fills in options that were
+/// not given on the command line.
+#[derive(Debug, Clone, Default, Deserialize)]
+#[serde(default, deny_unknown_fields)]
+pub struct Profile {
+    // Source: a CSV file, a Coda table or a Google Sheets range
+    pub file: Option<PathBuf>,
+    pub doc_id: Option<String>,
+    pub table_id: Option<String>,
+    pub spreadsheet_id: Option<String>,
+    pub range: Option<String>,
+    /// Column mapping file (see `mapping::MappingFile`)
+    pub mapping: Option<PathBuf>,
+
+    pub calendar_id: Option<String>,
+    pub timezone: Option<Tz>,
+    pub dst_policy: Option<DstPolicy>,
+    /// Reminders for events whose source sets none, e.g. "popup:120,email:1440"
+    pub reminders: Option<Reminders>,
+    /// Event color rules, replacing the top-level `[[colors]]` for this profile
+    pub colors: Option<Vec<ColorRule>>,
+    /// Who Google emails about events with attendees
+    pub send_updates: Option<SendUpdates>,
+    /// Google Calendar writes to run at once
+    pub jobs: Option<usize>,
+    /// Retries for failed API requests
+    pub max_retries: Option<u32>,
+    /// Google Calendar requests per second
+    pub rate_limit: Option<f64>,
+
+    // Default filters
+    pub start_date: Option<NaiveDate>,
+    pub end_date: Option<NaiveDate>,
+    pub purchased: Option<bool>,
+}
+
+/// A loaded config file, or the empty config when there is none
+#[derive(Debug, Default)]
+pub struct Config {
+    pub path: Option<PathBuf>,
+    pub file: ConfigFile,
+}
+
+impl Config {
+    /// Load `explicit` if given, else the first config file found in the search path
+    pub fn load(explicit: Option<&Path>) -> Result<Self> {
+        let path = match explicit {
+            Some(path) => path.to_path_buf(),
+            None => match search_paths().into_iter().find(|p| p.is_file()) {
+                Some(path) => path,
+                None => return Ok(Self::default()),
+            },
+        };
+
+        let contents = std::fs::read_to_string(&path)
+            .with_context(|| format!("Failed to read config file: {}", path.display()))?;
+        let mut file: ConfigFile = toml::from_str(&contents)
+            .with_context(|| format!("Invalid config file: {}", path.display()))?;
+
+        // Relative paths are relative to the config file, not wherever the command runs
+        let base = path.parent().unwrap_or(Path::new("."));
+        file.resolve_paths(base);
+        for rule in &file.colors {
+            rule.validate().with_context(|| format!("Invalid color rule in {}", path.display()))?;
+        }
+        for (name, profile) in &file.profiles {
+            profile.validate().with_context(|| format!("Invalid profile '{}' in {}", name, path.display()))?;
+        }
+
+        tracing::debug!("Loaded config from {}", path.display());
+        Ok(Self { path: Some(path), file })
+    }
+
+    /// The profile's color rules if it has its own, else the top-level ones
+    pub fn color_rules(&self, profile: Option<&Profile>) -> Vec<ColorRule> {
+        profile
+            .and_then(|p| p.colors.clone())
+            .unwrap_or_else(|| self.file.colors.clone())
+    }
+
+    /// The profile named by `--profile`, else `default_profile`, else none
+    pub fn profile(&self, name: Option<&str>) -> Result<Option<(&str, &Profile)>> {
+        let Some(name) = name.or(self.file.default_profile.as_deref()) else {
+            return Ok(None);
+        };
+        let Some(path) = &self.path else {
+            anyhow::bail!("Profile '{}' requested but no {} was found", name, CONFIG_FILE);
+        };
+        self.file
+            .profiles
+            .get_key_value(name)
+            .map(|(name, profile)| Some((name.as_str(), profile)))
+            .with_context(|| {
+                let names: Vec<&str> = self.file.profiles.keys().map(String::as_str).collect();
+                format!(
+                    "No profile '{}' in {} (available: {})",
+                    name,
+                    path.display(),
+                    if names.is_empty() { "none".to_string() } else { names.join(", ") }
+                )
+            })
+    }
+}
+
+/// `./calendar-sync.toml`, then `$XDG_CONFIG_HOME/calendar-sync/calendar-sync.toml`
+/// (`~/.config/...` when `XDG_CONFIG_HOME` is unset)
+pub fn search_paths() -> Vec<PathBuf> {
+    let mut paths = vec![PathBuf::from(CONFIG_FILE)];
+    let config_home = std::env::var_os("XDG_CONFIG_HOME")
+        .map(PathBuf::from)
+        .filter(|p| p.is_absolute())
+        .or_else(|| std::env::var_os("HOME").map(|home| PathBuf::from(home).join(".config")));
+    if let Some(config_home) = config_home {
+        paths.push(config_home.join("calendar-sync").join(CONFIG_FILE));
+    }
+    paths
+}
+
+/// The folder for one kind of saved state, e.g. "runs": `$XDG_STATE_HOME/calendar-sync/runs`
+/// (`~/.local/state/...` when `XDG_STATE_HOME` is unset), or `calendar-sync-runs` in the
+/// current directory without a home directory
+pub fn state_dir(name: &str) -> PathBuf {
+    let state_home = std::env::var_os("XDG_STATE_HOME")
+        .filter(|dir| !dir.is_empty())
+        .map(PathBuf::from)
+        .or_else(|| std::env::var_os("HOME").map(|home| PathBuf::from(home).join(".local").join("state")));
+    match state_home {
+        Some(dir) => dir.join("calendar-sync").join(name),
+        None => PathBuf::from(format!("calendar-sync-{}", name)),
+    }
+}
+
+impl ConfigFile {
+    fn resolve_paths(&mut self, base: &Path) {
+        let resolve = |path: &mut Option<PathBuf>| {
+            if let Some(p) = path.as_mut() {
+                *p = expand_path(p, base);
+            }
+        };
+        resolve(&mut self.google.credentials_path);
+        resolve(&mut self.google.token_cache_path);
+        for profile in self.profiles.values_mut() {
+            resolve(&mut profile.file);
+            resolve(&mut profile.mapping);
+        }
+    }
+}
+
+/// Expand a leading `~/` and anchor relative paths at `base`
+fn expand_path(path: &Path, base: &Path) -> PathBuf {
+    if let (Ok(rest), Some(home)) = (path.strip_prefix("~"), std::env::var_os("HOME")) {
+        return PathBuf::from(home).join(rest);
+    }
+    base.join(path)
+}
+
+impl Profile {
+    fn validate(&self) -> Result<()> {
+        let sources = [self.file.is_some(), self.doc_id.is_some(), self.spreadsheet_id.is_some()];
+        anyhow::ensure!(
+            sources.iter().filter(|&&set| set).count() <= 1,
+            "set only one of file, doc_id or spreadsheet_id"
+        );
+        if let Some(jobs) = self.jobs {
+            anyhow::ensure!(
+                (1..=cli::MAX_JOBS).contains(&jobs),
+                "invalid jobs {}: use a number from 1 to {}",
+                jobs,
+                cli::MAX_JOBS
+            );
+        }
+        if let Some(rate) = self.rate_limit {
+            anyhow::ensure!(
+                cli::valid_rate_limit(rate),
+                "invalid rate_limit {}: use a positive number of requests per second",
+                rate
+            );
+        }
+        for rule in self.colors.iter().flatten() {
+            rule.validate()?;
+        }
+        Ok(())
+    }
+
+    /// Fill in every option the command line left unset
+    pub fn apply(&self, cli: &mut Cli) {
+        fill(&mut cli.timezone, &self.timezone);
+        fill(&mut cli.dst_policy, &self.dst_policy);
+        fill(&mut cli.reminders, &self.reminders);
+        fill(&mut cli.max_retries, &self.max_retries);
+        fill(&mut cli.rate_limit, &self.rate_limit);
+
+        match &mut cli.command {
+            Commands::Import { file, mapping, args, .. } => {
+                fill(file, &self.file);
+                self.apply_mapping(mapping);
+                fill(&mut args.calendar_id, &self.calendar_id);
+                fill(&mut args.send_updates, &self.send_updates);
+                fill(&mut args.jobs, &self.jobs);
+                self.apply_filter(&mut args.filter);
+            }
+            Commands::CodaImport { doc_id, table_id, mapping, args } => {
+                fill(doc_id, &self.doc_id);
+                fill(table_id, &self.table_id);
+                self.apply_mapping(mapping);
+                fill(&mut args.calendar_id, &self.calendar_id);
+                fill(&mut args.send_updates, &self.send_updates);
+                fill(&mut args.jobs, &self.jobs);
+                self.apply_filter(&mut args.filter);
+            }
+            Commands::SheetsImport { spreadsheet_id, range, mapping, args } => {
+                fill(spreadsheet_id, &self.spreadsheet_id);
+                fill(range, &self.range);
+                self.apply_mapping(mapping);
+                fill(&mut args.calendar_id, &self.calendar_id);
+                fill(&mut args.send_updates, &self.send_updates);
+                fill(&mut args.jobs, &self.jobs);
+                self.apply_filter(&mut args.filter);
+            }
+            Commands::Sync { source, args } => {
+                self.apply_source(source);
+                fill(&mut args.calendar_id, &self.calendar_id);
+                fill(&mut args.send_updates, &self.send_updates);
+                fill(&mut args.jobs, &self.jobs);
+                self.apply_filter(&mut args.filter);
+            }
+            Commands::ExportIcs { source, args } => {
+                self.apply_source(source);
+                self.apply_filter(&mut args.filter);
+            }
+            Commands::Backup { args } => {
+                fill(&mut args.calendar_id, &self.calendar_id);
+                fill(&mut args.start_date, &self.start_date);
+                fill(&mut args.end_date, &self.end_date);
+            }
+            // The calendar comes from the backup, not the profile
+            Commands::Restore { args } => {
+                fill(&mut args.send_updates, &self.send_updates);
+                fill(&mut args.jobs, &self.jobs);
+            }
+            Commands::Undo { args } => {
+                fill(&mut args.send_updates, &self.send_updates);
+                fill(&mut args.jobs, &self.jobs);
+            }
+            Commands::ListCodaTables { doc_id } => fill(doc_id, &self.doc_id),
+            Commands::ListCalendars
+            | Commands::ListColors
+            | Commands::Auth
+            | Commands::History { .. }
+            | Commands::Config { .. } => {}
+        }
+    }
+
+    /// A source given on the command line wins outright; the profile only completes it
+    /// (e.g. `--table-id` with the profile's `doc_id`)
+    fn apply_source(&self, source: &mut SourceArgs) {
+        let cli_coda = source.doc_id.is_some() || source.table_id.is_some();
+        let cli_sheets = source.spreadsheet_id.is_some() || source.range.is_some();
+
+        if source.file.is_none() && !cli_sheets {
+            fill(&mut source.doc_id, &self.doc_id);
+            fill(&mut source.table_id, &self.table_id);
+        }
+        if source.file.is_none() && !cli_coda {
+            fill(&mut source.spreadsheet_id, &self.spreadsheet_id);
+            fill(&mut source.range, &self.range);
+        }
+        if !cli_coda && !cli_sheets {
+            fill(&mut source.file, &self.file);
+        }
+        self.apply_mapping(&mut source.mapping);
+    }
+
+    fn apply_mapping(&self, mapping: &mut MappingArgs) {
+        fill(&mut mapping.mapping, &self.mapping);
+    }
+
+    fn apply_filter(&self, filter: &mut FilterArgs) {
+        fill(&mut filter.start_date, &self.start_date);
+        fill(&mut filter.end_date, &self.end_date);
+        filter.purchased |= self.purchased.unwrap_or(false);
+    }
+}
+
+/// Print the settings a run would use: global flags merged over the profile and config file
+pub fn show(config: &Config, profile: Option<(&str, &Profile)>, options: &RunOptions) -> Result<()> {
+    fn row(label: &str, value: impl std::fmt::Display) {
+        println!("  {:<16} {}", label, value);
+    }
+    fn or_unset<T: ToString>(value: &Option<T>) -> String {
+        value.as_ref().map(T::to_string).unwrap_or_else(|| "-".to_string())
+    }
+
+    println!("\nConfiguration:");
+    println!("{:-<60}", "");
+    match &config.path {
+        Some(path) => row("Config file", path.display()),
+        None => {
+            let searched: Vec<String> = search_paths().iter().map(|p| p.display().to_string()).collect();
+            row("Config file", format!("(none found; searched {})", searched.join(", ")))
+        }
+    }
+    let available: Vec<&str> = config.file.profiles.keys().map(String::as_str).collect();
+    row("Profile", match profile {
+        Some((name, _)) => name.to_string(),
+        None => "-".to_string(),
+    });
+    if !available.is_empty() {
+        row("Profiles", available.join(", "));
+    }
+    row("Credentials", options.auth.credentials_path()?.display());
+    row("Token cache", options.auth.token_cache_path()?.display());
+    row("Time zone", match options.fetch.timezone {
+        Some(tz) => tz.to_string(),
+        None => format!("source default, else {}", DEFAULT_TIMEZONE),
+    });
+    row("DST policy", options.convert.dst_policy.to_possible_value().map(|v| v.get_name().to_string()).unwrap_or_default());
+    row("Reminders", match &options.fetch.reminders {
+        Some(reminders) => reminders.to_string(),
+        None => "calendar default".to_string(),
+    });
+    row("Max retries", options.requests.retry.max_retries);
+    row("Rate limit", format!("{} requests/s", options.requests.rate_limit));
+
+    let profile = profile.map(|(_, p)| p.clone()).unwrap_or_default();
+    let source = match &profile {
+        Profile { file: Some(file), .. } => format!("CSV {}", file.display()),
+        Profile { doc_id: Some(doc_id), .. } => format!("Coda {}/{}", doc_id, or_unset(&profile.table_id)),
+        Profile { spreadsheet_id: Some(id), .. } => format!(
+            "Google Sheets {} ({})",
+            id,
+            profile.range.as_deref().unwrap_or(DEFAULT_SHEETS_RANGE)
+        ),
+        _ => "-".to_string(),
+    };
+    row("Source", source);
+    row("Mapping", profile.mapping.as_ref().map(|p| p.display().to_string()).unwrap_or_else(|| "(built-in)".to_string()));
+    row("Calendar", profile.calendar_id.as_deref().unwrap_or(DEFAULT_CALENDAR_ID));
+    row("Send updates", profile.send_updates.map_or("none", SendUpdates::as_str));
+    row("Jobs", profile.jobs.unwrap_or(DEFAULT_JOBS));
+    row("Start date", or_unset(&profile.start_date));
+    row("End date", or_unset(&profile.end_date));
+    row("Purchased only", if profile.purchased.unwrap_or(false) { "yes" } else { "no" });
+    if options.convert.colors.is_empty() {
+        row("Colors", "calendar default");
+    }
+    for (i, rule) in options.convert.colors.iter().enumerate() {
+        row(if i == 0 { "Colors" } else { "" }, rule);
+    }
+    println!();
+    Ok(())
+}
+
+fn fill<T: Clone>(value: &mut Option<T>, default: &Option<T>) {
+    if value.is_none() {
+        value.clone_from(default);
+    }
+}
+
+/// Unwrap an option that must come from either a flag or the profile
+pub fn require<T>(value: Option<T>, flag: &str) -> Result<T> {
+    value.with_context(|| format!("Missing --{} (pass it, or set it in a --profile)", flag))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use clap::Parser;
+
+    fn config() -> Config {
+        let file: ConfigFile = toml::from_str(
+            r#"
+            default_profile = "season"
+
+            [google]
+            credentials_path = "secrets/credentials.json"
+
+            [[colors]]
+            organization = "SF Symphony"
+            color_id = 9
+
+            [[colors]]
+            purchased = true
+            color_id = 10
+
+            [profiles.season]
+            doc_id = "doc123"
+            table_id = "Performances"
+            calendar_id = "concerts"
+            timezone = "America/New_York"
+            start_date = "2026-09-01"
+            purchased = true
+
+            [profiles.tour]
+            file = "tour.csv"
+            dst_policy = "reject"
+            reminders = "popup:120,email:1440"
+            colors = []
+            "#,
+        )
+        .unwrap();
+        Config { path: Some(PathBuf::from(CONFIG_FILE)), file }
+    }
+
+    fn resolve(args: &[&str]) -> Cli {
+        let mut cli = Cli::try_parse_from(args).unwrap();
+        let config = config();
+        if let Some((_, profile)) = config.profile(cli.profile.as_deref()).unwrap() {
+            profile.apply(&mut cli);
+        }
+        cli
+    }
+
+    #[test]
+    fn test_profile_fills_unset_options() {
+        let cli = resolve(&["calendar-sync", "sync", "--end-date", "2026-12-31"]);
+        assert_eq!(cli.timezone, Some(chrono_tz::America::New_York));
+        let Commands::Sync { source, args } = cli.command else { panic!("expected sync") };
+        assert_eq!(source.doc_id.as_deref(), Some("doc123"));
+        assert_eq!(source.table_id.as_deref(), Some("Performances"));
+        assert_eq!(args.calendar_id(), "concerts");
+        assert_eq!(args.filter.start_date, NaiveDate::from_ymd_opt(2026, 9, 1));
+        assert_eq!(args.filter.end_date, NaiveDate::from_ymd_opt(2026, 12, 31));
+        assert!(args.filter.purchased);
+    }
+
+    #[test]
+    fn test_flags_override_profile() {
+        let cli = resolve(&[
+            "calendar-sync", "--timezone", "Europe/Berlin", "sync", "--file", "other.csv", "-c", "primary",
+        ]);
+        assert_eq!(cli.timezone, Some(chrono_tz::Europe::Berlin));
+        let Commands::Sync { source, args } = cli.command else { panic!("expected sync") };
+        assert_eq!(source.file, Some(PathBuf::from("other.csv")));
+        assert_eq!(source.doc_id, None);
+        assert_eq!(args.calendar_id(), "primary");
+
+        let cli = resolve(&["calendar-sync", "--profile", "tour", "import"]);
+        assert_eq!(cli.dst_policy, Some(DstPolicy::Reject));
+        assert_eq!(cli.reminders.as_ref().map(|r| r.to_string()).as_deref(), Some("popup:120,email:1440"));
+        let Commands::Import { file, args, .. } = cli.command else { panic!("expected import") };
+        assert_eq!(file, Some(PathBuf::from("tour.csv")));
+        assert_eq!(args.calendar_id(), "primary");
+    }
+
+    #[test]
+    fn test_unknown_profile_and_paths() {
+        let err = config().profile(Some("missing")).unwrap_err();
+        assert!(err.to_string().contains("available: season, tour"));
+
+        let mut file = config().file;
+        file.resolve_paths(Path::new("/etc/calendar-sync"));
+        assert_eq!(
+            file.google.credentials_path,
+            Some(PathBuf::from("/etc/calendar-sync/secrets/credentials.json"))
+        );
+        assert_eq!(file.profiles["tour"].file, Some(PathBuf::from("/etc/calendar-sync/tour.csv")));
+
+        let both: Profile = toml::from_str("file = \"a.csv\"\ndoc_id = \"doc\"").unwrap();
+        assert!(both.validate().is_err());
+        for jobs in [0, 500] {
+            let bad: Profile = toml::from_str(&format!("jobs = {}", jobs)).unwrap();
+            assert!(bad.validate().unwrap_err().to_string().contains("from 1 to 32"), "{}", jobs);
+        }
+        for rate in ["0", "-1.5", "inf"] {
+            let bad: Profile = toml::from_str(&format!("rate_limit = {}", rate)).unwrap();
+            assert!(bad.validate().unwrap_err().to_string().contains("rate_limit"), "{}", rate);
+        }
+    }
+
+    #[test]
+    fn test_color_rules() {
+        let config = config();
+        let rules = config.color_rules(Some(&config.file.profiles["season"]));
+        assert_eq!(rules.len(), 2);
+        assert_eq!(rules[0].to_string(), "organization = \"SF Symphony\" -> 9 (Blueberry)");
+        assert!(config.color_rules(Some(&config.file.profiles["tour"])).is_empty());
+
+        let bad: Profile = toml::from_str("[[colors]]\npurchased = true\ncolor_id = 12").unwrap();
+        assert!(bad.validate().unwrap_err().to_string().contains("list-colors"));
+    }
+}
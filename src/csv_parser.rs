@@ -1,77 +1,229 @@
 use anyhow::{Context, Result};
+use async_trait::async_trait;
 use chrono::{NaiveDate, NaiveTime};
-use csv::Reader;
-use serde::Deserialize;
-use std::path::Path;
-
-use crate::event::CalendarEvent;
-
-#[derive(Debug, Deserialize)]
-struct CsvRecord {
-    title: String,
-    #[serde(default)]
-    description: Option<String>,
-    #[serde(default)]
-    location: Option<String>,
-    start_date: String,
-    #[serde(default)]
-    start_time: Option<String>,
-    #[serde(default)]
-    end_date: Option<String>,
-    #[serde(default)]
-    end_time: Option<String>,
+use chrono_tz::Tz;
+use csv::{ReaderBuilder, StringRecord};
+use std::collections::HashMap;
+use std::path::{Path, PathBuf};
+
+use crate::event::{parse_attendees, parse_timezone, CalendarEvent, ContentKeys, Reminders};
+use crate::mapping::{self, CsvMapping};
+use crate::recurrence::Recurrence;
+use crate::source::{self, EventSource, FetchOptions, RowDiagnostic, SourceEvents};
+
+/// Column positions in a header row. Header names match case-insensitively, with spaces and
+/// underscores treated alike, so "Start Date" finds `start_date`.
+pub struct CsvHeaders {
+    columns: HashMap<String, usize>,
+}
+
+impl CsvHeaders {
+    pub fn new(headers: &StringRecord) -> Self {
+        let mut columns = HashMap::new();
+        for (index, header) in headers.iter().enumerate() {
+            columns.entry(normalize_header(header)).or_insert(index);
+        }
+        Self { columns }
+    }
+
+    /// Fail early if a required field is mapped to a column the file doesn't have
+    pub fn check(&self, mapping: &CsvMapping) -> Result<()> {
+        for (field, spec) in [("title", &mapping.title), ("start_date", &mapping.start_date)] {
+            if !spec.contains('{') && !self.columns.contains_key(&normalize_header(spec)) {
+                anyhow::bail!(
+                    "No '{}' column for {} (map one with --map {}=<header>)",
+                    spec, field, field
+                );
+            }
+        }
+        Ok(())
+    }
+
+    /// A column's trimmed value in `row`, or `None` if the column is missing or empty
+    fn get(&self, row: &StringRecord, column: &str) -> Option<String> {
+        let index = *self.columns.get(&normalize_header(column))?;
+        row.get(index)
+            .map(str::trim)
+            .filter(|v| !v.is_empty())
+            .map(str::to_string)
+    }
+}
+
+fn normalize_header(header: &str) -> String {
+    header.trim().to_lowercase().replace(' ', "_")
+}
+
+/// Events read from a CSV file
+pub struct CsvSource {
+    path: PathBuf,
+    mapping: CsvMapping,
+    name: String,
+}
+
+impl CsvSource {
+    pub fn new(path: PathBuf, mapping: CsvMapping) -> Self {
+        let name = match &mapping.source_name {
+            Some(name) => format!("csv:{}", name),
+            None => format!("csv:{}", source::file_identity(&path)),
+        };
+        Self { path, mapping, name }
+    }
+}
+
+#[async_trait]
+impl EventSource for CsvSource {
+    /// e.g. "csv:/home/sam/events.csv", or "csv:season-2026" if the mapping sets `source_name`
+    fn name(&self) -> String {
+        self.name.clone()
+    }
+
+    async fn fetch(&self, options: &FetchOptions) -> Result<SourceEvents> {
+        parse_csv(&self.path, &self.name(), &self.mapping, options.default_timezone(None))
+    }
 }
 
-pub fn parse_csv(path: &Path) -> Result<Vec<CalendarEvent>> {
-    let mut reader = Reader::from_path(path)
+/// Parse a CSV file. Rows that fail to parse are reported as diagnostics and skipped.
+/// Rows without a `timezone` value are interpreted in `default_tz`.
+pub fn parse_csv(path: &Path, source: &str, mapping: &CsvMapping, default_tz: Tz) -> Result<SourceEvents> {
+    let bytes = std::fs::read(path)
         .with_context(|| format!("Failed to open CSV file: {}", path.display()))?;
+    let text = mapping
+        .encoding
+        .decode(&bytes)
+        .with_context(|| format!("Failed to read CSV file: {}", path.display()))?;
+
+    let delimiter = mapping.delimiter.unwrap_or_else(|| detect_delimiter(&text));
+    let mut reader = ReaderBuilder::new()
+        .delimiter(delimiter)
+        .flexible(true)
+        .from_reader(text.as_bytes());
 
-    let mut events = Vec::new();
+    let headers = CsvHeaders::new(reader.headers().context("Failed to read CSV header row")?);
+    headers.check(mapping)?;
 
-    for (index, result) in reader.deserialize().enumerate() {
-        let record: CsvRecord = result
-            .with_context(|| format!("Failed to parse row {}", index + 1))?;
+    let mut parsed = SourceEvents::default();
+    let mut keys = ContentKeys::default();
 
-        let event = parse_record(record, index + 1)?;
-        events.push(event);
+    for (index, result) in reader.records().enumerate() {
+        let row_num = index + 1;
+        let event = result
+            .with_context(|| format!("Failed to parse row {}", row_num))
+            .and_then(|row| parse_row(&headers, &row, row_num, mapping, default_tz, &mut keys));
+
+        match event {
+            Ok(mut event) => {
+                event.source = Some(source.to_string());
+                parsed.events.push(event);
+            }
+            Err(e) => parsed.diagnostics.push(RowDiagnostic {
+                row: row_num,
+                message: format!("{:#}", e),
+            }),
+        }
     }
 
-    Ok(events)
+    Ok(parsed)
 }
 
-fn parse_record(record: CsvRecord, row_num: usize) -> Result<CalendarEvent> {
-    let start_date = parse_date(&record.start_date)
-        .with_context(|| format!("Invalid start_date in row {}: '{}'", row_num, record.start_date))?;
+/// Pick whichever of comma, semicolon or tab appears most in the header line
+fn detect_delimiter(text: &str) -> u8 {
+    let header_line = text.lines().next().unwrap_or_default();
+    [b',', b';', b'\t']
+        .into_iter()
+        .max_by_key(|&d| header_line.bytes().filter(|&b| b == d).count())
+        .filter(|&d| header_line.as_bytes().contains(&d))
+        .unwrap_or(b',')
+}
 
-    let end_date = match &record.end_date {
-        Some(d) if !d.is_empty() => parse_date(d)
-            .with_context(|| format!("Invalid end_date in row {}: '{}'", row_num, d))?,
-        _ => start_date,
-    };
+/// Parse one row against the header row. Also used by sources that are CSV-shaped but not
+/// files (Sheets).
+pub fn parse_row(
+    headers: &CsvHeaders,
+    row: &StringRecord,
+    row_num: usize,
+    mapping: &CsvMapping,
+    default_tz: Tz,
+    keys: &mut ContentKeys,
+) -> Result<CalendarEvent> {
+    let field = |spec: &str| mapping::render(spec, |column| headers.get(row, column));
+
+    let title = field(&mapping.title)
+        .with_context(|| format!("Missing title in row {}", row_num))?;
+
+    let start_date_str = field(&mapping.start_date)
+        .with_context(|| format!("Missing start_date in row {}", row_num))?;
+    let start_date = parse_date(&start_date_str)
+        .with_context(|| format!("Invalid start_date in row {}: '{}'", row_num, start_date_str))?;
 
-    let start_time = match &record.start_time {
-        Some(t) if !t.is_empty() => Some(parse_time(t)
+    let end_date = field(&mapping.end_date)
+        .map(|d| parse_date(&d).with_context(|| format!("Invalid end_date in row {}: '{}'", row_num, d)))
+        .transpose()?;
+
+    let start_time = match field(&mapping.start_time) {
+        Some(t) => Some(parse_time(&t)
             .with_context(|| format!("Invalid start_time in row {}: '{}'", row_num, t))?),
-        _ => None,
+        None => None,
     };
 
-    let end_time = match &record.end_time {
-        Some(t) if !t.is_empty() => Some(parse_time(t)
+    let end_time = match field(&mapping.end_time) {
+        Some(t) => Some(parse_time(&t)
             .with_context(|| format!("Invalid end_time in row {}: '{}'", row_num, t))?),
-        _ => None,
+        None => None,
     };
 
-    Ok(CalendarEvent {
-        title: record.title,
-        description: record.description.filter(|s| !s.is_empty()),
-        location: record.location.filter(|s| !s.is_empty()),
-        organization: None, // CSV doesn't have organization column
-        purchased: false,   // CSV doesn't have purchased column
+    // Without an end date, an end time earlier than the start time is past midnight
+    let end_date = match (end_date, start_time, end_time) {
+        (Some(d), _, _) => d,
+        (None, Some(start), Some(end)) if end < start => start_date.succ_opt().unwrap_or(start_date),
+        (None, _, _) => start_date,
+    };
+
+    let timezone = match field(&mapping.timezone) {
+        Some(tz) => parse_timezone(&tz)
+            .with_context(|| format!("Invalid timezone in row {}", row_num))?,
+        None => default_tz,
+    };
+
+    let recurrence = match field(&mapping.recurrence) {
+        Some(rule) => Some(Recurrence::parse(&rule, field(&mapping.exdates).as_deref())
+            .with_context(|| format!("Invalid recurrence in row {}", row_num))?),
+        None => None,
+    };
+
+    let reminders = match field(&mapping.reminders) {
+        Some(spec) => Some(spec.parse::<Reminders>()
+            .with_context(|| format!("Invalid reminders in row {}", row_num))?),
+        None => None,
+    };
+
+    let attendees = match field(&mapping.attendees) {
+        Some(spec) => parse_attendees(&spec)
+            .with_context(|| format!("Invalid attendees in row {}", row_num))?,
+        None => Vec::new(),
+    };
+
+    let mut event = CalendarEvent {
+        title,
+        description: field(&mapping.description),
+        location: field(&mapping.location),
+        organization: field(&mapping.organization),
+        purchased: field(&mapping.purchased).is_some_and(|v| mapping::is_truthy(&v)),
         start_date,
         start_time,
         end_date,
         end_time,
-    })
+        timezone: Some(timezone),
+        source: None,
+        source_id: None,
+        recurrence,
+        reminders,
+        attendees,
+    };
+
+    // Prefer an explicit `id` column; otherwise fall back to a hash of title and date
+    event.source_id = Some(field(&mapping.id).unwrap_or_else(|| keys.key(&event)));
+
+    Ok(event)
 }
 
 fn parse_date(s: &str) -> Result<NaiveDate> {
@@ -131,4 +283,155 @@ mod tests {
         assert!(parse_time("14:30:00").is_ok());
         assert!(parse_time("2:30 PM").is_ok());
     }
+
+    #[test]
+    fn test_parse_row_timezone_column() {
+        let headers = CsvHeaders::new(&StringRecord::from(vec!["title", "start_date", "start_time", "timezone"]));
+        let mapping = CsvMapping::default();
+        let default_tz = chrono_tz::America::Los_Angeles;
+
+        let row = StringRecord::from(vec!["Carnegie Hall", "2026-04-02", "20:00", "America/New_York"]);
+        let event = parse_row(&headers, &row, 1, &mapping, default_tz, &mut ContentKeys::default()).unwrap();
+        assert_eq!(event.timezone, Some(chrono_tz::America::New_York));
+
+        let row = StringRecord::from(vec!["Davies Hall", "2026-04-03", "19:30", ""]);
+        let event = parse_row(&headers, &row, 2, &mapping, default_tz, &mut ContentKeys::default()).unwrap();
+        assert_eq!(event.timezone, Some(default_tz));
+
+        let row = StringRecord::from(vec!["Nowhere", "2026-04-04", "19:30", "Mars/Olympus"]);
+        assert!(parse_row(&headers, &row, 3, &mapping, default_tz, &mut ContentKeys::default()).is_err());
+    }
+
+    #[test]
+    fn test_parse_row_recurrence_columns() {
+        let headers = CsvHeaders::new(&StringRecord::from(vec!["title", "start_date", "start_time", "recurrence", "exdates"]));
+        let mapping = CsvMapping::default();
+        let tz = chrono_tz::America::Los_Angeles;
+
+        let row = StringRecord::from(vec!["Rehearsal", "2026-03-03", "19:00", "FREQ=WEEKLY;BYDAY=TU", "2026-03-10"]);
+        let event = parse_row(&headers, &row, 1, &mapping, tz, &mut ContentKeys::default()).unwrap();
+        let recurrence = event.recurrence.unwrap();
+        assert_eq!(recurrence.rule, "FREQ=WEEKLY;BYDAY=TU");
+        assert_eq!(recurrence.exdates, [NaiveDate::from_ymd_opt(2026, 3, 10).unwrap()]);
+
+        let row = StringRecord::from(vec!["Concert", "2026-03-05", "19:30", "", ""]);
+        assert!(parse_row(&headers, &row, 2, &mapping, tz, &mut ContentKeys::default()).unwrap().recurrence.is_none());
+
+        let row = StringRecord::from(vec!["Rehearsal", "2026-03-03", "19:00", "every tuesday", ""]);
+        assert!(parse_row(&headers, &row, 3, &mapping, tz, &mut ContentKeys::default()).is_err());
+    }
+
+    #[test]
+    fn test_parse_row_end_time_past_midnight() {
+        let headers = CsvHeaders::new(&StringRecord::from(vec!["title", "start_date", "start_time", "end_time"]));
+        let mapping = CsvMapping::default();
+        let tz = chrono_tz::America::Los_Angeles;
+
+        let row = StringRecord::from(vec!["Late Show", "2026-03-14", "22:30", "00:45"]);
+        let event = parse_row(&headers, &row, 1, &mapping, tz, &mut ContentKeys::default()).unwrap();
+        assert_eq!(event.end_date, NaiveDate::from_ymd_opt(2026, 3, 15).unwrap());
+
+        let row = StringRecord::from(vec!["Concert", "2026-03-14", "19:30", "22:00"]);
+        let event = parse_row(&headers, &row, 2, &mapping, tz, &mut ContentKeys::default()).unwrap();
+        assert_eq!(event.end_date, event.start_date);
+    }
+
+    #[test]
+    fn test_parse_csv_mapped_semicolon_windows_1252() {
+        let path = std::env::temp_dir().join(format!("calendar_sync_mapped_{}.csv", std::process::id()));
+        // Excel-style export: semicolons, Windows-1252 ("Théâtre"), display-style headers
+        std::fs::write(
+            &path,
+            b"Date;Start;Event;Venue;Org;Bought\r\n\
+              14/03/2026;19:30;Carmen;Th\xE9\xE2tre du Ch\xE2telet;Op\xE9ra de Paris;Yes\r\n\
+              not a date;20:00;Broken;;;\r\n",
+        )
+        .unwrap();
+
+        let mut mapping = CsvMapping {
+            encoding: mapping::CsvEncoding::Windows1252,
+            ..CsvMapping::default()
+        };
+        for (field, header) in [
+            ("start_date", "Date"),
+            ("start_time", "Start"),
+            ("title", "Event"),
+            ("location", "Venue"),
+            ("organization", "Org"),
+            ("purchased", "Bought"),
+        ] {
+            mapping.set(field, header).unwrap();
+        }
+
+        let parsed = parse_csv(&path, "csv:test.csv", &mapping, chrono_tz::Europe::Paris).unwrap();
+        std::fs::remove_file(&path).ok();
+
+        assert_eq!(parsed.events.len(), 1);
+        let event = &parsed.events[0];
+        assert_eq!(event.title, "Carmen");
+        assert_eq!(event.start_date, NaiveDate::from_ymd_opt(2026, 3, 14).unwrap());
+        assert_eq!(event.location.as_deref(), Some("Théâtre du Châtelet"));
+        assert_eq!(event.organization.as_deref(), Some("Opéra de Paris"));
+        assert!(event.purchased);
+        assert_eq!(parsed.diagnostics.len(), 1);
+        assert_eq!(parsed.diagnostics[0].row, 2);
+    }
+
+    #[test]
+    fn test_same_day_rows_get_distinct_fallback_ids() {
+        let path = std::env::temp_dir().join(format!("calendar_sync_double_bill_{}.csv", std::process::id()));
+        std::fs::write(
+            &path,
+            "title,start_date,start_time\nHamlet,2026-03-14,14:00\nHamlet,2026-03-14,19:30\nHamlet,2026-03-15,19:30\n",
+        )
+        .unwrap();
+        let parsed = parse_csv(&path, "csv:season.csv", &CsvMapping::default(), chrono_tz::UTC).unwrap();
+        std::fs::remove_file(&path).unwrap();
+
+        let ids: Vec<&str> = parsed.events.iter().filter_map(|e| e.source_id.as_deref()).collect();
+        let matinee = parsed.events[0].content_key();
+        assert_eq!(ids[..2], [matinee.clone(), format!("{}-2", matinee)]);
+        assert_eq!(ids[2], parsed.events[2].content_key());
+    }
+
+    #[test]
+    fn test_source_name_tells_same_named_files_apart() {
+        let base = std::env::temp_dir().join(format!("calendar-sync-names-{}", std::process::id()));
+        let (a, b) = (base.join("2025").join("season.csv"), base.join("2026").join("season.csv"));
+        for path in [&a, &b] {
+            std::fs::create_dir_all(path.parent().unwrap()).unwrap();
+            std::fs::write(path, "title,start_date\n").unwrap();
+        }
+        let name_a = CsvSource::new(a.clone(), CsvMapping::default()).name();
+        let name_b = CsvSource::new(b.clone(), CsvMapping::default()).name();
+        // The same file reached through a relative-looking path is still the same source
+        let dotted = CsvSource::new(base.join("2026").join("..").join("2025").join("season.csv"), CsvMapping::default());
+        let pinned = CsvMapping { source_name: Some("season".to_string()), ..CsvMapping::default() };
+        let pinned = (CsvSource::new(a, pinned.clone()).name(), CsvSource::new(b, pinned).name());
+        std::fs::remove_dir_all(&base).unwrap();
+
+        assert_ne!(name_a, name_b);
+        assert!(name_a.starts_with("csv:/") && name_a.ends_with("season.csv"), "{}", name_a);
+        assert_eq!(dotted.name(), name_a);
+        assert_eq!(pinned, ("csv:season".to_string(), "csv:season".to_string()));
+    }
+
+    #[test]
+    fn test_missing_required_column() {
+        let headers = CsvHeaders::new(&StringRecord::from(vec!["Event", "Date"]));
+        assert!(headers.check(&CsvMapping::default()).is_err());
+
+        let mut mapping = CsvMapping::default();
+        mapping.set("title", "Event").unwrap();
+        mapping.set("start_date", "date").unwrap();
+        assert!(headers.check(&mapping).is_ok());
+    }
+
+    #[test]
+    fn test_detect_delimiter() {
+        assert_eq!(detect_delimiter("title,start_date\nA,2026-01-01"), b',');
+        assert_eq!(detect_delimiter("title;start_date;location\nA, B;2026-01-01;X"), b';');
+        assert_eq!(detect_delimiter("title\tstart_date"), b'\t');
+        assert_eq!(detect_delimiter("title"), b',');
+    }
 }
@@ -1,7 +1,18 @@
-use chrono::{NaiveDate, NaiveDateTime, NaiveTime};
+use anyhow::{Context, Result};
+use chrono::{DateTime, Duration, LocalResult, NaiveDate, NaiveDateTime, NaiveTime, Offset, TimeZone, Utc};
+use chrono_tz::Tz;
 use serde::{Deserialize, Serialize};
+use std::collections::HashMap;
 use std::fmt;
 
+use crate::recurrence::Recurrence;
+
+/// Time zone used for timed events when neither the row, the CLI nor the source specifies one
+pub const DEFAULT_TIMEZONE: Tz = chrono_tz::America::Los_Angeles;
+
+/// How far past its first occurrence an open-ended filter looks for a recurring event's occurrences
+const RECURRENCE_LOOKAHEAD_DAYS: i64 = 10 * 366;
+
 #[derive(Debug, Clone, Serialize, Deserialize)]
 pub struct CalendarEvent {
     pub title: String,
@@ -13,6 +24,21 @@ pub struct CalendarEvent {
     pub start_time: Option<NaiveTime>,
     pub end_date: NaiveDate,
     pub end_time: Option<NaiveTime>,
+    /// Time zone the start/end times are expressed in (defaults to `DEFAULT_TIMEZONE`)
+    pub timezone: Option<Tz>,
+    /// Name of the source this event was read from (e.g. "coda:<doc>/<table>")
+    pub source: Option<String>,
+    /// Stable identifier of the event within its source (row ID, `id` column or content hash)
+    pub source_id: Option<String>,
+    /// How the event repeats; `None` for a single occurrence
+    #[serde(default)]
+    pub recurrence: Option<Recurrence>,
+    /// Notification overrides; `None` leaves the calendar's default reminders
+    #[serde(default)]
+    pub reminders: Option<Reminders>,
+    /// Guests to invite; when empty, guests already on the calendar event are left alone
+    #[serde(default)]
+    pub attendees: Vec<Attendee>,
 }
 
 impl CalendarEvent {
@@ -30,6 +56,279 @@ impl CalendarEvent {
     pub fn end_datetime(&self) -> NaiveDateTime {
         self.end_date.and_time(self.end_time.unwrap_or(NaiveTime::from_hms_opt(23, 59, 59).unwrap()))
     }
+
+    /// Time zone the start/end times should be interpreted in
+    pub fn timezone(&self) -> Tz {
+        self.timezone.unwrap_or(DEFAULT_TIMEZONE)
+    }
+
+    /// Resolve a timed event's start and end to instants in its time zone. Notes describe
+    /// any DST adjustment made; with `DstPolicy::Reject` a time in a transition is an error.
+    pub fn resolve_times(&self, policy: DstPolicy) -> Result<(DateTime<Tz>, DateTime<Tz>, Vec<String>)> {
+        let tz = self.timezone();
+        let (start, start_note) = resolve_local_datetime(tz, self.start_datetime(), policy)?;
+        let (end, end_note) = resolve_local_datetime(tz, self.end_datetime(), policy)?;
+        let notes = [start_note.map(|n| format!("start {}", n)), end_note.map(|n| format!("end {}", n))];
+        Ok((start, end, notes.into_iter().flatten().collect()))
+    }
+
+    /// Returns true if the event starts within the given dates (inclusive; `None` is open-ended).
+    /// A recurring event counts if any of its occurrences does.
+    pub fn occurs_between(&self, start: Option<NaiveDate>, end: Option<NaiveDate>) -> bool {
+        let window_start = start.unwrap_or(NaiveDate::MIN);
+        let window_end = end.unwrap_or(NaiveDate::MAX);
+        match &self.recurrence {
+            Some(recurrence) => {
+                let end = window_end.min(self.start_date + Duration::days(RECURRENCE_LOOKAHEAD_DAYS));
+                !recurrence.occurrences(self.start_datetime(), self.timezone(), window_start, end).is_empty()
+            }
+            None => self.start_date >= window_start && self.start_date <= window_end,
+        }
+    }
+
+    /// Fallback source ID for rows without an explicit identifier: a stable hash
+    /// (FNV-1a) of the lowercase title and start date
+    pub fn content_key(&self) -> String {
+        stable_hash(&format!("{}\u{1f}{}", self.title.to_lowercase(), self.start_date))
+    }
+}
+
+/// Hands out fallback source IDs that are unique within one source. The first event with a
+/// given title and date gets its plain `content_key`; later ones (a matinee and an evening
+/// show, say) get the key with "-2", "-3", ... appended, in source order.
+#[derive(Debug, Default)]
+pub struct ContentKeys {
+    seen: HashMap<String, usize>,
+}
+
+impl ContentKeys {
+    pub fn key(&mut self, event: &CalendarEvent) -> String {
+        let key = event.content_key();
+        let count = self.seen.entry(key.clone()).or_insert(0);
+        *count += 1;
+        if *count == 1 {
+            return key;
+        }
+        tracing::warn!(
+            "{} ({}) appears more than once; give the rows IDs so reordering them can't mix them up",
+            event.title,
+            event.start_date
+        );
+        format!("{}-{}", key, count)
+    }
+}
+
+/// FNV-1a hash as 16 hex digits; unlike `DefaultHasher`, stable across Rust versions and runs
+pub fn stable_hash(content: &str) -> String {
+    let mut hash: u64 = 0xcbf29ce484222325;
+    for byte in content.bytes() {
+        hash ^= byte as u64;
+        hash = hash.wrapping_mul(0x100000001b3);
+    }
+    format!("{:016x}", hash)
+}
+
+/// What to do with a local time that falls in a DST transition: a spring-forward gap
+/// (e.g. 02:30 on the day clocks jump from 02:00 to 03:00) or a fall-back overlap
+/// (e.g. 01:30 on the day clocks repeat 01:00-02:00).
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq, clap::ValueEnum, Serialize, Deserialize)]
+#[serde(rename_all = "kebab-case")]
+pub enum DstPolicy {
+    /// Move times in a gap forward by the gap length (02:30 -> 03:30); use the first of two overlapping times
+    #[default]
+    ShiftForward,
+    /// Skip the row
+    Reject,
+    /// Use the earlier reading: before the gap (02:30 -> 01:30), or the first overlapping time
+    Earliest,
+    /// Use the later reading: after the gap (02:30 -> 03:30), or the second overlapping time
+    Latest,
+}
+
+/// Interpret a wall-clock time in `tz`, applying `policy` if it falls in a DST gap or overlap.
+/// Returns the chosen instant and, when the policy had to step in, a note describing what it did.
+pub fn resolve_local_datetime(
+    tz: Tz,
+    local: NaiveDateTime,
+    policy: DstPolicy,
+) -> Result<(DateTime<Tz>, Option<String>)> {
+    match tz.from_local_datetime(&local) {
+        LocalResult::Single(dt) => Ok((dt, None)),
+        LocalResult::Ambiguous(earliest, latest) => {
+            let chosen = match policy {
+                DstPolicy::Reject => anyhow::bail!(
+                    "{} occurs twice in {} (clocks fall back)",
+                    local.format("%Y-%m-%d %H:%M"),
+                    tz.name()
+                ),
+                DstPolicy::Latest => latest,
+                DstPolicy::ShiftForward | DstPolicy::Earliest => earliest,
+            };
+            let note = format!(
+                "{} occurs twice in {} (clocks fall back); using {}",
+                local.format("%Y-%m-%d %H:%M"),
+                tz.name(),
+                chosen.format("%H:%M %Z")
+            );
+            Ok((chosen, Some(note)))
+        }
+        LocalResult::None => {
+            // Offsets in force either side of the gap; transitions are never a day apart
+            let before = tz.offset_from_utc_datetime(&(local - Duration::days(1))).fix();
+            let after = tz.offset_from_utc_datetime(&(local + Duration::days(1))).fix();
+            let offset = match policy {
+                DstPolicy::Reject => anyhow::bail!(
+                    "{} does not exist in {} (clocks spring forward)",
+                    local.format("%Y-%m-%d %H:%M"),
+                    tz.name()
+                ),
+                // Reading the wall time with the pre-gap offset lands after the gap, and vice versa
+                DstPolicy::ShiftForward | DstPolicy::Latest => before,
+                DstPolicy::Earliest => after,
+            };
+            let utc = local - Duration::seconds(offset.local_minus_utc() as i64);
+            let chosen = Utc.from_utc_datetime(&utc).with_timezone(&tz);
+            let note = format!(
+                "{} does not exist in {} (clocks spring forward); using {}",
+                local.format("%Y-%m-%d %H:%M"),
+                tz.name(),
+                chosen.format("%H:%M %Z")
+            );
+            Ok((chosen, Some(note)))
+        }
+    }
+}
+
+/// Google Calendar accepts at most this many reminder overrides per event
+const MAX_REMINDERS: usize = 5;
+/// ... each at most four weeks before the event
+const MAX_REMINDER_MINUTES: u32 = 40320;
+
+/// Reminder overrides for an event, written as `popup:120,email:1440` (method and minutes
+/// before the start), or `none` for no reminders at all
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(try_from = "String", into = "String")]
+pub struct Reminders(pub Vec<Reminder>);
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, PartialOrd, Ord)]
+pub struct Reminder {
+    pub method: ReminderMethod,
+    pub minutes: u32,
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, PartialOrd, Ord)]
+pub enum ReminderMethod {
+    Popup,
+    Email,
+}
+
+impl ReminderMethod {
+    /// The name used by the Calendar API and in reminder specs
+    pub fn as_str(self) -> &'static str {
+        match self {
+            ReminderMethod::Popup => "popup",
+            ReminderMethod::Email => "email",
+        }
+    }
+}
+
+impl std::str::FromStr for Reminders {
+    type Err = anyhow::Error;
+
+    fn from_str(s: &str) -> Result<Self> {
+        let s = s.trim();
+        if s.eq_ignore_ascii_case("none") {
+            return Ok(Reminders(Vec::new()));
+        }
+        let reminders = s
+            .split(',')
+            .map(|part| {
+                let (method, minutes) = part
+                    .split_once(':')
+                    .with_context(|| format!("Invalid reminder '{}'. Use METHOD:MINUTES, e.g. popup:120", part.trim()))?;
+                let method = match method.trim().to_lowercase().as_str() {
+                    "popup" => ReminderMethod::Popup,
+                    "email" => ReminderMethod::Email,
+                    other => anyhow::bail!("Unknown reminder method '{}' (expected popup or email)", other),
+                };
+                let minutes: u32 = minutes
+                    .trim()
+                    .parse()
+                    .with_context(|| format!("Invalid reminder minutes '{}'", minutes.trim()))?;
+                anyhow::ensure!(
+                    minutes <= MAX_REMINDER_MINUTES,
+                    "Reminder {} minutes before is more than four weeks",
+                    minutes
+                );
+                Ok(Reminder { method, minutes })
+            })
+            .collect::<Result<Vec<_>>>()?;
+        anyhow::ensure!(
+            reminders.len() <= MAX_REMINDERS,
+            "At most {} reminders per event are allowed",
+            MAX_REMINDERS
+        );
+        Ok(Reminders(reminders))
+    }
+}
+
+impl fmt::Display for Reminders {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        if self.0.is_empty() {
+            return write!(f, "none");
+        }
+        let parts: Vec<String> = self.0.iter().map(|r| format!("{}:{}", r.method.as_str(), r.minutes)).collect();
+        write!(f, "{}", parts.join(","))
+    }
+}
+
+impl TryFrom<String> for Reminders {
+    type Error = anyhow::Error;
+
+    fn try_from(s: String) -> Result<Self> {
+        s.parse()
+    }
+}
+
+impl From<Reminders> for String {
+    fn from(reminders: Reminders) -> Self {
+        reminders.to_string()
+    }
+}
+
+/// A guest to invite to an event
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+pub struct Attendee {
+    pub email: String,
+    pub optional: bool,
+}
+
+/// Parse an attendees column: comma- or semicolon-separated email addresses, each optionally
+/// prefixed with `optional:`, e.g. `alex@example.com, optional:sam@example.com`
+pub fn parse_attendees(s: &str) -> Result<Vec<Attendee>> {
+    s.split([',', ';'])
+        .map(str::trim)
+        .filter(|part| !part.is_empty())
+        .map(|part| {
+            let (email, optional) = match part.get(..9).filter(|p| p.eq_ignore_ascii_case("optional:")) {
+                Some(_) => (part[9..].trim(), true),
+                None => (part, false),
+            };
+            let email = email.strip_prefix("mailto:").unwrap_or(email);
+            let valid = email
+                .split_once('@')
+                .is_some_and(|(user, domain)| !user.is_empty() && domain.contains('.') && !email.contains(' '));
+            anyhow::ensure!(valid, "Invalid attendee email '{}'", email);
+            Ok(Attendee { email: email.to_string(), optional })
+        })
+        .collect()
+}
+
+/// Parse an IANA time zone name such as "America/New_York" or "Europe/Berlin"
+pub fn parse_timezone(s: &str) -> Result<Tz> {
+    s.trim()
+        .parse()
+        .map_err(|_| anyhow::anyhow!("Unknown time zone '{}'. Use an IANA name like 'America/New_York'", s))
 }
 
 impl fmt::Display for CalendarEvent {
@@ -49,3 +348,122 @@ impl fmt::Display for CalendarEvent {
         }
     }
 }
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use chrono_tz::{America, Australia, Europe};
+
+    fn local(s: &str) -> NaiveDateTime {
+        NaiveDateTime::parse_from_str(s, "%Y-%m-%d %H:%M").unwrap()
+    }
+
+    fn resolve(tz: Tz, s: &str, policy: DstPolicy) -> Option<String> {
+        resolve_local_datetime(tz, local(s), policy)
+            .ok()
+            .map(|(dt, _)| dt.format("%Y-%m-%d %H:%M %z").to_string())
+    }
+
+    #[test]
+    fn test_resolve_regular_time_has_no_note() {
+        let (dt, note) =
+            resolve_local_datetime(America::Los_Angeles, local("2026-07-04 19:30"), DstPolicy::Reject).unwrap();
+        assert_eq!(dt.format("%H:%M %z").to_string(), "19:30 -0700");
+        assert!(note.is_none());
+    }
+
+    #[test]
+    fn test_resolve_spring_forward_gap() {
+        let cases = [
+            (America::Los_Angeles, "2026-03-08 02:30", "2026-03-08 03:30 -0700", "2026-03-08 01:30 -0800"),
+            (America::New_York, "2026-03-08 02:15", "2026-03-08 03:15 -0400", "2026-03-08 01:15 -0500"),
+            (Europe::Berlin, "2026-03-29 02:30", "2026-03-29 03:30 +0200", "2026-03-29 01:30 +0100"),
+            (Australia::Sydney, "2026-10-04 02:30", "2026-10-04 03:30 +1100", "2026-10-04 01:30 +1000"),
+        ];
+        for (tz, time, forward, backward) in cases {
+            assert_eq!(resolve(tz, time, DstPolicy::ShiftForward).as_deref(), Some(forward), "{}", tz);
+            assert_eq!(resolve(tz, time, DstPolicy::Latest).as_deref(), Some(forward), "{}", tz);
+            assert_eq!(resolve(tz, time, DstPolicy::Earliest).as_deref(), Some(backward), "{}", tz);
+            assert_eq!(resolve(tz, time, DstPolicy::Reject), None, "{}", tz);
+        }
+    }
+
+    #[test]
+    fn test_resolve_fall_back_overlap() {
+        let cases = [
+            (America::Los_Angeles, "2026-11-01 01:30", "2026-11-01 01:30 -0700", "2026-11-01 01:30 -0800"),
+            (Europe::Berlin, "2026-10-25 02:30", "2026-10-25 02:30 +0200", "2026-10-25 02:30 +0100"),
+            (Australia::Sydney, "2026-04-05 02:30", "2026-04-05 02:30 +1100", "2026-04-05 02:30 +1000"),
+        ];
+        for (tz, time, first, second) in cases {
+            assert_eq!(resolve(tz, time, DstPolicy::ShiftForward).as_deref(), Some(first), "{}", tz);
+            assert_eq!(resolve(tz, time, DstPolicy::Earliest).as_deref(), Some(first), "{}", tz);
+            assert_eq!(resolve(tz, time, DstPolicy::Latest).as_deref(), Some(second), "{}", tz);
+            assert_eq!(resolve(tz, time, DstPolicy::Reject), None, "{}", tz);
+        }
+    }
+
+    #[test]
+    fn test_resolve_times_reports_each_end() {
+        let date = NaiveDate::from_ymd_opt(2026, 3, 8).unwrap();
+        let event = CalendarEvent {
+            title: "Late show".to_string(),
+            description: None,
+            location: None,
+            organization: None,
+            purchased: false,
+            start_date: date,
+            start_time: NaiveTime::from_hms_opt(1, 0, 0),
+            end_date: date,
+            end_time: NaiveTime::from_hms_opt(2, 30, 0),
+            timezone: Some(America::Los_Angeles),
+            source: None,
+            source_id: None,
+            recurrence: None,
+            reminders: None,
+            attendees: Vec::new(),
+        };
+
+        let (start, end, notes) = event.resolve_times(DstPolicy::ShiftForward).unwrap();
+        assert_eq!(start.format("%H:%M").to_string(), "01:00");
+        assert_eq!(end.format("%H:%M").to_string(), "03:30");
+        assert_eq!(notes.len(), 1);
+        assert!(notes[0].starts_with("end "));
+
+        assert!(event.resolve_times(DstPolicy::Reject).is_err());
+    }
+
+    #[test]
+    fn test_parse_reminders() {
+        let reminders: Reminders = " Popup:120, email:1440".parse().unwrap();
+        assert_eq!(
+            reminders.0,
+            [
+                Reminder { method: ReminderMethod::Popup, minutes: 120 },
+                Reminder { method: ReminderMethod::Email, minutes: 1440 },
+            ]
+        );
+        assert_eq!(reminders.to_string(), "popup:120,email:1440");
+        assert_eq!("none".parse::<Reminders>().unwrap(), Reminders(Vec::new()));
+
+        assert!("sms:10".parse::<Reminders>().is_err());
+        assert!("popup".parse::<Reminders>().is_err());
+        assert!("popup:50000".parse::<Reminders>().is_err());
+        assert!("popup:1,popup:2,popup:3,popup:4,popup:5,popup:6".parse::<Reminders>().is_err());
+    }
+
+    #[test]
+    fn test_parse_attendees() {
+        let attendees = parse_attendees("alex@example.com; Optional: sam@example.org,,").unwrap();
+        assert_eq!(
+            attendees,
+            [
+                Attendee { email: "alex@example.com".to_string(), optional: false },
+                Attendee { email: "sam@example.org".to_string(), optional: true },
+            ]
+        );
+        assert!(parse_attendees("").unwrap().is_empty());
+        assert!(parse_attendees("alex").is_err());
+        assert!(parse_attendees("optional:").is_err());
+    }
+}
@@ -0,0 +1,175 @@
+//! The run history: past runs as recorded by their journals in `journal::runs_dir()`, and
+//! undoing them.
+//!
+//! Undo deletes the events a run created and re-creates the events it deleted, from the
+//! copies its journal kept. Re-created events get new IDs. Updates are not reverted.
+
+use chrono::{Local, Utc};
+
+use crate::backend::CalendarBackend;
+use crate::calendar::{self, EventFailure};
+use crate::journal::{Action, Journal};
+use crate::pipeline::truncate;
+
+/// Print runs as a table, one line per run plus its source
+pub fn print_runs(runs: &[Journal]) {
+    if runs.is_empty() {
+        println!("\nNo runs recorded yet\n");
+        return;
+    }
+    println!("\n{:<18} {:<17} {:<7} {:<26} {:>7} {:>7} {:>7} {:>6}  UNDO",
+        "RUN", "STARTED", "COMMAND", "CALENDAR", "CREATED", "UPDATED", "DELETED", "FAILED");
+    println!("{}", "-".repeat(110));
+    for run in runs {
+        let tally = run.tally();
+        let undone = run.operations.iter().filter(|op| op.undone).count();
+        let undoable = run.operations.iter().filter(|op| op.is_undoable()).count();
+        let undo = match (undone, undoable) {
+            (0, _) => "",
+            (_, 0) => "undone",
+            _ => "partly undone",
+        };
+        println!("{:<18} {:<17} {:<7} {:<26} {:>7} {:>7} {:>7} {:>6}  {}",
+            run.id,
+            run.started_at.with_timezone(&Local).format("%Y-%m-%d %H:%M"),
+            run.command,
+            truncate(&run.calendar_id, 26),
+            tally.create[0],
+            tally.update[0],
+            tally.delete[0],
+            tally.failed(),
+            undo,
+        );
+        println!("  {}", run.source);
+    }
+    println!();
+}
+
+/// Print what `undo` would do to a run
+pub fn print_undo_plan(run: &Journal) {
+    println!("\nUndo run {} ({} from {} into calendar '{}', {}):",
+        run.id,
+        run.command,
+        run.source,
+        run.calendar_id,
+        run.started_at.with_timezone(&Local).format("%Y-%m-%d %H:%M"),
+    );
+    println!("{}", "=".repeat(80));
+    println!("{:<10} {:<40} {:<12}", "ACTION", "TITLE", "DATE");
+    println!("{}", "-".repeat(80));
+    for operation in run.operations.iter().filter(|op| op.is_undoable()) {
+        let action = match operation.action {
+            Action::Create => "delete",
+            _ => "re-create",
+        };
+        println!("{:<10} {:<40} {:<12}", action, truncate(&operation.title, 38), operation.date);
+    }
+    let updated = run.tally().update[0];
+    if updated > 0 {
+        println!("\n({} events this run updated are left as they are)", updated);
+    }
+    println!();
+}
+
+/// Reverse every operation of `run` that is done and not undone yet, marking each one that
+/// succeeds. Returns how many were undone and the failures; failed ones can be retried by
+/// undoing the run again.
+pub async fn undo(backend: &dyn CalendarBackend, run: &mut Journal, jobs: usize) -> (usize, Vec<EventFailure>) {
+    let calendar_id = run.calendar_id.clone();
+    let (created, deleted): (Vec<usize>, Vec<usize>) = (0..run.operations.len())
+        .filter(|&i| run.operations[i].is_undoable())
+        .partition(|&i| run.operations[i].action == Action::Create);
+    let mut undone = 0;
+    let mut failures = Vec::new();
+
+    let event_ids: Vec<String> = created
+        .iter()
+        .map(|&i| run.operations[i].event_id.clone().unwrap_or_default())
+        .collect();
+    let results = calendar::delete_events(backend, &calendar_id, &event_ids, jobs).await;
+    for (&i, result) in created.iter().zip(results) {
+        match result {
+            Ok(()) => {
+                run.operations[i].undone = true;
+                undone += 1;
+            }
+            Err(error) => failures.push(EventFailure { title: run.operations[i].title.clone(), error }),
+        }
+    }
+
+    let titles: Vec<String> = deleted.iter().map(|&i| run.operations[i].title.clone()).collect();
+    let titles: Vec<&str> = titles.iter().map(String::as_str).collect();
+    let events = deleted
+        .iter()
+        .map(|&i| Ok(calendar::reinsertable(run.operations[i].event.as_ref().expect("undoable deletes keep the event"))))
+        .collect();
+    let results = calendar::insert_events(backend, &calendar_id, &titles, events, jobs).await;
+    for (&i, result) in deleted.iter().zip(results) {
+        match result {
+            Ok(_) => {
+                run.operations[i].undone = true;
+                undone += 1;
+            }
+            Err(error) => failures.push(EventFailure { title: run.operations[i].title.clone(), error }),
+        }
+    }
+
+    if undone > 0 {
+        run.undone_at = Some(Utc::now());
+    }
+    (undone, failures)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::backend::{concert, listed_event, MemoryCalendar};
+    use crate::journal::Operation;
+    use crate::sync;
+    use google_calendar3::api::Event;
+    use std::path::Path;
+
+    #[tokio::test]
+    async fn test_undo_deletes_created_and_recreates_deleted() {
+        let (mahler, brahms) = (concert("Mahler 9", 14, None), concert("Brahms 4", 15, None));
+        let created = listed_event("evt-new", &mahler);
+        let stuck = listed_event("evt-stuck", &brahms);
+        let gone = Event {
+            etag: Some("\"3\"".to_string()),
+            location: Some("Herbst Theatre".to_string()),
+            ..listed_event("evt-gone", &concert("Piano Recital", 16, None))
+        };
+        let backend = MemoryCalendar::new()
+            .with_event("primary", created.clone())
+            .with_event("primary", stuck.clone())
+            .failing_on("evt-stuck");
+
+        let mut run = Journal::start("sync", "csv:season.csv", "primary", Some(Path::new("run.json")));
+        run.plan(Operation::create(&mahler));
+        run.plan(Operation::delete(&sync::to_found(&gone)));
+        run.plan(Operation::create(&brahms));
+        run.record(0, Ok(Some("evt-new".to_string())));
+        run.record(1, Ok(None));
+        run.record(2, Ok(Some("evt-stuck".to_string())));
+
+        let (undone, failures) = undo(&backend, &mut run, 2).await;
+        assert_eq!(undone, 2);
+        let failed: Vec<&str> = failures.iter().map(|f| f.title.as_str()).collect();
+        assert_eq!(failed, ["Brahms 4"]);
+        assert!(run.undone_at.is_some());
+        // Undoing again would only retry the one that failed
+        let undoable: Vec<bool> = run.operations.iter().map(Operation::is_undoable).collect();
+        assert_eq!(undoable, [false, false, true]);
+
+        let mut titles: Vec<String> = backend.events("primary").into_iter().filter_map(|e| e.summary).collect();
+        titles.sort();
+        assert_eq!(titles, ["Brahms 4", "Piano Recital"]);
+        let restored = backend
+            .events("primary")
+            .into_iter()
+            .find(|e| e.summary.as_deref() == Some("Piano Recital"))
+            .unwrap();
+        assert_ne!(restored.id.as_deref(), Some("evt-gone"));
+        assert_eq!((restored.location.as_deref(), restored.etag), (Some("Herbst Theatre"), None));
+    }
+}
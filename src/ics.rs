@@ -0,0 +1,363 @@
+use anyhow::{Context, Result};
+use chrono::{DateTime, Datelike, Duration, NaiveDate, NaiveDateTime, Offset, TimeZone, Utc};
+use chrono_tz::{OffsetComponents, OffsetName, Tz};
+use std::collections::BTreeMap;
+use std::path::Path;
+
+use crate::event::{stable_hash, CalendarEvent, DstPolicy, ReminderMethod};
+
+const PRODID: &str = "-//calendar-sync//calendar-sync//EN";
+const UID_DOMAIN: &str = "calendar-sync";
+/// RFC 5545 content lines are folded at 75 octets
+const MAX_LINE_OCTETS: usize = 75;
+/// VTIMEZONEs cover repeating events for at most this many years past their first occurrence
+const MAX_RECURRENCE_YEARS: i32 = 10;
+
+/// Write events to an iCalendar file
+pub fn write_ics(path: &Path, events: &[CalendarEvent], policy: DstPolicy) -> Result<()> {
+    let calendar = render_calendar(events, policy, Utc::now())?;
+    std::fs::write(path, calendar)
+        .with_context(|| format!("Failed to write iCalendar file: {}", path.display()))
+}
+
+/// Render events as an RFC 5545 VCALENDAR with a VTIMEZONE for every time zone used.
+/// `dtstamp` is the creation time stamped on every event.
+pub fn render_calendar(events: &[CalendarEvent], policy: DstPolicy, dtstamp: DateTime<Utc>) -> Result<String> {
+    let mut lines = vec![
+        "BEGIN:VCALENDAR".to_string(),
+        "VERSION:2.0".to_string(),
+        format!("PRODID:{}", PRODID),
+        "CALSCALE:GREGORIAN".to_string(),
+        "METHOD:PUBLISH".to_string(),
+    ];
+
+    // Each VTIMEZONE only needs to cover the years its events fall in
+    let mut zones: BTreeMap<&str, (Tz, i32, i32)> = BTreeMap::new();
+    for event in events.iter().filter(|e| !e.is_all_day()) {
+        let tz = event.timezone();
+        let last_year = last_occurrence_year(event);
+        let span = zones.entry(tz.name()).or_insert((tz, event.start_date.year(), last_year));
+        span.1 = span.1.min(event.start_date.year());
+        span.2 = span.2.max(last_year);
+    }
+    for (tz, first_year, last_year) in zones.into_values() {
+        lines.extend(vtimezone(tz, first_year, last_year));
+    }
+
+    for event in events {
+        lines.extend(vevent(event, policy, dtstamp)?);
+    }
+    lines.push("END:VCALENDAR".to_string());
+
+    Ok(lines.iter().map(|line| fold_line(line)).collect())
+}
+
+fn vevent(event: &CalendarEvent, policy: DstPolicy, dtstamp: DateTime<Utc>) -> Result<Vec<String>> {
+    let mut lines = vec![
+        "BEGIN:VEVENT".to_string(),
+        format!("UID:{}", uid(event)),
+        format!("DTSTAMP:{}", dtstamp.format("%Y%m%dT%H%M%SZ")),
+    ];
+
+    if event.is_all_day() {
+        // DTEND is exclusive, as for Google Calendar's all-day end date
+        let end_date = event.end_date.succ_opt().unwrap_or(event.end_date);
+        lines.push(format!("DTSTART;VALUE=DATE:{}", format_date(event.start_date)));
+        lines.push(format!("DTEND;VALUE=DATE:{}", format_date(end_date)));
+    } else {
+        let tz = event.timezone();
+        let (start, end, _) = event.resolve_times(policy)?;
+        lines.push(format!("DTSTART;TZID={}:{}", tz.name(), format_local(start.naive_local())));
+        lines.push(format!("DTEND;TZID={}:{}", tz.name(), format_local(end.naive_local())));
+    }
+
+    if let Some(recurrence) = &event.recurrence {
+        lines.extend(recurrence.to_lines(event.start_time, event.timezone()));
+    }
+
+    lines.push(format!("SUMMARY:{}", escape_text(&event.title)));
+    if let Some(location) = &event.location {
+        lines.push(format!("LOCATION:{}", escape_text(location)));
+    }
+    if let Some(description) = &event.description {
+        lines.push(format!("DESCRIPTION:{}", escape_text(description)));
+    }
+    // Email alarms need an ATTENDEE to send to, so only popups carry over
+    let popups = event.reminders.iter().flat_map(|r| &r.0).filter(|r| r.method == ReminderMethod::Popup);
+    for reminder in popups {
+        lines.extend([
+            "BEGIN:VALARM".to_string(),
+            "ACTION:DISPLAY".to_string(),
+            format!("DESCRIPTION:{}", escape_text(&event.title)),
+            format!("TRIGGER:-PT{}M", reminder.minutes),
+            "END:VALARM".to_string(),
+        ]);
+    }
+    lines.push("END:VEVENT".to_string());
+    Ok(lines)
+}
+
+/// Year of the event's last occurrence, looking at most `MAX_RECURRENCE_YEARS` ahead for
+/// repeating events
+fn last_occurrence_year(event: &CalendarEvent) -> i32 {
+    let Some(recurrence) = &event.recurrence else {
+        return event.end_date.year();
+    };
+    let horizon = NaiveDate::from_ymd_opt(event.start_date.year() + MAX_RECURRENCE_YEARS, 12, 31).unwrap_or(NaiveDate::MAX);
+    let last = recurrence
+        .occurrences(event.start_datetime(), event.timezone(), event.start_date, horizon)
+        .last()
+        .map_or(event.start_date, |at| at.date());
+    (last + (event.end_date - event.start_date)).year()
+}
+
+/// Stable across exports, so calendar apps update re-imported events instead of duplicating them
+fn uid(event: &CalendarEvent) -> String {
+    let key = match (&event.source, &event.source_id) {
+        (Some(source), Some(source_id)) => stable_hash(&format!("{}\u{1f}{}", source, source_id)),
+        _ => event.content_key(),
+    };
+    format!("{}@{}", key, UID_DOMAIN)
+}
+
+/// A VTIMEZONE with one observance for the offset in force at local midnight on 1 January
+/// of `first_year`, plus one per transition up to the end of `last_year`
+fn vtimezone(tz: Tz, first_year: i32, last_year: i32) -> Vec<String> {
+    let start = local_year_start(tz, first_year);
+    let end = local_year_start(tz, last_year + 1);
+
+    let mut lines = vec!["BEGIN:VTIMEZONE".to_string(), format!("TZID:{}", tz.name())];
+    lines.extend(observance(tz, start, start));
+
+    let mut day = start;
+    while day < end {
+        let next = day + Duration::days(1);
+        if offset_key(tz, day) != offset_key(tz, next) {
+            lines.extend(observance(tz, find_transition(tz, day, next), day));
+        }
+        day = next;
+    }
+
+    lines.push("END:VTIMEZONE".to_string());
+    lines
+}
+
+/// A STANDARD or DAYLIGHT block for the offset starting at `at`, replacing the one at `before`
+fn observance(tz: Tz, at: DateTime<Utc>, before: DateTime<Utc>) -> Vec<String> {
+    let from = tz.offset_from_utc_datetime(&before.naive_utc());
+    let to = tz.offset_from_utc_datetime(&at.naive_utc());
+    let kind = if to.dst_offset().is_zero() { "STANDARD" } else { "DAYLIGHT" };
+    // Observance onsets are written in the local time of the offset being replaced
+    let onset = at.naive_utc() + Duration::seconds(from.fix().local_minus_utc() as i64);
+
+    vec![
+        format!("BEGIN:{}", kind),
+        format!("DTSTART:{}", format_local(onset)),
+        format!("TZOFFSETFROM:{}", format_offset(from.fix().local_minus_utc())),
+        format!("TZOFFSETTO:{}", format_offset(to.fix().local_minus_utc())),
+        format!("TZNAME:{}", to.abbreviation()),
+        format!("END:{}", kind),
+    ]
+}
+
+/// Total offset and abbreviation, which together identify an observance
+fn offset_key(tz: Tz, at: DateTime<Utc>) -> (i32, String) {
+    let offset = tz.offset_from_utc_datetime(&at.naive_utc());
+    (offset.fix().local_minus_utc(), offset.abbreviation().to_string())
+}
+
+/// The first whole minute in `(low, high]` whose offset differs from the one at `low`
+fn find_transition(tz: Tz, low: DateTime<Utc>, high: DateTime<Utc>) -> DateTime<Utc> {
+    let before = offset_key(tz, low);
+    let (mut lo, mut hi) = (0, (high - low).num_minutes());
+    while hi - lo > 1 {
+        let mid = (lo + hi) / 2;
+        if offset_key(tz, low + Duration::minutes(mid)) == before {
+            lo = mid;
+        } else {
+            hi = mid;
+        }
+    }
+    low + Duration::minutes(hi)
+}
+
+/// The instant the year begins in `tz`, so observance onsets fall in the right local year
+fn local_year_start(tz: Tz, year: i32) -> DateTime<Utc> {
+    let midnight = NaiveDate::from_ymd_opt(year, 1, 1).unwrap().and_hms_opt(0, 0, 0).unwrap();
+    match tz.from_local_datetime(&midnight).earliest() {
+        Some(start) => start.with_timezone(&Utc),
+        // A transition skipped local midnight that year; fall back to midnight UTC
+        None => Utc.from_utc_datetime(&midnight),
+    }
+}
+
+fn format_date(date: NaiveDate) -> String {
+    date.format("%Y%m%d").to_string()
+}
+
+fn format_local(datetime: NaiveDateTime) -> String {
+    datetime.format("%Y%m%dT%H%M%S").to_string()
+}
+
+/// UTC offset as +HHMM, or +HHMMSS for the odd historical offset with seconds
+fn format_offset(seconds: i32) -> String {
+    let sign = if seconds < 0 { '-' } else { '+' };
+    let seconds = seconds.abs();
+    let (hours, minutes, secs) = (seconds / 3600, seconds / 60 % 60, seconds % 60);
+    if secs == 0 {
+        format!("{}{:02}{:02}", sign, hours, minutes)
+    } else {
+        format!("{}{:02}{:02}{:02}", sign, hours, minutes, secs)
+    }
+}
+
+/// Escape a TEXT value (RFC 5545 section 3.3.11)
+fn escape_text(text: &str) -> String {
+    let mut escaped = String::with_capacity(text.len());
+    for c in text.chars() {
+        match c {
+            '\\' => escaped.push_str("\\\\"),
+            ';' => escaped.push_str("\\;"),
+            ',' => escaped.push_str("\\,"),
+            '\n' => escaped.push_str("\\n"),
+            '\r' => {}
+            _ => escaped.push(c),
+        }
+    }
+    escaped
+}
+
+/// Fold a content line into CRLF-terminated chunks of at most 75 octets, continuation lines
+/// starting with a space, never splitting a UTF-8 character
+fn fold_line(line: &str) -> String {
+    let mut folded = String::with_capacity(line.len() + 8);
+    let mut octets = 0;
+    for c in line.chars() {
+        if octets + c.len_utf8() > MAX_LINE_OCTETS {
+            folded.push_str("\r\n ");
+            octets = 1;
+        }
+        folded.push(c);
+        octets += c.len_utf8();
+    }
+    folded.push_str("\r\n");
+    folded
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use chrono::NaiveTime;
+
+    fn event(title: &str, date: (i32, u32, u32), times: Option<((u32, u32), (u32, u32))>, tz: Tz) -> CalendarEvent {
+        let date = NaiveDate::from_ymd_opt(date.0, date.1, date.2).unwrap();
+        CalendarEvent {
+            title: title.to_string(),
+            description: None,
+            location: None,
+            organization: None,
+            purchased: false,
+            start_date: date,
+            start_time: times.map(|((h, m), _)| NaiveTime::from_hms_opt(h, m, 0).unwrap()),
+            end_date: date,
+            end_time: times.map(|(_, (h, m))| NaiveTime::from_hms_opt(h, m, 0).unwrap()),
+            timezone: Some(tz),
+            source: Some("csv:season.csv".to_string()),
+            source_id: Some(title.to_lowercase().replace(' ', "-")),
+            recurrence: None,
+            reminders: None,
+            attendees: Vec::new(),
+        }
+    }
+
+    #[test]
+    fn test_render_calendar_golden() {
+        let mut mahler = event("Mahler 9", (2026, 3, 14), Some(((19, 30), (22, 0))), chrono_tz::America::Los_Angeles);
+        mahler.location = Some("Davies Symphony Hall, San Francisco".to_string());
+        mahler.description = Some(
+            "Program: Mahler; Symphony No. 9 in D major\nConductor: Esa-Pekka Salonen \\ SF Symphony"
+                .to_string(),
+        );
+        let mut festival = event("Ojai Festival", (2026, 6, 4), None, chrono_tz::America::Los_Angeles);
+        festival.end_date = NaiveDate::from_ymd_opt(2026, 6, 7).unwrap();
+        let mut berlin = event("Philharmonie", (2026, 10, 25), Some(((20, 0), (22, 0))), chrono_tz::Europe::Berlin);
+        berlin.location = Some("Philharmonie Berlin, Herbert-von-Karajan-Straße 1".to_string());
+
+        let dtstamp = Utc.with_ymd_and_hms(2026, 1, 2, 3, 4, 5).unwrap();
+        let rendered = render_calendar(&[mahler, festival, berlin], DstPolicy::default(), dtstamp).unwrap();
+
+        assert_eq!(rendered, include_str!("../testdata/export.ics"));
+    }
+
+    #[test]
+    fn test_recurring_event_with_alarm() {
+        let mut rehearsal = event("Rehearsal", (2026, 3, 3), Some(((18, 0), (20, 0))), chrono_tz::Europe::Berlin);
+        rehearsal.recurrence =
+            Some(crate::recurrence::Recurrence::parse("FREQ=MONTHLY;COUNT=14", Some("2026-04-03")).unwrap());
+        rehearsal.reminders = Some("email:1440,popup:30".parse().unwrap());
+
+        let dtstamp = Utc.with_ymd_and_hms(2026, 1, 2, 3, 4, 5).unwrap();
+        let rendered = render_calendar(&[rehearsal], DstPolicy::default(), dtstamp).unwrap();
+        assert!(rendered.contains("\r\nRRULE:FREQ=MONTHLY;COUNT=14\r\n"));
+        assert!(rendered.contains("\r\nEXDATE;TZID=Europe/Berlin:20260403T180000\r\n"));
+        assert!(rendered.contains("\r\nTRIGGER:-PT30M\r\n"));
+        assert_eq!(rendered.matches("BEGIN:VALARM").count(), 1);
+        // The last occurrence is in April 2027, so the time zone covers that year's transitions
+        assert!(rendered.contains("DTSTART:20270328T020000"));
+    }
+
+    #[test]
+    fn test_uid_is_stable_and_source_scoped() {
+        let a = event("Mahler 9", (2026, 3, 14), None, chrono_tz::UTC);
+        let mut renamed = a.clone();
+        renamed.title = "Mahler 9 (rescheduled)".to_string();
+        assert_eq!(uid(&a), uid(&renamed));
+
+        let mut other_source = a.clone();
+        other_source.source = Some("csv:other.csv".to_string());
+        assert_ne!(uid(&a), uid(&other_source));
+    }
+
+    #[test]
+    fn test_vtimezone_without_transitions() {
+        let lines = vtimezone(chrono_tz::Asia::Tokyo, 2026, 2026);
+        assert_eq!(
+            lines,
+            [
+                "BEGIN:VTIMEZONE", "TZID:Asia/Tokyo",
+                "BEGIN:STANDARD", "DTSTART:20260101T000000", "TZOFFSETFROM:+0900", "TZOFFSETTO:+0900",
+                "TZNAME:JST", "END:STANDARD",
+                "END:VTIMEZONE",
+            ]
+        );
+    }
+
+    #[test]
+    fn test_vtimezone_starts_at_local_midnight_east_of_utc() {
+        // Sydney is on daylight time (+11:00) over New Year; the first onset must be in 2026,
+        // not 13:00 on 31 December 2025 or 11:00 on 1 January
+        let lines = vtimezone(chrono_tz::Australia::Sydney, 2026, 2026);
+        assert_eq!(
+            lines[2..8],
+            [
+                "BEGIN:DAYLIGHT", "DTSTART:20260101T000000", "TZOFFSETFROM:+1100", "TZOFFSETTO:+1100",
+                "TZNAME:AEDT", "END:DAYLIGHT",
+            ]
+        );
+        assert!(lines.contains(&"DTSTART:20260405T030000".to_string()));
+        assert!(lines.contains(&"DTSTART:20261004T020000".to_string()));
+    }
+
+    #[test]
+    fn test_escape_and_fold() {
+        assert_eq!(escape_text("a,b;c\\d\r\ne"), "a\\,b\\;c\\\\d\\ne");
+
+        // 12 + 80 octets: folds once, without splitting a two-byte character
+        let line = format!("DESCRIPTION:{}", "é".repeat(40));
+        let folded = fold_line(&line);
+        let lines: Vec<&str> = folded.trim_end_matches("\r\n").split("\r\n").collect();
+        assert_eq!(lines.len(), 2);
+        assert!(lines.iter().all(|l| l.len() <= MAX_LINE_OCTETS));
+        assert_eq!(folded.replace("\r\n ", ""), format!("{}\r\n", line));
+    }
+}
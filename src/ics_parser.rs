@@ -0,0 +1,603 @@
+use anyhow::{Context, Result};
+use async_trait::async_trait;
+use chrono::{DateTime, Duration, NaiveDate, NaiveDateTime, NaiveTime, TimeZone, Utc};
+use chrono_tz::Tz;
+use std::collections::{HashMap, HashSet};
+use std::path::PathBuf;
+
+use crate::event::{parse_timezone, CalendarEvent, ContentKeys};
+use crate::recurrence::RRule;
+use crate::source::{self, EventSource, FetchOptions, RowDiagnostic, SourceEvents};
+
+/// How far past its first occurrence (or `--start-date`) an endless rule is expanded when
+/// no `--end-date` is given
+const UNBOUNDED_EXPANSION_DAYS: i64 = 365;
+
+/// Events read from an iCalendar (.ics) file
+pub struct IcsSource {
+    path: PathBuf,
+}
+
+impl IcsSource {
+    pub fn new(path: PathBuf) -> Self {
+        Self { path }
+    }
+}
+
+#[async_trait]
+impl EventSource for IcsSource {
+    /// e.g. "ics:/home/sam/venue.ics"
+    fn name(&self) -> String {
+        format!("ics:{}", source::file_identity(&self.path))
+    }
+
+    async fn fetch(&self, options: &FetchOptions) -> Result<SourceEvents> {
+        let text = std::fs::read_to_string(&self.path)
+            .with_context(|| format!("Failed to open iCalendar file: {}", self.path.display()))?;
+        parse_ics(&text, &self.name(), options)
+    }
+}
+
+/// One content line: `NAME;PARAM=value:VALUE`
+#[derive(Debug, Clone)]
+struct Property {
+    name: String,
+    params: Vec<(String, String)>,
+    value: String,
+}
+
+impl Property {
+    fn param(&self, name: &str) -> Option<&str> {
+        self.params.iter().find(|(k, _)| k == name).map(|(_, v)| v.as_str())
+    }
+}
+
+/// The properties of one VEVENT (nested components such as VALARM are skipped)
+#[derive(Debug, Default)]
+struct VEvent {
+    properties: Vec<Property>,
+}
+
+impl VEvent {
+    fn get(&self, name: &str) -> Option<&Property> {
+        self.properties.iter().find(|p| p.name == name)
+    }
+
+    fn all<'a>(&'a self, name: &'a str) -> impl Iterator<Item = &'a Property> {
+        self.properties.iter().filter(move |p| p.name == name)
+    }
+
+    fn text(&self, name: &str) -> Option<String> {
+        self.get(name).map(|p| unescape_text(&p.value)).filter(|s| !s.trim().is_empty())
+    }
+}
+
+/// A DTSTART/DTEND-style value
+#[derive(Debug, Clone, Copy, PartialEq)]
+enum IcsTime {
+    Date(NaiveDate),
+    /// Wall-clock time in a zone (TZID, or the calendar default for floating times)
+    Local(NaiveDateTime, Tz),
+    Utc(DateTime<Utc>),
+}
+
+impl IcsTime {
+    fn parse(property: &Property, default_tz: Tz) -> Result<Self> {
+        parse_time_value(&property.value, property.param("VALUE"), property.param("TZID"), default_tz)
+    }
+
+    /// Wall-clock time in `tz` (all-day values are midnight)
+    fn local_in(&self, tz: Tz) -> NaiveDateTime {
+        match *self {
+            IcsTime::Date(d) => d.and_time(NaiveTime::MIN),
+            IcsTime::Local(dt, from) if from == tz => dt,
+            IcsTime::Local(dt, from) => from
+                .from_local_datetime(&dt)
+                .earliest()
+                .map(|t| t.with_timezone(&tz).naive_local())
+                .unwrap_or(dt),
+            IcsTime::Utc(t) => t.with_timezone(&tz).naive_local(),
+        }
+    }
+}
+
+fn parse_time_value(value: &str, value_type: Option<&str>, tzid: Option<&str>, default_tz: Tz) -> Result<IcsTime> {
+    let value = value.trim();
+    if value_type == Some("DATE") || value.len() == 8 {
+        let date = NaiveDate::parse_from_str(value, "%Y%m%d").with_context(|| format!("Invalid date '{}'", value))?;
+        return Ok(IcsTime::Date(date));
+    }
+    if let Some(utc) = value.strip_suffix('Z') {
+        let dt = NaiveDateTime::parse_from_str(utc, "%Y%m%dT%H%M%S")
+            .with_context(|| format!("Invalid date-time '{}'", value))?;
+        return Ok(IcsTime::Utc(dt.and_utc()));
+    }
+    let dt = NaiveDateTime::parse_from_str(value, "%Y%m%dT%H%M%S")
+        .with_context(|| format!("Invalid date-time '{}'", value))?;
+    let tz = match tzid {
+        Some(tzid) => resolve_tzid(tzid)?,
+        None => default_tz,
+    };
+    Ok(IcsTime::Local(dt, tz))
+}
+
+/// Map a TZID to an IANA zone. Exporters often prefix the IANA name with their own path,
+/// e.g. "/freeassociation.sourceforge.net/Tzfile/Europe/Berlin".
+fn resolve_tzid(tzid: &str) -> Result<Tz> {
+    let tzid = tzid.trim_matches('"');
+    let segments: Vec<&str> = tzid.split('/').collect();
+    (0..segments.len())
+        .find_map(|i| segments[i..].join("/").parse::<Tz>().ok())
+        .with_context(|| format!("Unknown TZID '{}'", tzid))
+}
+
+/// Parse an iCalendar document. VEVENTs that fail to parse are reported as diagnostics
+/// (numbered by their position in the file) and skipped.
+pub fn parse_ics(text: &str, source: &str, options: &FetchOptions) -> Result<SourceEvents> {
+    let lines = unfold(text);
+    anyhow::ensure!(
+        lines.first().is_some_and(|l| l.eq_ignore_ascii_case("BEGIN:VCALENDAR")),
+        "Not an iCalendar file (expected BEGIN:VCALENDAR)"
+    );
+
+    // X-WR-TIMEZONE is the calendar's own zone for floating times, like a spreadsheet's time zone
+    let calendar_tz = lines
+        .iter()
+        .filter_map(|l| parse_property(l))
+        .find(|p| p.name == "X-WR-TIMEZONE")
+        .and_then(|p| parse_timezone(&p.value).ok());
+    let default_tz = options.default_timezone(calendar_tz);
+
+    let vevents = collect_vevents(&lines);
+
+    // Modified instances (RECURRENCE-ID) replace the occurrence they name in the series
+    let mut overridden: HashMap<String, HashSet<NaiveDateTime>> = HashMap::new();
+    for vevent in &vevents {
+        if let (Some(uid), Some(recurrence_id)) = (vevent.text("UID"), vevent.get("RECURRENCE-ID")) {
+            if let Ok(time) = IcsTime::parse(recurrence_id, default_tz) {
+                overridden.entry(uid).or_default().insert(series_key(vevent, time, default_tz));
+            }
+        }
+    }
+
+    let mut parsed = SourceEvents::default();
+    let mut keys = ContentKeys::default();
+    for (index, vevent) in vevents.iter().enumerate() {
+        let row_num = index + 1;
+        match parse_vevent(vevent, default_tz, options, &overridden, &mut keys) {
+            Ok(events) => parsed.events.extend(events.into_iter().map(|mut event| {
+                event.source = Some(source.to_string());
+                event
+            })),
+            Err(e) => parsed.diagnostics.push(RowDiagnostic {
+                row: row_num,
+                message: format!("{:#}", e),
+            }),
+        }
+    }
+
+    Ok(parsed)
+}
+
+/// Unfold continuation lines (RFC 5545 section 3.1) and drop blank ones
+fn unfold(text: &str) -> Vec<String> {
+    let mut lines: Vec<String> = Vec::new();
+    for raw in text.split('\n') {
+        let raw = raw.strip_suffix('\r').unwrap_or(raw);
+        match (raw.strip_prefix(' ').or_else(|| raw.strip_prefix('\t')), lines.last_mut()) {
+            (Some(continuation), Some(last)) => last.push_str(continuation),
+            _ if raw.is_empty() => {}
+            _ => lines.push(raw.to_string()),
+        }
+    }
+    lines
+}
+
+fn parse_property(line: &str) -> Option<Property> {
+    // The value starts at the first colon outside a quoted parameter value
+    let mut in_quotes = false;
+    let colon = line.char_indices().find_map(|(i, c)| match c {
+        '"' => {
+            in_quotes = !in_quotes;
+            None
+        }
+        ':' if !in_quotes => Some(i),
+        _ => None,
+    })?;
+    let (head, value) = (&line[..colon], &line[colon + 1..]);
+
+    let mut parts = head.split(';');
+    let name = parts.next()?.trim().to_ascii_uppercase();
+    let params = parts
+        .filter_map(|p| p.split_once('='))
+        .map(|(k, v)| (k.trim().to_ascii_uppercase(), v.trim_matches('"').to_string()))
+        .collect();
+    Some(Property { name, params, value: value.to_string() })
+}
+
+fn collect_vevents(lines: &[String]) -> Vec<VEvent> {
+    let mut vevents = Vec::new();
+    let mut current: Option<VEvent> = None;
+    let mut nested = 0;
+
+    for line in lines {
+        let Some(property) = parse_property(line) else {
+            continue;
+        };
+        let component = property.value.trim().to_ascii_uppercase();
+        match (property.name.as_str(), current.as_mut()) {
+            ("BEGIN", None) if component == "VEVENT" => current = Some(VEvent::default()),
+            ("BEGIN", Some(_)) => nested += 1,
+            ("END", Some(_)) if nested > 0 => nested -= 1,
+            ("END", Some(_)) if component == "VEVENT" => vevents.extend(current.take()),
+            (_, Some(vevent)) if nested == 0 => vevent.properties.push(property),
+            _ => {}
+        }
+    }
+    vevents
+}
+
+fn parse_vevent(
+    vevent: &VEvent,
+    default_tz: Tz,
+    options: &FetchOptions,
+    overridden: &HashMap<String, HashSet<NaiveDateTime>>,
+    keys: &mut ContentKeys,
+) -> Result<Vec<CalendarEvent>> {
+    if vevent.text("STATUS").is_some_and(|s| s.eq_ignore_ascii_case("CANCELLED")) {
+        return Ok(Vec::new());
+    }
+
+    let title = vevent.text("SUMMARY").context("Missing SUMMARY")?;
+    let start = IcsTime::parse(vevent.get("DTSTART").context("Missing DTSTART")?, default_tz)
+        .context("Invalid DTSTART")?;
+    let end = match (vevent.get("DTEND"), vevent.get("DURATION")) {
+        (Some(dtend), _) => Some(IcsTime::parse(dtend, default_tz).context("Invalid DTEND")?),
+        (None, Some(duration)) => Some(add_duration(start, parse_duration(&duration.value)?)?),
+        (None, None) => None,
+    };
+
+    let tz = match start {
+        IcsTime::Local(_, tz) => tz,
+        _ => default_tz,
+    };
+    let all_day = matches!(start, IcsTime::Date(_));
+    let start_local = start.local_in(tz);
+    // All-day ends are exclusive; a missing end means a one-day (or instantaneous) event
+    let length = match end {
+        Some(end) if all_day => (end.local_in(tz) - start_local - Duration::days(1)).max(Duration::zero()),
+        Some(end) => (end.local_in(tz) - start_local).max(Duration::zero()),
+        None => Duration::zero(),
+    };
+
+    let uid = vevent.text("UID");
+    let template = CalendarEvent {
+        title,
+        description: vevent.text("DESCRIPTION"),
+        location: vevent.text("LOCATION"),
+        organization: vevent.get("CATEGORIES").and_then(|p| split_list(&p.value).into_iter().next()),
+        purchased: false,
+        start_date: start_local.date(),
+        start_time: None,
+        end_date: start_local.date(),
+        end_time: None,
+        timezone: Some(tz),
+        source: None,
+        source_id: None,
+        recurrence: None,
+        reminders: None,
+        attendees: Vec::new(),
+    };
+
+    let occurrence = |at: NaiveDateTime| -> CalendarEvent {
+        let end_at = at + length;
+        let mut event = template.clone();
+        event.start_date = at.date();
+        event.end_date = end_at.date();
+        if !all_day {
+            event.start_time = Some(at.time());
+            event.end_time = Some(end_at.time());
+        }
+        event
+    };
+
+    let Some(rrule) = vevent.get("RRULE") else {
+        let mut event = occurrence(start_local);
+        event.source_id = Some(match (&uid, vevent.get("RECURRENCE-ID")) {
+            (Some(uid), Some(recurrence_id)) => {
+                let original = IcsTime::parse(recurrence_id, default_tz).context("Invalid RECURRENCE-ID")?;
+                instance_id(uid, series_key(vevent, original, default_tz), all_day)
+            }
+            (Some(uid), None) => uid.clone(),
+            (None, _) => keys.key(&event),
+        });
+        return Ok(vec![event]);
+    };
+
+    let rule = RRule::parse(&rrule.value, |until| {
+        Ok(match parse_time_value(until, None, None, tz)? {
+            // A date UNTIL includes every occurrence on that day
+            IcsTime::Date(d) => d.and_time(NaiveTime::from_hms_opt(23, 59, 59).unwrap()),
+            time => time.local_in(tz),
+        })
+    })
+    .context("Invalid RRULE")?;
+
+    let window_start = options.start_date.unwrap_or(NaiveDate::MIN);
+    let window_end = options.end_date.unwrap_or_else(|| {
+        options.start_date.unwrap_or(start_local.date()).max(start_local.date())
+            + Duration::days(UNBOUNDED_EXPANSION_DAYS)
+    });
+
+    let mut starts = rule.expand(start_local, window_start, window_end);
+    for time in list_values(vevent, "RDATE", tz)? {
+        let at = match time {
+            IcsTime::Date(d) => d.and_time(start_local.time()),
+            time => time.local_in(tz),
+        };
+        if at.date() >= window_start && at.date() <= window_end {
+            starts.push(at);
+        }
+    }
+
+    // EXDATEs name occurrence starts; a bare date excludes that whole day
+    let mut excluded_times: HashSet<NaiveDateTime> = HashSet::new();
+    let mut excluded_dates: HashSet<NaiveDate> = HashSet::new();
+    for time in list_values(vevent, "EXDATE", tz)? {
+        match time {
+            IcsTime::Date(d) => excluded_dates.insert(d),
+            time => excluded_times.insert(time.local_in(tz)),
+        };
+    }
+    if let Some(keys) = uid.as_ref().and_then(|uid| overridden.get(uid)) {
+        excluded_times.extend(keys);
+    }
+
+    starts.sort();
+    starts.dedup();
+    Ok(starts
+        .into_iter()
+        .filter(|at| !excluded_times.contains(at) && !excluded_dates.contains(&at.date()))
+        .map(|at| {
+            let mut event = occurrence(at);
+            event.source_id = Some(match &uid {
+                Some(uid) => instance_id(uid, at, all_day),
+                None => keys.key(&event),
+            });
+            event
+        })
+        .collect())
+}
+
+/// Every value of a date list property (RDATE, EXDATE), which may repeat and hold several values
+fn list_values(vevent: &VEvent, name: &str, tz: Tz) -> Result<Vec<IcsTime>> {
+    let mut values = Vec::new();
+    for property in vevent.all(name) {
+        for value in property.value.split(',') {
+            values.push(
+                parse_time_value(value, property.param("VALUE"), property.param("TZID"), tz)
+                    .with_context(|| format!("Invalid {}", name))?,
+            );
+        }
+    }
+    Ok(values)
+}
+
+/// Local start an instance had in its series, used to match RECURRENCE-ID against occurrences
+fn series_key(vevent: &VEvent, time: IcsTime, default_tz: Tz) -> NaiveDateTime {
+    let tz = match vevent.get("DTSTART").and_then(|p| IcsTime::parse(p, default_tz).ok()) {
+        Some(IcsTime::Local(_, tz)) => tz,
+        _ => default_tz,
+    };
+    time.local_in(tz)
+}
+
+/// Source ID of one occurrence of a series, e.g. "abc@venue.org/20260314T193000"
+fn instance_id(uid: &str, at: NaiveDateTime, all_day: bool) -> String {
+    let key = if all_day { at.format("%Y%m%d") } else { at.format("%Y%m%dT%H%M%S") };
+    format!("{}/{}", uid, key)
+}
+
+fn add_duration(start: IcsTime, duration: Duration) -> Result<IcsTime> {
+    let end = match start {
+        IcsTime::Date(d) => d.checked_add_signed(duration).map(IcsTime::Date),
+        IcsTime::Local(dt, tz) => dt.checked_add_signed(duration).map(|dt| IcsTime::Local(dt, tz)),
+        IcsTime::Utc(t) => t.checked_add_signed(duration).map(IcsTime::Utc),
+    };
+    end.context("DURATION ends too far from DTSTART")
+}
+
+/// Parse a DURATION value such as `PT2H30M`, `P1D` or `P1W`
+fn parse_duration(value: &str) -> Result<Duration> {
+    let value = value.trim();
+    let (negative, rest) = match value.strip_prefix('-') {
+        Some(rest) => (true, rest),
+        None => (false, value.strip_prefix('+').unwrap_or(value)),
+    };
+    let rest = rest.strip_prefix('P').with_context(|| format!("Invalid DURATION '{}'", value))?;
+
+    let mut total = Duration::zero();
+    let mut number = String::new();
+    for c in rest.chars() {
+        match c {
+            '0'..='9' => number.push(c),
+            'T' => {}
+            'W' | 'D' | 'H' | 'M' | 'S' => {
+                let n: i64 = number.parse().with_context(|| format!("Invalid DURATION '{}'", value))?;
+                number.clear();
+                let part = match c {
+                    'W' => Duration::try_weeks(n),
+                    'D' => Duration::try_days(n),
+                    'H' => Duration::try_hours(n),
+                    'M' => Duration::try_minutes(n),
+                    _ => Duration::try_seconds(n),
+                };
+                total = part
+                    .and_then(|part| total.checked_add(&part))
+                    .with_context(|| format!("DURATION '{}' is too long", value))?;
+            }
+            _ => anyhow::bail!("Invalid DURATION '{}'", value),
+        }
+    }
+    Ok(if negative { -total } else { total })
+}
+
+/// Unescape a TEXT value (RFC 5545 section 3.3.11)
+fn unescape_text(value: &str) -> String {
+    let mut text = String::with_capacity(value.len());
+    let mut chars = value.chars();
+    while let Some(c) = chars.next() {
+        if c != '\\' {
+            text.push(c);
+            continue;
+        }
+        match chars.next() {
+            Some('n') | Some('N') => text.push('\n'),
+            Some(other) => text.push(other),
+            None => text.push('\\'),
+        }
+    }
+    text.trim().to_string()
+}
+
+/// Split a comma-separated TEXT list, honouring escaped commas
+fn split_list(value: &str) -> Vec<String> {
+    let mut items = Vec::new();
+    let mut current = String::new();
+    let mut escaped = false;
+    for c in value.chars() {
+        match c {
+            ',' if !escaped => items.push(std::mem::take(&mut current)),
+            '\\' if !escaped => {
+                escaped = true;
+                current.push(c);
+                continue;
+            }
+            _ => current.push(c),
+        }
+        escaped = false;
+    }
+    items.push(current);
+    items.iter().map(|item| unescape_text(item)).filter(|item| !item.is_empty()).collect()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    const CALENDAR: &str = "BEGIN:VCALENDAR\r\n\
+VERSION:2.0\r\n\
+X-WR-TIMEZONE:America/Los_Angeles\r\n\
+BEGIN:VEVENT\r\n\
+UID:recital@venue.org\r\n\
+SUMMARY:Recital\\, Hall B\r\n\
+DTSTART;TZID=/example.com/Tzfile/Europe/Berlin:20260314T193000\r\n\
+DTEND;TZID=Europe/Berlin:20260314T213000\r\n\
+LOCATION:Philharmonie\r\n\
+DESCRIPTION:Program:\\nBach\\; Brahms and a description that is long enough to be \r\n\
+\x20folded\r\n\
+CATEGORIES:Berliner Phil,Chamber\r\n\
+BEGIN:VALARM\r\n\
+ACTION:DISPLAY\r\n\
+SUMMARY:Reminder\r\n\
+END:VALARM\r\n\
+END:VEVENT\r\n\
+BEGIN:VEVENT\r\n\
+UID:festival@venue.org\r\n\
+SUMMARY:Festival\r\n\
+DTSTART;VALUE=DATE:20260701\r\n\
+DTEND;VALUE=DATE:20260704\r\n\
+END:VEVENT\r\n\
+BEGIN:VEVENT\r\n\
+UID:rehearsal@venue.org\r\n\
+SUMMARY:Rehearsal\r\n\
+DTSTART:20260302T180000\r\n\
+DURATION:PT2H\r\n\
+RRULE:FREQ=WEEKLY;BYDAY=MO\r\n\
+EXDATE:20260309T180000\r\n\
+END:VEVENT\r\n\
+BEGIN:VEVENT\r\n\
+UID:rehearsal@venue.org\r\n\
+RECURRENCE-ID:20260316T180000\r\n\
+SUMMARY:Rehearsal (moved)\r\n\
+DTSTART:20260317T180000\r\n\
+DURATION:PT2H\r\n\
+END:VEVENT\r\n\
+BEGIN:VEVENT\r\n\
+UID:broken@venue.org\r\n\
+DTSTART:20260401T100000\r\n\
+END:VEVENT\r\n\
+END:VCALENDAR\r\n";
+
+    fn parse(start: (i32, u32, u32), end: (i32, u32, u32)) -> SourceEvents {
+        let options = FetchOptions {
+            start_date: NaiveDate::from_ymd_opt(start.0, start.1, start.2),
+            end_date: NaiveDate::from_ymd_opt(end.0, end.1, end.2),
+            ..Default::default()
+        };
+        parse_ics(CALENDAR, "ics:test.ics", &options).unwrap()
+    }
+
+    #[test]
+    fn test_parse_timed_and_all_day_events() {
+        let parsed = parse((2026, 3, 1), (2026, 3, 8));
+        let recital = parsed.events.iter().find(|e| e.title == "Recital, Hall B").unwrap();
+        assert_eq!(recital.timezone, Some(chrono_tz::Europe::Berlin));
+        assert_eq!(recital.start_time, NaiveTime::from_hms_opt(19, 30, 0));
+        assert_eq!(recital.end_time, NaiveTime::from_hms_opt(21, 30, 0));
+        assert_eq!(recital.location.as_deref(), Some("Philharmonie"));
+        assert_eq!(
+            recital.description.as_deref(),
+            Some("Program:\nBach; Brahms and a description that is long enough to be folded")
+        );
+        assert_eq!(recital.organization.as_deref(), Some("Berliner Phil"));
+        assert_eq!(recital.source.as_deref(), Some("ics:test.ics"));
+        assert_eq!(recital.source_id.as_deref(), Some("recital@venue.org"));
+
+        // The DTEND of an all-day event is exclusive
+        let festival = parsed.events.iter().find(|e| e.title == "Festival").unwrap();
+        assert_eq!(festival.start_date, NaiveDate::from_ymd_opt(2026, 7, 1).unwrap());
+        assert_eq!(festival.end_date, NaiveDate::from_ymd_opt(2026, 7, 3).unwrap());
+        assert_eq!(festival.start_time, None);
+
+        assert_eq!(parsed.diagnostics.len(), 1);
+        assert_eq!(parsed.diagnostics[0].row, 5);
+        assert!(parsed.diagnostics[0].message.contains("SUMMARY"));
+    }
+
+    #[test]
+    fn test_recurring_event_expanded_within_window() {
+        let parsed = parse((2026, 3, 1), (2026, 3, 31));
+        let rehearsals: Vec<_> = parsed.events.iter().filter(|e| e.title.starts_with("Rehearsal")).collect();
+        let starts: Vec<String> = rehearsals
+            .iter()
+            .map(|e| format!("{} {}", e.title, e.start_date))
+            .collect();
+        // 03-09 is excluded and 03-16 is replaced by the moved instance
+        assert_eq!(
+            starts,
+            [
+                "Rehearsal 2026-03-02",
+                "Rehearsal 2026-03-23",
+                "Rehearsal 2026-03-30",
+                "Rehearsal (moved) 2026-03-17"
+            ]
+        );
+        // Floating times use the calendar's X-WR-TIMEZONE
+        assert_eq!(rehearsals[0].timezone, Some(chrono_tz::America::Los_Angeles));
+        assert_eq!(rehearsals[0].end_time, NaiveTime::from_hms_opt(20, 0, 0));
+        assert_eq!(rehearsals[0].source_id.as_deref(), Some("rehearsal@venue.org/20260302T180000"));
+        assert_eq!(rehearsals[3].source_id.as_deref(), Some("rehearsal@venue.org/20260316T180000"));
+    }
+
+    #[test]
+    fn test_parse_duration() {
+        assert_eq!(parse_duration("PT2H30M").unwrap(), Duration::minutes(150));
+        assert_eq!(parse_duration("P1W").unwrap(), Duration::days(7));
+        assert_eq!(parse_duration("-P1DT12H").unwrap(), -Duration::hours(36));
+        assert!(parse_duration("2H").is_err());
+        assert!(parse_duration("P99999999999999W").unwrap_err().to_string().contains("too long"));
+        let start = IcsTime::Date(NaiveDate::from_ymd_opt(2026, 3, 14).unwrap());
+        assert!(add_duration(start, parse_duration("P9999999999D").unwrap()).is_err());
+    }
+}
@@ -0,0 +1,492 @@
+//! Run journals: a JSON file listing every operation an import, delete or sync planned, and
+//! how each one went.
+//!
+//! The journal is written once the plan is known (every operation `pending`) and again when
+//! the run ends, so an interrupted run still shows what it meant to do. `--resume <journal>`
+//! re-plans the same command and skips the operations the journal records as done.
+//!
+//! The journals in `runs_dir()` double as the run history behind `history list` and `undo`.
+//! For that, a delete keeps a full copy of the event it removed.
+
+use anyhow::{Context, Result};
+use chrono::{DateTime, NaiveDate, Utc};
+use google_calendar3::api::Event;
+use serde::{Deserialize, Serialize};
+use std::fmt;
+use std::path::{Path, PathBuf};
+
+use crate::calendar::{self, FoundCalendarEvent};
+use crate::config;
+use crate::event::CalendarEvent;
+
+/// Runs are kept in `$XDG_STATE_HOME/calendar-sync/runs` (see `config::state_dir`)
+pub fn runs_dir() -> PathBuf {
+    config::state_dir("runs")
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(rename_all = "lowercase")]
+pub enum Action {
+    Create,
+    Update,
+    Delete,
+}
+
+impl Action {
+    pub fn as_str(self) -> &'static str {
+        match self {
+            Action::Create => "create",
+            Action::Update => "update",
+            Action::Delete => "delete",
+        }
+    }
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(rename_all = "lowercase")]
+pub enum Status {
+    /// Planned but not attempted (yet)
+    Pending,
+    Done,
+    Failed,
+}
+
+/// How an operation went: the ID of the event written (if it has one), or the error
+pub type Outcome = std::result::Result<Option<String>, String>;
+
+/// Describe a result as an `Outcome`
+pub fn outcome<T>(result: &Result<T>, event_id: impl FnOnce(&T) -> Option<String>) -> Outcome {
+    match result {
+        Ok(value) => Ok(event_id(value)),
+        Err(e) => Err(format!("{:#}", e)),
+    }
+}
+
+/// One planned write to the calendar
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct Operation {
+    pub action: Action,
+    pub title: String,
+    pub date: NaiveDate,
+    /// `source:id` stamp of the source event, if it has one
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub source_key: Option<String>,
+    /// The calendar event updated or deleted, or once done, the one created
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub event_id: Option<String>,
+    pub status: Status,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub error: Option<String>,
+    /// For a delete, the event as it was before, so it can be re-created
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub event: Option<Event>,
+    /// Reversed by `undo`
+    #[serde(default, skip_serializing_if = "std::ops::Not::not")]
+    pub undone: bool,
+}
+
+impl Operation {
+    pub fn create(event: &CalendarEvent) -> Self {
+        Self::new(Action::Create, event, None)
+    }
+
+    pub fn update(event: &CalendarEvent, found: &FoundCalendarEvent) -> Self {
+        Self::new(Action::Update, event, Some(found.id.clone()))
+    }
+
+    pub fn delete(found: &FoundCalendarEvent) -> Self {
+        Self {
+            action: Action::Delete,
+            title: found.title.clone(),
+            date: found.date,
+            source_key: None,
+            event_id: Some(found.id.clone()),
+            status: Status::Pending,
+            error: None,
+            event: Some(found.event.clone()),
+            undone: false,
+        }
+    }
+
+    /// Creating a copy of a listed event, e.g. from a backup
+    pub fn recreate(event: &Event) -> Self {
+        Self {
+            action: Action::Create,
+            title: event.summary.clone().unwrap_or_default(),
+            date: calendar::extract_event_date(event).unwrap_or(NaiveDate::MIN),
+            source_key: calendar::source_key(event).map(|(source, id)| format!("{}:{}", source, id)),
+            event_id: None,
+            status: Status::Pending,
+            error: None,
+            event: None,
+            undone: false,
+        }
+    }
+
+    fn new(action: Action, event: &CalendarEvent, event_id: Option<String>) -> Self {
+        let source_key = match (&event.source, &event.source_id) {
+            (Some(source), Some(id)) => Some(format!("{}:{}", source, id)),
+            _ => None,
+        };
+        Self {
+            action,
+            title: event.title.clone(),
+            date: event.start_date,
+            source_key,
+            event_id,
+            status: Status::Pending,
+            error: None,
+            event: None,
+            undone: false,
+        }
+    }
+
+    /// What makes a re-planned operation the same as a journaled one: the target event for
+    /// updates and deletes; for creates the source stamp, or failing that title and date
+    fn same_as(&self, other: &Operation) -> bool {
+        if self.action != other.action {
+            return false;
+        }
+        match self.action {
+            Action::Update | Action::Delete => self.event_id == other.event_id,
+            Action::Create => match (&self.source_key, &other.source_key) {
+                (Some(a), Some(b)) => a == b,
+                _ => self.title.eq_ignore_ascii_case(&other.title) && self.date == other.date,
+            },
+        }
+    }
+
+    /// Whether `undo` can still reverse this operation: a create or delete that was done
+    pub fn is_undoable(&self) -> bool {
+        self.status == Status::Done
+            && !self.undone
+            && match self.action {
+                Action::Create => self.event_id.is_some(),
+                Action::Update => false,
+                Action::Delete => self.event.is_some(),
+            }
+    }
+}
+
+/// The journal of one run
+#[derive(Debug, Serialize, Deserialize)]
+pub struct Journal {
+    pub id: String,
+    /// "import", "delete" or "sync"
+    pub command: String,
+    /// The source's name, e.g. "csv:season.csv" or "coda:<doc>/<table>"
+    pub source: String,
+    pub calendar_id: String,
+    pub started_at: DateTime<Utc>,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub finished_at: Option<DateTime<Utc>>,
+    pub operations: Vec<Operation>,
+    /// When `undo` last reversed operations of this run
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub undone_at: Option<DateTime<Utc>>,
+    /// Backup of the events this run was about to delete
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub backup: Option<PathBuf>,
+    /// Operations already done when this run resumed the journal; they are not run again
+    #[serde(skip)]
+    skipped: usize,
+    /// Which operations came from the resumed journal and have not been re-planned yet
+    #[serde(skip)]
+    resumable: Vec<bool>,
+    #[serde(skip)]
+    path: PathBuf,
+}
+
+impl Journal {
+    /// A new journal, saved at `path` or in `runs_dir()` under the run's ID (its start time)
+    pub fn start(command: &str, source: &str, calendar_id: &str, path: Option<&Path>) -> Self {
+        let started_at = Utc::now();
+        let id = unused_id(&runs_dir(), &started_at.format("%Y%m%d-%H%M%S").to_string());
+        let path = match path {
+            Some(path) => path.to_path_buf(),
+            None => runs_dir().join(format!("{}.json", id)),
+        };
+        Self {
+            id,
+            command: command.to_string(),
+            source: source.to_string(),
+            calendar_id: calendar_id.to_string(),
+            started_at,
+            finished_at: None,
+            operations: Vec::new(),
+            undone_at: None,
+            backup: None,
+            skipped: 0,
+            resumable: Vec::new(),
+            path,
+        }
+    }
+
+    pub fn load(path: &Path) -> Result<Self> {
+        let contents = std::fs::read_to_string(path)
+            .with_context(|| format!("Failed to read run journal {}", path.display()))?;
+        let mut journal: Journal = serde_json::from_str(&contents)
+            .with_context(|| format!("Failed to parse run journal {}", path.display()))?;
+        journal.path = path.to_path_buf();
+        Ok(journal)
+    }
+
+    /// Pick up an earlier run of the same command against the same calendar
+    pub fn resume(path: &Path, command: &str, calendar_id: &str) -> Result<Self> {
+        let mut journal = Self::load(path)?;
+        if journal.command != command || journal.calendar_id != calendar_id {
+            anyhow::bail!(
+                "Run journal {} is for {} on calendar '{}', not {} on '{}'",
+                path.display(),
+                journal.command,
+                journal.calendar_id,
+                command,
+                calendar_id
+            );
+        }
+        journal.finished_at = None;
+        journal.resumable = vec![true; journal.operations.len()];
+        Ok(journal)
+    }
+
+    pub fn path(&self) -> &Path {
+        &self.path
+    }
+
+    /// Add a planned operation, returning its index in the journal, or `None` if a resumed
+    /// journal records it as done already. Each resumed operation is matched at most once;
+    /// operations planned by this run always get a slot of their own.
+    pub fn plan(&mut self, operation: Operation) -> Option<usize> {
+        let resumed = (self.resumable.iter().zip(&self.operations))
+            .position(|(&open, op)| open && op.same_as(&operation));
+        if let Some(i) = resumed {
+            self.resumable[i] = false;
+        }
+        match resumed {
+            Some(i) if self.operations[i].status == Status::Done => {
+                self.skipped += 1;
+                None
+            }
+            Some(i) => {
+                let event_id = self.operations[i].event_id.take();
+                self.operations[i] = Operation { event_id: operation.event_id.or(event_id), ..operation };
+                Some(i)
+            }
+            None => {
+                self.operations.push(operation);
+                Some(self.operations.len() - 1)
+            }
+        }
+    }
+
+    /// Record how operation `index` went
+    pub fn record(&mut self, index: usize, outcome: Outcome) {
+        let operation = &mut self.operations[index];
+        match outcome {
+            Ok(event_id) => {
+                operation.status = Status::Done;
+                operation.error = None;
+                operation.event_id = event_id.or(operation.event_id.take());
+            }
+            Err(error) => {
+                operation.status = Status::Failed;
+                operation.error = Some(error);
+            }
+        }
+    }
+
+    /// Write the journal, replacing the previous copy atomically
+    pub fn save(&self) -> Result<()> {
+        if let Some(dir) = self.path.parent().filter(|dir| !dir.as_os_str().is_empty()) {
+            std::fs::create_dir_all(dir)
+                .with_context(|| format!("Failed to create directory {}", dir.display()))?;
+        }
+        let temp = self.path.with_extension("json.tmp");
+        let json = serde_json::to_string_pretty(self).context("Failed to serialize the run journal")?;
+        std::fs::write(&temp, json + "\n")
+            .and_then(|()| std::fs::rename(&temp, &self.path))
+            .with_context(|| format!("Failed to write run journal {}", self.path.display()))
+    }
+
+    /// Stamp the end time and save
+    pub fn finish(&mut self) -> Result<()> {
+        self.finished_at = Some(Utc::now());
+        self.save()
+    }
+
+    /// Done, failed and pending operations of each action, plus those skipped on resume
+    pub fn tally(&self) -> Tally {
+        let mut tally = Tally { skipped: self.skipped, ..Default::default() };
+        for operation in &self.operations {
+            let row = match operation.action {
+                Action::Create => &mut tally.create,
+                Action::Update => &mut tally.update,
+                Action::Delete => &mut tally.delete,
+            };
+            match operation.status {
+                Status::Done => row[0] += 1,
+                Status::Failed => row[1] += 1,
+                Status::Pending => row[2] += 1,
+            }
+        }
+        tally
+    }
+}
+
+/// The journals in `runs_dir()`, newest first. Files that aren't journals are skipped.
+pub fn list_runs() -> Result<Vec<Journal>> {
+    let dir = runs_dir();
+    let entries = match std::fs::read_dir(&dir) {
+        Ok(entries) => entries,
+        Err(e) if e.kind() == std::io::ErrorKind::NotFound => return Ok(Vec::new()),
+        Err(e) => return Err(e).with_context(|| format!("Failed to read {}", dir.display())),
+    };
+    let mut runs = Vec::new();
+    for entry in entries {
+        let path = entry.with_context(|| format!("Failed to read {}", dir.display()))?.path();
+        if path.extension().is_some_and(|ext| ext == "json") {
+            match Journal::load(&path) {
+                Ok(journal) => runs.push(journal),
+                Err(e) => tracing::debug!("Skipping {}: {:#}", path.display(), e),
+            }
+        }
+    }
+    runs.sort_by_key(|run| std::cmp::Reverse(run.started_at));
+    Ok(runs)
+}
+
+/// The run with this ID (or the journal at this path), or by default the latest run with
+/// something left to undo
+pub fn find_run(run: Option<&str>) -> Result<Journal> {
+    match run {
+        Some(run) if Path::new(run).is_file() => Journal::load(Path::new(run)),
+        Some(id) => {
+            let path = runs_dir().join(format!("{}.json", id));
+            if !path.is_file() {
+                anyhow::bail!("No run '{}' in {} (see history list)", id, runs_dir().display());
+            }
+            Journal::load(&path)
+        }
+        None => list_runs()?
+            .into_iter()
+            .find(|journal| journal.operations.iter().any(Operation::is_undoable))
+            .context("No run in the history has anything left to undo"),
+    }
+}
+
+/// `id`, or `id-2` and so on if `dir` already holds a run by that ID
+fn unused_id(dir: &Path, id: &str) -> String {
+    let mut candidate = id.to_string();
+    let mut n = 2;
+    while dir.join(format!("{}.json", candidate)).exists() {
+        candidate = format!("{}-{}", id, n);
+        n += 1;
+    }
+    candidate
+}
+
+/// Operation counts by action, as [done, failed, pending]
+#[derive(Debug, Default, PartialEq, Eq)]
+pub struct Tally {
+    pub create: [usize; 3],
+    pub update: [usize; 3],
+    pub delete: [usize; 3],
+    /// Done by an earlier run of a resumed journal (and included in `done`)
+    pub skipped: usize,
+}
+
+impl Tally {
+    pub fn done(&self) -> usize {
+        self.create[0] + self.update[0] + self.delete[0]
+    }
+
+    /// Done by this run, leaving out what the earlier run of a resumed journal did
+    pub fn done_this_run(&self) -> usize {
+        self.done() - self.skipped
+    }
+
+    pub fn failed(&self) -> usize {
+        self.create[1] + self.update[1] + self.delete[1]
+    }
+}
+
+/// The run summary table
+impl fmt::Display for Tally {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        writeln!(f, "{:<10} {:>6} {:>7} {:>8}", "ACTION", "DONE", "FAILED", "PENDING")?;
+        writeln!(f, "{}", "-".repeat(34))?;
+        for (action, [done, failed, pending]) in [
+            (Action::Create, self.create),
+            (Action::Update, self.update),
+            (Action::Delete, self.delete),
+        ] {
+            if done + failed + pending > 0 {
+                writeln!(f, "{:<10} {:>6} {:>7} {:>8}", action.as_str(), done, failed, pending)?;
+            }
+        }
+        if self.skipped > 0 {
+            writeln!(f, "({} done by the earlier run, not repeated)", self.skipped)?;
+        }
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::backend::concert;
+
+    #[test]
+    fn test_resume_skips_done_operations() {
+        let dir = std::env::temp_dir().join(format!("calendar-sync-journal-{}", std::process::id()));
+        let path = dir.join("run.json");
+        let events = [concert("Mahler 9", 14, Some("1")), concert("Piano Recital", 15, None), concert("Brahms 4", 16, Some("3"))];
+
+        let mut journal = Journal::start("import", "csv:season.csv", "primary", Some(&path));
+        let slots: Vec<Option<usize>> = events.iter().map(|e| journal.plan(Operation::create(e))).collect();
+        assert_eq!(slots, [Some(0), Some(1), Some(2)]);
+        journal.record(0, Ok(Some("evt1".to_string())));
+        journal.record(1, outcome::<()>(&Err(anyhow::anyhow!("HTTP 500")), |_| None));
+        journal.finish().unwrap();
+        assert_eq!(journal.tally().create, [1, 1, 1]);
+
+        let mut resumed = Journal::resume(&path, "import", "primary").unwrap();
+        assert_eq!(resumed.operations[1].error.as_deref(), Some("HTTP 500"));
+        // The source stamp identifies the first event even though its title changed
+        let renamed = concert("Mahler: Symphony No. 9", 14, Some("1"));
+        assert_eq!(resumed.plan(Operation::create(&renamed)), None);
+        assert_eq!(resumed.plan(Operation::create(&concert("piano recital", 15, None))), Some(1));
+        assert_eq!(resumed.plan(Operation::create(&events[2])), Some(2));
+        resumed.record(1, Ok(Some("evt2".to_string())));
+        resumed.record(2, Ok(Some("evt3".to_string())));
+        let tally = resumed.tally();
+        assert_eq!((tally.create, tally.skipped, tally.done_this_run()), ([3, 0, 0], 1, 2));
+        assert_eq!(tally.failed(), 0);
+
+        assert!(Journal::resume(&path, "sync", "primary").is_err());
+        assert!(Journal::resume(&path, "import", "team@example.com").is_err());
+        std::fs::remove_dir_all(dir).unwrap();
+    }
+    #[test]
+    fn test_identical_operations_get_their_own_slots() {
+        let path = std::env::temp_dir().join(format!("calendar-sync-lunch-{}.json", std::process::id()));
+        let lunch = concert("Lunch", 14, None);
+
+        let mut journal = Journal::start("import", "csv:season.csv", "primary", Some(&path));
+        assert_eq!(journal.plan(Operation::create(&lunch)), Some(0));
+        assert_eq!(journal.plan(Operation::create(&lunch)), Some(1));
+        journal.record(0, Ok(Some("evt1".to_string())));
+        journal.record(1, Err("HTTP 500".to_string()));
+        journal.save().unwrap();
+        let ids: Vec<Option<&str>> = journal.operations.iter().map(|op| op.event_id.as_deref()).collect();
+        assert_eq!(ids, [Some("evt1"), None]);
+
+        // On resume each journaled operation is claimed once: the done one is skipped
+        let mut resumed = Journal::resume(&path, "import", "primary").unwrap();
+        std::fs::remove_file(&path).unwrap();
+        assert_eq!(resumed.plan(Operation::create(&lunch)), None);
+        assert_eq!(resumed.plan(Operation::create(&lunch)), Some(1));
+        assert_eq!(resumed.plan(Operation::create(&lunch)), Some(2));
+        assert_eq!(resumed.tally().skipped, 1);
+    }
+}
@@ -1,142 +1,108 @@
+mod backend;
+mod backup;
+mod batch;
 mod calendar;
 mod cli;
 mod coda;
+mod config;
 mod csv_parser;
 mod event;
-
-use std::collections::HashMap;
-
-use anyhow::Result;
-use chrono::NaiveDate;
+mod history;
+mod ics;
+mod ics_parser;
+mod journal;
+mod mapping;
+mod pipeline;
+mod recurrence;
+mod retry;
+mod sheets;
+mod source;
+mod sync;
+
+use anyhow::{Context, Result};
 use clap::Parser;
+use std::path::PathBuf;
+use std::process::ExitCode;
 use tracing_subscriber::{layer::SubscriberExt, util::SubscriberInitExt};
 
-use crate::cli::{Cli, Commands};
-use crate::event::CalendarEvent;
+use crate::calendar::ConvertOptions;
+use crate::cli::{Cli, Commands, ConfigCommand, HistoryCommand, CsvFormatArgs, MappingArgs, SourceArgs, DEFAULT_SHEETS_RANGE};
+use crate::config::Config;
+use crate::mapping::{CodaMapping, CsvMapping, MappingFile};
+use crate::pipeline::RunOptions;
+use crate::retry::{RequestOptions, RetryPolicy, DEFAULT_MAX_RETRIES, DEFAULT_RATE_LIMIT};
+use crate::source::{EventSource, FetchOptions};
+
+/// Build the event source selected by the generic source flags
+async fn build_source(args: SourceArgs, options: &RunOptions) -> Result<Box<dyn EventSource>> {
+    match args {
+        SourceArgs { file: Some(file), mapping, format, .. } => file_source(file, &mapping, &format),
+        SourceArgs { doc_id: Some(doc_id), table_id, mapping, .. } => {
+            let table_id = config::require(table_id, "table-id")?;
+            let mapping = coda_mapping(&mapping)?;
+            let client = coda::CodaClient::new(coda::get_api_token()?).with_retry(options.requests.retry);
+            Ok(Box::new(coda::CodaSource::new(client, doc_id, table_id, mapping)))
+        }
+        SourceArgs { spreadsheet_id: Some(spreadsheet_id), range, mapping, .. } => {
+            let range = range.unwrap_or_else(|| DEFAULT_SHEETS_RANGE.to_string());
+            let mapping = csv_mapping(&mapping, None)?;
+            let client = sheets::SheetsClient::new(sheets::get_access_token(&options.auth).await?);
+            Ok(Box::new(sheets::SheetsSource::new(client, spreadsheet_id, range, mapping)))
+        }
+        _ => anyhow::bail!(
+            "Specify --file, --doc-id and --table-id, or --spreadsheet-id (or a --profile with a source)"
+        ),
+    }
+}
 
-fn truncate(s: &str, max_len: usize) -> String {
-    if s.chars().count() <= max_len {
-        s.to_string()
-    } else {
-        let truncated: String = s.chars().take(max_len.saturating_sub(3)).collect();
-        format!("{}...", truncated)
+/// A CSV file, or an iCalendar file when the extension is .ics
+fn file_source(file: PathBuf, mapping: &MappingArgs, format: &CsvFormatArgs) -> Result<Box<dyn EventSource>> {
+    let is_ics = file.extension().is_some_and(|ext| ext.eq_ignore_ascii_case("ics"));
+    if is_ics {
+        return Ok(Box::new(ics_parser::IcsSource::new(file)));
     }
+    Ok(Box::new(csv_parser::CsvSource::new(file, csv_mapping(mapping, Some(format))?)))
 }
 
-fn filter_events(
-    events: Vec<CalendarEvent>,
-    start_date: Option<NaiveDate>,
-    end_date: Option<NaiveDate>,
-    purchased_only: bool,
-) -> Vec<CalendarEvent> {
-    let mut filtered: Vec<CalendarEvent> = events
-        .into_iter()
-        .filter(|e| {
-            // Filter by start date
-            if let Some(sd) = start_date {
-                if e.start_date < sd {
-                    return false;
-                }
-            }
-            // Filter by end date
-            if let Some(ed) = end_date {
-                if e.start_date > ed {
-                    return false;
-                }
-            }
-            // Filter by purchased
-            if purchased_only && !e.purchased {
-                return false;
-            }
-            true
-        })
-        .collect();
-    
-    // Sort by date and time
-    filtered.sort_by(|a, b| {
-        a.start_date.cmp(&b.start_date)
-            .then_with(|| a.start_time.cmp(&b.start_time))
-    });
-    
-    filtered
+/// The mapping file given with --mapping, or the built-in column layout
+fn load_mapping(args: &MappingArgs) -> Result<MappingFile> {
+    args.mapping.as_deref().map(mapping::load_mapping).transpose().map(Option::unwrap_or_default)
 }
 
-fn print_events(events: &[CalendarEvent]) {
-    println!("\n{:<40} {:<12} {:<8} {:<12} {:<8} {:<25}", 
-        "summary", "start.date", "start", "end.date", "end", "location");
-    println!("{}", "-".repeat(105));
-    for event in events {
-        // Line 1: summary, dates, times, location
-        println!("{:<40} {:<12} {:<8} {:<12} {:<8} {:<25}",
-            truncate(&event.title, 38),
-            event.start_date.format("%Y-%m-%d"),
-            event.start_time.map(|t| t.format("%H:%M").to_string()).unwrap_or_else(|| "all-day".to_string()),
-            event.end_date.format("%Y-%m-%d"),
-            event.end_time.map(|t| t.format("%H:%M").to_string()).unwrap_or_else(|| "all-day".to_string()),
-            event.location.as_deref().map(|l| truncate(l, 23)).unwrap_or_default(),
-        );
-        // Line 2: description (indented)
-        if let Some(desc) = &event.description {
-            let desc_preview = truncate(desc.replace('\n', " | ").as_str(), 100);
-            println!("  description: {}", desc_preview);
-        }
+/// The `[csv]` mapping with --map and (for files) --delimiter/--encoding applied
+fn csv_mapping(args: &MappingArgs, format: Option<&CsvFormatArgs>) -> Result<CsvMapping> {
+    let mut mapping = load_mapping(args)?.csv;
+    for (field, column) in &args.fields {
+        mapping.set(field, column)?;
+    }
+    if let Some(format) = format {
+        mapping.delimiter = format.delimiter.or(mapping.delimiter);
+        mapping.encoding = format.encoding.unwrap_or(mapping.encoding);
     }
+    Ok(mapping)
 }
 
-fn print_stats(events: &[CalendarEvent]) {
-    println!("\n{}", "=".repeat(60));
-    println!("STATISTICS");
-    println!("{}", "=".repeat(60));
-    
-    // Total events and purchased
-    let total_purchased = events.iter().filter(|e| e.purchased).count();
-    println!("\nTotal Events: {} ({} purchased)", events.len(), total_purchased);
-    
-    // Events by venue (total, purchased)
-    let mut by_venue: HashMap<String, (usize, usize)> = HashMap::new();
-    for event in events {
-        let venue = event.location.clone().unwrap_or_else(|| "(No venue)".to_string());
-        let entry = by_venue.entry(venue).or_insert((0, 0));
-        entry.0 += 1;
-        if event.purchased {
-            entry.1 += 1;
-        }
-    }
-    
-    println!("\nEvents by Venue:");
-    println!("{:<6} {:<6} {}", "Total", "Purch", "Venue");
-    println!("{:-<50}", "");
-    let mut venue_counts: Vec<_> = by_venue.into_iter().collect();
-    venue_counts.sort_by(|a, b| b.1.0.cmp(&a.1.0)); // Sort by total count descending
-    for (venue, (total, purchased)) in venue_counts {
-        println!("  {:>4} {:>6}  {}", total, purchased, venue);
+/// The `[coda]` mapping with --map applied
+fn coda_mapping(args: &MappingArgs) -> Result<CodaMapping> {
+    let mut mapping = load_mapping(args)?.coda;
+    for (field, column) in &args.fields {
+        mapping.set(field, column)?;
     }
-    
-    // Events by organization (total, purchased)
-    let mut by_org: HashMap<String, (usize, usize)> = HashMap::new();
-    for event in events {
-        let org = event.organization.clone().unwrap_or_else(|| "(No organization)".to_string());
-        let entry = by_org.entry(org).or_insert((0, 0));
-        entry.0 += 1;
-        if event.purchased {
-            entry.1 += 1;
+    Ok(mapping)
+}
+
+#[tokio::main]
+async fn main() -> ExitCode {
+    match run().await {
+        Ok(()) => ExitCode::SUCCESS,
+        Err(error) => {
+            eprintln!("Error: {:?}", error);
+            ExitCode::from(pipeline::exit_code(&error))
         }
     }
-    
-    println!("\nEvents by Organization:");
-    println!("{:<6} {:<6} {}", "Total", "Purch", "Organization");
-    println!("{:-<50}", "");
-    let mut org_counts: Vec<_> = by_org.into_iter().collect();
-    org_counts.sort_by(|a, b| b.1.0.cmp(&a.1.0)); // Sort by total count descending
-    for (org, (total, purchased)) in org_counts {
-        println!("  {:>4} {:>6}  {}", total, purchased, org);
-    }
-    
-    println!();
 }
 
-#[tokio::main]
-async fn main() -> Result<()> {
+async fn run() -> Result<()> {
     // Initialize logging
     tracing_subscriber::registry()
         .with(tracing_subscriber::EnvFilter::new(
@@ -148,134 +114,82 @@ async fn main() -> Result<()> {
     // Load .env file if present
     dotenvy::dotenv().ok();
 
-    let cli = Cli::parse();
-
-    match cli.command {
-        Commands::Import { file, calendar_id, dry_run, stats, start_date, end_date, purchased, delete } => {
-            tracing::info!("Importing events from: {}", file.display());
-            
-            let all_events = csv_parser::parse_csv(&file)?;
-            tracing::info!("Parsed {} events", all_events.len());
-
-            let events = filter_events(all_events, start_date, end_date, purchased);
-            if start_date.is_some() || end_date.is_some() || purchased {
-                tracing::info!("After filtering: {} events", events.len());
-            }
-
-            if delete {
-                let hub = calendar::create_calendar_hub().await?;
-                let matches = calendar::find_matching_events(&hub, &calendar_id, &events).await?;
-                
-                if dry_run {
-                    println!("\n{} events would be DELETED:", matches.len());
-                    println!("{}", "=".repeat(80));
-                    println!("{:<40} {:<12} {:<30}", "TITLE", "DATE", "GCAL LOCATION");
-                    println!("{}", "-".repeat(80));
-                    for (_, gcal) in &matches {
-                        println!("{:<40} {:<12} {:<30}",
-                            truncate(&gcal.title, 38),
-                            gcal.date.format("%Y-%m-%d"),
-                            gcal.location.as_deref().map(|l| truncate(l, 28)).unwrap_or_default(),
-                        );
-                    }
-                    if stats {
-                        print_stats(&events);
-                    }
-                    println!();
-                    return Ok(());
-                }
-
-                let event_ids: Vec<String> = matches.iter().map(|(_, g)| g.id.clone()).collect();
-                let deleted = calendar::delete_events(&hub, &calendar_id, &event_ids).await?;
-                tracing::info!("Successfully deleted {} events", deleted);
-                return Ok(());
-            }
-
-            if dry_run {
-                tracing::info!("Dry run mode - not creating events");
-                print_events(&events);
-                if stats {
-                    print_stats(&events);
-                }
-                println!();
-                return Ok(());
-            }
+    let mut cli = Cli::parse();
+    let config = Config::load(cli.config.as_deref())?;
+    let profile = config.profile(cli.profile.as_deref())?;
+    if let Some((name, profile)) = profile {
+        tracing::debug!("Using profile '{}'", name);
+        profile.apply(&mut cli);
+    }
 
-            if stats {
-                print_stats(&events);
-            }
+    let options = RunOptions {
+        fetch: FetchOptions {
+            timezone: cli.timezone,
+            reminders: cli.reminders.clone(),
+            ..Default::default()
+        },
+        convert: ConvertOptions {
+            dst_policy: cli.dst_policy.unwrap_or_default(),
+            colors: config.color_rules(profile.map(|(_, p)| p)),
+        },
+        auth: config.file.google.clone(),
+        requests: RequestOptions {
+            retry: RetryPolicy {
+                max_retries: cli.max_retries.unwrap_or(DEFAULT_MAX_RETRIES),
+                ..Default::default()
+            },
+            rate_limit: cli.rate_limit.unwrap_or(DEFAULT_RATE_LIMIT),
+        },
+    };
 
-            let hub = calendar::create_calendar_hub().await?;
-            calendar::create_events(&hub, &calendar_id, &events).await?;
-            
-            tracing::info!("Successfully created {} events", events.len());
+    match cli.command {
+        Commands::Import { file, mapping, format, args } => {
+            let source = file_source(config::require(file, "file")?, &mapping, &format)?;
+            pipeline::run_import(source.as_ref(), &options, &args).await?;
         }
-        Commands::CodaImport { doc_id, table_id, calendar_id, dry_run, stats, start_date, end_date, purchased, delete } => {
-            tracing::info!("Importing events from Coda doc: {}, table: {}", doc_id, table_id);
-            
-            let api_token = coda::get_api_token()?;
-            let client = coda::CodaClient::new(api_token);
-            let all_events = client.fetch_events(&doc_id, &table_id).await?;
-            tracing::info!("Fetched {} events from Coda", all_events.len());
-
-            let events = filter_events(all_events, start_date, end_date, purchased);
-            if start_date.is_some() || end_date.is_some() || purchased {
-                tracing::info!("After filtering: {} events", events.len());
-            }
-
-            if delete {
-                let hub = calendar::create_calendar_hub().await?;
-                let matches = calendar::find_matching_events(&hub, &calendar_id, &events).await?;
-                
-                if dry_run {
-                    println!("\n{} events would be DELETED:", matches.len());
-                    println!("{}", "=".repeat(80));
-                    println!("{:<40} {:<12} {:<30}", "TITLE", "DATE", "GCAL LOCATION");
-                    println!("{}", "-".repeat(80));
-                    for (_, gcal) in &matches {
-                        println!("{:<40} {:<12} {:<30}",
-                            truncate(&gcal.title, 38),
-                            gcal.date.format("%Y-%m-%d"),
-                            gcal.location.as_deref().map(|l| truncate(l, 28)).unwrap_or_default(),
-                        );
-                    }
-                    if stats {
-                        print_stats(&events);
-                    }
-                    println!();
-                    return Ok(());
-                }
-
-                let event_ids: Vec<String> = matches.iter().map(|(_, g)| g.id.clone()).collect();
-                let deleted = calendar::delete_events(&hub, &calendar_id, &event_ids).await?;
-                tracing::info!("Successfully deleted {} events", deleted);
-                return Ok(());
-            }
-
-            if dry_run {
-                tracing::info!("Dry run mode - not creating events");
-                print_events(&events);
-                if stats {
-                    print_stats(&events);
-                }
-                println!();
-                return Ok(());
-            }
-
-            if stats {
-                print_stats(&events);
-            }
-
-            let hub = calendar::create_calendar_hub().await?;
-            calendar::create_events(&hub, &calendar_id, &events).await?;
-            
-            tracing::info!("Successfully created {} events", events.len());
+        Commands::CodaImport { doc_id, table_id, mapping, args } => {
+            let doc_id = config::require(doc_id, "doc-id")?;
+            let table_id = config::require(table_id, "table-id")?;
+            let mapping = coda_mapping(&mapping)?;
+            let client = coda::CodaClient::new(coda::get_api_token()?).with_retry(options.requests.retry);
+            let source = coda::CodaSource::new(client, doc_id, table_id, mapping);
+            pipeline::run_import(&source, &options, &args).await?;
+        }
+        Commands::SheetsImport { spreadsheet_id, range, mapping, args } => {
+            let spreadsheet_id = config::require(spreadsheet_id, "spreadsheet-id")?;
+            let range = range.unwrap_or_else(|| DEFAULT_SHEETS_RANGE.to_string());
+            let mapping = csv_mapping(&mapping, None)?;
+            let client = sheets::SheetsClient::new(sheets::get_access_token(&options.auth).await?);
+            let source = sheets::SheetsSource::new(client, spreadsheet_id, range, mapping);
+            pipeline::run_import(&source, &options, &args).await?;
+        }
+        Commands::Sync { source, args } => {
+            let source = build_source(source, &options).await?;
+            pipeline::run_sync(source.as_ref(), &options, &args).await?;
+        }
+        Commands::ExportIcs { source, args } => {
+            let source = build_source(source, &options).await?;
+            pipeline::run_export_ics(source.as_ref(), &options, &args).await?;
+        }
+        Commands::Backup { args } => {
+            pipeline::run_backup(&options, &args).await?;
+        }
+        Commands::Restore { args } => {
+            pipeline::run_restore(&options, &args).await?;
+        }
+        Commands::Undo { args } => {
+            pipeline::run_undo(&options, &args).await?;
+        }
+        Commands::History { command: HistoryCommand::List { limit } } => {
+            let runs = journal::list_runs()?;
+            history::print_runs(&runs[..limit.min(runs.len())]);
         }
         Commands::ListCodaTables { doc_id } => {
+            let doc_id = config::require(doc_id, "doc-id")?;
             tracing::info!("Listing tables in Coda doc: {}", doc_id);
             
             let api_token = coda::get_api_token()?;
-            let client = coda::CodaClient::new(api_token);
+            let client = coda::CodaClient::new(api_token).with_retry(options.requests.retry);
             let tables = client.list_tables(&doc_id).await?;
 
             println!("\nTables in Coda document:");
@@ -287,14 +201,22 @@ async fn main() -> Result<()> {
             println!();
         }
         Commands::ListCalendars => {
-            let hub = calendar::create_calendar_hub().await?;
-            calendar::list_calendars(&hub).await?;
+            let backend = calendar::connect(&options.auth).await?.with_requests(options.requests);
+            calendar::list_calendars(&backend).await?;
+        }
+        Commands::ListColors => {
+            let backend = calendar::connect(&options.auth).await?.with_requests(options.requests);
+            calendar::list_colors(&backend).await?;
         }
         Commands::Auth => {
-            tracing::info!("Authenticating with Google Calendar...");
-            let _hub = calendar::create_calendar_hub().await?;
+            tracing::info!("Authenticating with Google Calendar and Sheets...");
+            let auth = calendar::create_authenticator(&options.auth).await?;
+            auth.token(calendar::AUTH_SCOPES).await.context("Failed to authenticate")?;
             tracing::info!("Authentication successful!");
         }
+        Commands::Config { command: ConfigCommand::Show } => {
+            config::show(&config, profile, &options)?;
+        }
     }
 
     Ok(())
@@ -0,0 +1,521 @@
+use anyhow::{Context, Result};
+use serde::Deserialize;
+use std::path::Path;
+
+use crate::source::SyncedEvent;
+
+/// A column mapping file (TOML). Each source type has its own table:
+///
+/// ```toml
+/// [csv]
+/// title = "Event"
+/// start_date = "Date"
+/// delimiter = ";"
+///
+/// [coda]
+/// title = "{Organization}: {Display}"
+/// start = "performanceDate"
+/// location = "venue"
+/// description = ["{kenticoUrl}", "{artists}", "{works}"]
+/// ```
+#[derive(Debug, Clone, Default, Deserialize)]
+#[serde(default, deny_unknown_fields)]
+pub struct MappingFile {
+    /// Used for CSV files and Google Sheets
+    pub csv: CsvMapping,
+    pub coda: CodaMapping,
+}
+
+/// Which CSV (or Sheets) headers feed which event fields, plus how to read the file.
+/// Headers match case-insensitively, with spaces and underscores treated alike.
+#[derive(Debug, Clone, Deserialize)]
+#[serde(default, deny_unknown_fields)]
+pub struct CsvMapping {
+    pub id: String,
+    pub title: String,
+    pub description: String,
+    pub location: String,
+    pub organization: String,
+    /// Column whose value "Yes"/"true" marks the event as purchased
+    pub purchased: String,
+    pub start_date: String,
+    pub start_time: String,
+    pub end_date: String,
+    pub end_time: String,
+    pub timezone: String,
+    /// Column holding an RRULE such as "FREQ=WEEKLY;BYDAY=TU" for repeating events
+    pub recurrence: String,
+    /// Column listing dates (YYYY-MM-DD) on which a repeating event is skipped
+    pub exdates: String,
+    /// Column holding reminder overrides such as "popup:120,email:1440"
+    pub reminders: String,
+    /// Column holding guest emails, e.g. "alex@example.com, optional:sam@example.com"
+    pub attendees: String,
+    /// Field separator; detected from the header line when not set
+    #[serde(deserialize_with = "deserialize_delimiter")]
+    pub delimiter: Option<u8>,
+    pub encoding: CsvEncoding,
+    /// Name that identifies a CSV file as a source (stamped on its events as `csv:<name>`),
+    /// in place of its full path; keeps the identity when the file moves
+    pub source_name: Option<String>,
+}
+
+impl Default for CsvMapping {
+    /// Each field read from the column of the same name
+    fn default() -> Self {
+        Self {
+            id: "id".to_string(),
+            title: "title".to_string(),
+            description: "description".to_string(),
+            location: "location".to_string(),
+            organization: "organization".to_string(),
+            purchased: "purchased".to_string(),
+            start_date: "start_date".to_string(),
+            start_time: "start_time".to_string(),
+            end_date: "end_date".to_string(),
+            end_time: "end_time".to_string(),
+            timezone: "timezone".to_string(),
+            recurrence: "recurrence".to_string(),
+            exdates: "exdates".to_string(),
+            reminders: "reminders".to_string(),
+            attendees: "attendees".to_string(),
+            delimiter: None,
+            encoding: CsvEncoding::default(),
+            source_name: None,
+        }
+    }
+}
+
+impl CsvMapping {
+    /// Point a field at a column or template, as given by `--map field=Header`
+    pub fn set(&mut self, field: &str, spec: &str) -> Result<()> {
+        let target = match field {
+            "id" => &mut self.id,
+            "title" => &mut self.title,
+            "description" => &mut self.description,
+            "location" => &mut self.location,
+            "organization" => &mut self.organization,
+            "purchased" => &mut self.purchased,
+            "start_date" => &mut self.start_date,
+            "start_time" => &mut self.start_time,
+            "end_date" => &mut self.end_date,
+            "end_time" => &mut self.end_time,
+            "timezone" => &mut self.timezone,
+            "recurrence" => &mut self.recurrence,
+            "exdates" => &mut self.exdates,
+            "reminders" => &mut self.reminders,
+            "attendees" => &mut self.attendees,
+            _ => anyhow::bail!(
+                "Unknown CSV field '{}' (expected one of: id, title, description, location, \
+                 organization, purchased, start_date, start_time, end_date, end_time, timezone, \
+                 recurrence, exdates, reminders, attendees)",
+                field
+            ),
+        };
+        *target = spec.to_string();
+        Ok(())
+    }
+}
+
+/// Text encoding of a CSV file. A UTF-8 byte order mark is always honoured and stripped.
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq, Deserialize, clap::ValueEnum)]
+pub enum CsvEncoding {
+    #[default]
+    #[serde(rename = "utf-8")]
+    #[value(name = "utf-8")]
+    Utf8,
+    /// ISO-8859-1, read as its Windows-1252 superset (as browsers do)
+    #[serde(rename = "latin1")]
+    #[value(name = "latin1")]
+    Latin1,
+    /// Excel's default "CSV" export on Western-locale Windows
+    #[serde(rename = "windows-1252")]
+    #[value(name = "windows-1252")]
+    Windows1252,
+}
+
+impl CsvEncoding {
+    /// Decode raw file contents
+    pub fn decode(self, bytes: &[u8]) -> Result<String> {
+        let encoding = match self {
+            CsvEncoding::Utf8 => encoding_rs::UTF_8,
+            CsvEncoding::Latin1 | CsvEncoding::Windows1252 => encoding_rs::WINDOWS_1252,
+        };
+        // `decode` sniffs and strips a BOM, which overrides the requested encoding
+        let (text, _, had_errors) = encoding.decode(bytes);
+        anyhow::ensure!(
+            !had_errors,
+            "File is not valid UTF-8 (try --encoding windows-1252 for Excel exports)"
+        );
+        Ok(text.into_owned())
+    }
+}
+
+/// Parse a delimiter given as a single ASCII character, or "tab"
+pub fn parse_delimiter(s: &str) -> Result<u8> {
+    match s {
+        "tab" | "\\t" | "\t" => Ok(b'\t'),
+        _ if s.len() == 1 && s.is_ascii() => Ok(s.as_bytes()[0]),
+        _ => anyhow::bail!("Invalid delimiter '{}': use a single character such as ';', or \"tab\"", s),
+    }
+}
+
+fn deserialize_delimiter<'de, D: serde::Deserializer<'de>>(deserializer: D) -> Result<Option<u8>, D::Error> {
+    let s = String::deserialize(deserializer)?;
+    parse_delimiter(&s).map(Some).map_err(serde::de::Error::custom)
+}
+
+/// Whether a cell value marks a yes/no column as set
+pub fn is_truthy(value: &str) -> bool {
+    matches!(value.trim().to_lowercase().as_str(), "yes" | "true")
+}
+
+/// Which Coda columns feed which event fields. Every text field is either a bare column
+/// name or a template combining columns, e.g. `"{Organization}: {Display}"`.
+#[derive(Debug, Clone, Deserialize)]
+#[serde(default, deny_unknown_fields)]
+pub struct CodaMapping {
+    pub title: String,
+    /// Column holding the start date or datetime
+    pub start: String,
+    /// Column holding the end datetime (takes precedence over `duration`)
+    pub end: Option<String>,
+    /// Column holding the event length in minutes
+    pub duration: Option<String>,
+    /// Event length when neither `end` nor `duration` has a value and no `durations` rule matches
+    #[serde(deserialize_with = "deserialize_minutes")]
+    pub default_duration_minutes: i64,
+    /// Default event lengths for particular organizations or venues; the first match wins
+    pub durations: Vec<DurationRule>,
+    pub location: Option<String>,
+    pub organization: Option<String>,
+    /// Column whose value "Yes"/"true" marks the event as purchased
+    pub purchased: Option<String>,
+    /// Column holding an IANA time zone name for the row
+    pub timezone: Option<String>,
+    /// Column holding an RRULE such as "FREQ=WEEKLY;BYDAY=TU" for repeating events
+    pub recurrence: Option<String>,
+    /// Column listing dates (YYYY-MM-DD) on which a repeating event is skipped
+    pub exdates: Option<String>,
+    /// Column holding reminder overrides such as "popup:120,email:1440"
+    pub reminders: Option<String>,
+    /// Column holding guest emails, e.g. "alex@example.com, optional:sam@example.com"
+    pub attendees: Option<String>,
+    /// Description lines; lines whose columns are all empty are dropped
+    pub description: Vec<String>,
+    /// Skip rows hidden by the table's filters (Coda's `visibleOnly`)
+    pub visible_only: bool,
+
+    // Write-back: columns that receive each row's Google Calendar event after it is created
+    // or updated. These are written, not read, and are skipped when unset.
+    /// Column for the Google Calendar event ID
+    pub event_id_column: Option<String>,
+    /// Column for a link to the event in the Google Calendar web UI
+    pub event_link_column: Option<String>,
+    /// Column for the time the row was last written to Google Calendar
+    pub synced_at_column: Option<String>,
+}
+
+impl Default for CodaMapping {
+    /// The layout of the original concert-season doc
+    fn default() -> Self {
+        Self {
+            title: "Display".to_string(),
+            start: "performanceDate".to_string(),
+            end: None,
+            duration: None,
+            default_duration_minutes: 150, // 2.5 hours
+            durations: Vec::new(),
+            location: Some("venue".to_string()),
+            organization: Some("Organization".to_string()),
+            purchased: Some("Purchased".to_string()),
+            timezone: Some("timezone".to_string()),
+            recurrence: None,
+            exdates: None,
+            reminders: None,
+            attendees: None,
+            visible_only: false,
+            event_id_column: None,
+            event_link_column: None,
+            synced_at_column: None,
+            description: vec![
+                "kenticoUrl".to_string(),
+                "artists".to_string(),
+                "works".to_string(),
+            ],
+        }
+    }
+}
+
+impl CodaMapping {
+    /// Point a field at a column or template, as given by `--map field=Column`
+    pub fn set(&mut self, field: &str, spec: &str) -> Result<()> {
+        let spec = spec.to_string();
+        match field {
+            "title" => self.title = spec,
+            "start" => self.start = spec,
+            "end" => self.end = Some(spec),
+            "duration" => self.duration = Some(spec),
+            "default_duration_minutes" => {
+                self.default_duration_minutes = spec
+                    .parse()
+                    .map_err(anyhow::Error::from)
+                    .and_then(check_minutes)
+                    .with_context(|| format!("Invalid default_duration_minutes: '{}'", spec))?
+            }
+            "location" => self.location = Some(spec),
+            "organization" => self.organization = Some(spec),
+            "purchased" => self.purchased = Some(spec),
+            "timezone" => self.timezone = Some(spec),
+            "recurrence" => self.recurrence = Some(spec),
+            "exdates" => self.exdates = Some(spec),
+            "reminders" => self.reminders = Some(spec),
+            "attendees" => self.attendees = Some(spec),
+            "description" => self.description = vec![spec],
+            "visible_only" => {
+                self.visible_only = spec
+                    .parse()
+                    .with_context(|| format!("Invalid visible_only: '{}' (expected true or false)", spec))?
+            }
+            "event_id_column" => self.event_id_column = Some(spec),
+            "event_link_column" => self.event_link_column = Some(spec),
+            "synced_at_column" => self.synced_at_column = Some(spec),
+            _ => anyhow::bail!(
+                "Unknown Coda field '{}' (expected one of: title, start, end, duration, \
+                 default_duration_minutes, location, organization, purchased, timezone, recurrence, \
+                 exdates, reminders, attendees, description, visible_only, event_id_column, \
+                 event_link_column, synced_at_column)",
+                field
+            ),
+        }
+        Ok(())
+    }
+
+    /// Cells to write back to a row for its calendar event, as (column, value) pairs
+    pub fn write_back_cells(&self, synced: &SyncedEvent, synced_at: &str) -> Vec<(String, String)> {
+        [
+            (&self.event_id_column, Some(synced.event_id.clone())),
+            (&self.event_link_column, synced.html_link.clone()),
+            (&self.synced_at_column, Some(synced_at.to_string())),
+        ]
+        .into_iter()
+        .filter_map(|(column, value)| Some((column.clone()?, value?)))
+        .collect()
+    }
+
+    /// The default length of an event from this organization at this venue
+    pub fn default_duration(&self, organization: Option<&str>, location: Option<&str>) -> i64 {
+        self.durations
+            .iter()
+            .find(|rule| rule.matches(organization, location))
+            .map_or(self.default_duration_minutes, |rule| rule.minutes)
+    }
+}
+
+/// A default event length for rows from one organization and/or venue, e.g.
+///
+/// ```toml
+/// [[coda.durations]]
+/// organization = "SF Opera"
+/// minutes = 210
+/// ```
+#[derive(Debug, Clone, Deserialize)]
+#[serde(deny_unknown_fields)]
+pub struct DurationRule {
+    pub organization: Option<String>,
+    pub location: Option<String>,
+    #[serde(deserialize_with = "deserialize_minutes")]
+    pub minutes: i64,
+}
+
+impl DurationRule {
+    /// Whether every field the rule sets matches (case-insensitively)
+    fn matches(&self, organization: Option<&str>, location: Option<&str>) -> bool {
+        let matches = |want: &Option<String>, have: Option<&str>| {
+            want.as_deref().is_none_or(|w| have.is_some_and(|h| h.trim().eq_ignore_ascii_case(w.trim())))
+        };
+        matches(&self.organization, organization) && matches(&self.location, location)
+    }
+
+    fn validate(&self) -> Result<()> {
+        if self.organization.is_none() && self.location.is_none() {
+            anyhow::bail!("Duration rule sets neither organization nor location");
+        }
+        Ok(())
+    }
+}
+
+/// Longest event length accepted, in minutes: a year
+pub const MAX_EVENT_MINUTES: i64 = 366 * 24 * 60;
+
+/// An event length in minutes must be positive, or the event would end before it starts, and
+/// at most `MAX_EVENT_MINUTES`, so it can't overflow the end date
+pub fn check_minutes(minutes: i64) -> Result<i64> {
+    anyhow::ensure!(minutes > 0, "event length must be a positive number of minutes, got {}", minutes);
+    anyhow::ensure!(
+        minutes <= MAX_EVENT_MINUTES,
+        "event length must be at most {} minutes (a year), got {}",
+        MAX_EVENT_MINUTES,
+        minutes
+    );
+    Ok(minutes)
+}
+
+fn deserialize_minutes<'de, D: serde::Deserializer<'de>>(deserializer: D) -> Result<i64, D::Error> {
+    check_minutes(i64::deserialize(deserializer)?).map_err(serde::de::Error::custom)
+}
+
+/// Load a mapping file
+pub fn load_mapping(path: &Path) -> Result<MappingFile> {
+    let contents = std::fs::read_to_string(path)
+        .with_context(|| format!("Failed to read mapping file: {}", path.display()))?;
+    let mapping: MappingFile = toml::from_str(&contents)
+        .with_context(|| format!("Invalid mapping file: {}", path.display()))?;
+    for rule in &mapping.coda.durations {
+        rule.validate().with_context(|| format!("Invalid mapping file: {}", path.display()))?;
+    }
+    Ok(mapping)
+}
+
+/// Render a mapping value against a row. A bare column name yields that column's value; a
+/// template substitutes each `{Column}` and yields `None` only if every referenced column is
+/// empty. `lookup` returns a column's value, or `None` if it is missing or empty.
+pub fn render(spec: &str, lookup: impl Fn(&str) -> Option<String>) -> Option<String> {
+    if !spec.contains('{') {
+        return lookup(spec);
+    }
+
+    let mut rendered = String::new();
+    let mut any_value = false;
+    let mut rest = spec;
+    while let Some(open) = rest.find('{') {
+        let Some(close) = rest[open..].find('}').map(|i| open + i) else {
+            break;
+        };
+        rendered.push_str(&rest[..open]);
+        if let Some(value) = lookup(&rest[open + 1..close]) {
+            rendered.push_str(&value);
+            any_value = true;
+        }
+        rest = &rest[close + 1..];
+    }
+    rendered.push_str(rest);
+
+    let rendered = rendered.trim().to_string();
+    (any_value && !rendered.is_empty()).then_some(rendered)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::collections::HashMap;
+
+    fn row() -> HashMap<&'static str, &'static str> {
+        HashMap::from([("Display", "Mahler 9"), ("Organization", "SF Symphony"), ("works", "")])
+    }
+
+    fn lookup(name: &str) -> Option<String> {
+        row().get(name).filter(|v| !v.is_empty()).map(|v| v.to_string())
+    }
+
+    #[test]
+    fn test_render_column_and_template() {
+        assert_eq!(render("Display", lookup).as_deref(), Some("Mahler 9"));
+        assert_eq!(render("{Organization}: {Display}", lookup).as_deref(), Some("SF Symphony: Mahler 9"));
+        assert_eq!(render("Works: {works}", lookup), None);
+        assert_eq!(render("missing", lookup), None);
+    }
+
+    #[test]
+    fn test_mapping_file_overrides_defaults() {
+        let mapping: MappingFile = toml::from_str(
+            r#"
+            [coda]
+            title = "{Show} ({Company})"
+            start = "Curtain"
+            duration = "Minutes"
+            description = ["{Cast}"]
+            "#,
+        )
+        .unwrap();
+        assert_eq!(mapping.coda.title, "{Show} ({Company})");
+        assert_eq!(mapping.coda.start, "Curtain");
+        assert_eq!(mapping.coda.duration.as_deref(), Some("Minutes"));
+        assert_eq!(mapping.coda.location.as_deref(), Some("venue"));
+        assert_eq!(mapping.coda.default_duration_minutes, 150);
+
+        assert!(toml::from_str::<MappingFile>("[coda]\ntitel = \"Display\"").is_err());
+    }
+
+    #[test]
+    fn test_durations_must_be_positive_and_bounded() {
+        let error = toml::from_str::<MappingFile>("[coda]\ndefault_duration_minutes = 0").unwrap_err();
+        assert!(error.to_string().contains("positive"), "{}", error);
+        let rule = "[[coda.durations]]\norganization = \"SF Opera\"\nminutes = -30";
+        assert!(toml::from_str::<MappingFile>(rule).is_err());
+
+        let mut mapping = CodaMapping::default();
+        assert!(mapping.set("default_duration_minutes", "-90").is_err());
+        assert!(mapping.set("default_duration_minutes", "abc").is_err());
+        assert!(mapping.set("default_duration_minutes", "9223372036854775807").is_err());
+        let rule = "[[coda.durations]]\nlocation = \"Black Cat\"\nminutes = 100000000";
+        let error = toml::from_str::<MappingFile>(rule).unwrap_err();
+        assert!(error.to_string().contains("at most"), "{}", error);
+        mapping.set("default_duration_minutes", "90").unwrap();
+        assert_eq!(mapping.default_duration_minutes, 90);
+    }
+
+    #[test]
+    fn test_write_back_cells() {
+        let synced = SyncedEvent {
+            source_id: "i-abc".to_string(),
+            event_id: "evt1".to_string(),
+            html_link: None,
+        };
+        let mut mapping = CodaMapping::default();
+        assert!(mapping.write_back_cells(&synced, "2026-03-14T12:00:00Z").is_empty());
+
+        mapping.set("event_id_column", "Google Event").unwrap();
+        mapping.set("event_link_column", "Calendar Link").unwrap();
+        mapping.set("synced_at_column", "Last Synced").unwrap();
+        // No link, so that column is left as it is
+        assert_eq!(
+            mapping.write_back_cells(&synced, "2026-03-14T12:00:00Z"),
+            [
+                ("Google Event".to_string(), "evt1".to_string()),
+                ("Last Synced".to_string(), "2026-03-14T12:00:00Z".to_string()),
+            ]
+        );
+    }
+
+    #[test]
+    fn test_csv_mapping_file_and_overrides() {
+        let mut mapping: MappingFile = toml::from_str(
+            r#"
+            [csv]
+            title = "Event"
+            start_date = "Date"
+            delimiter = "tab"
+            encoding = "windows-1252"
+            "#,
+        )
+        .unwrap();
+        assert_eq!(mapping.csv.delimiter, Some(b'\t'));
+        assert_eq!(mapping.csv.encoding, CsvEncoding::Windows1252);
+        assert_eq!(mapping.csv.location, "location");
+
+        mapping.csv.set("location", "Venue").unwrap();
+        assert_eq!(mapping.csv.location, "Venue");
+        assert!(mapping.csv.set("venue", "Venue").is_err());
+        assert!(toml::from_str::<MappingFile>("[csv]\ndelimiter = \";;\"").is_err());
+    }
+
+    #[test]
+    fn test_decode_encodings() {
+        // "Café" with a UTF-8 BOM, and in Windows-1252
+        assert_eq!(CsvEncoding::Utf8.decode(b"\xEF\xBB\xBFCaf\xC3\xA9").unwrap(), "Café");
+        assert_eq!(CsvEncoding::Windows1252.decode(b"Caf\xE9").unwrap(), "Café");
+        assert_eq!(CsvEncoding::Latin1.decode(b"Caf\xE9 \x80").unwrap(), "Café €");
+        assert!(CsvEncoding::Utf8.decode(b"Caf\xE9").is_err());
+    }
+}
@@ -0,0 +1,792 @@
+use anyhow::{Context, Result};
+use chrono::NaiveDate;
+use std::collections::HashMap;
+use std::fmt;
+use std::path::Path;
+
+use crate::backend::CalendarBackend;
+use crate::calendar::{self, AuthPaths, ConvertOptions, EventFailure, FoundCalendarEvent, GoogleCalendar, SendUpdates};
+use crate::backup::{self, Backup};
+use crate::cli::{BackupArgs, ExportArgs, FilterArgs, ImportArgs, RestoreArgs, SyncArgs, UndoArgs};
+use crate::config;
+use crate::event::{CalendarEvent, DstPolicy, Reminders};
+use crate::history;
+use crate::ics;
+use crate::journal::{self, outcome, Journal, Operation};
+use crate::recurrence::Recurrence;
+use crate::retry::RequestOptions;
+use crate::source::{EventSource, FetchOptions, SourceEvents, SyncedEvent};
+use crate::sync;
+
+/// Settings from the global CLI options that apply to every command in a run
+#[derive(Debug, Clone, Default)]
+pub struct RunOptions {
+    pub fetch: FetchOptions,
+    pub convert: ConvertOptions,
+    pub auth: AuthPaths,
+    pub requests: RequestOptions,
+}
+
+/// Read and filter events from a source, logging any rows that had to be skipped
+async fn load_events(
+    source: &dyn EventSource,
+    options: &RunOptions,
+    filter: &FilterArgs,
+) -> Result<Vec<CalendarEvent>> {
+    tracing::info!("Reading events from {}", source.name());
+
+    let fetch = FetchOptions {
+        start_date: filter.start_date,
+        end_date: filter.end_date,
+        purchased_only: filter.purchased,
+        ..options.fetch.clone()
+    };
+    let SourceEvents { mut events, diagnostics } = source.fetch(&fetch).await?;
+    for diagnostic in &diagnostics {
+        tracing::warn!("Skipping {}", diagnostic);
+    }
+    tracing::info!("Parsed {} events ({} rows skipped)", events.len(), diagnostics.len());
+
+    if let Some(reminders) = &fetch.reminders {
+        for event in events.iter_mut().filter(|e| e.reminders.is_none()) {
+            event.reminders = Some(reminders.clone());
+        }
+    }
+
+    let events = filter_events(events, filter.start_date, filter.end_date, filter.purchased);
+    if filter.is_active() {
+        tracing::info!("After filtering: {} events", events.len());
+    }
+
+    Ok(check_dst(events, options.convert.dst_policy))
+}
+
+/// Apply the DST policy up front so adjustments are reported per row and rejected rows
+/// are dropped before anything is written
+fn check_dst(events: Vec<CalendarEvent>, policy: DstPolicy) -> Vec<CalendarEvent> {
+    events
+        .into_iter()
+        .filter(|event| {
+            if event.is_all_day() {
+                return true;
+            }
+            match event.resolve_times(policy) {
+                Ok((_, _, notes)) => {
+                    for note in notes {
+                        tracing::warn!("{} ({}): {}", event.title, event.start_date, note);
+                    }
+                    true
+                }
+                Err(e) => {
+                    tracing::warn!("Skipping {} ({}): {}", event.title, event.start_date, e);
+                    false
+                }
+            }
+        })
+        .collect()
+}
+
+/// Connect to Google Calendar with the run's retry, rate-limit and notification settings
+async fn connect(options: &RunOptions, send_updates: Option<SendUpdates>) -> Result<GoogleCalendar> {
+    Ok(calendar::connect(&options.auth)
+        .await?
+        .with_requests(options.requests)
+        .with_send_updates(send_updates))
+}
+
+/// Log how many requests had to be retried or were held back by the rate limiter
+fn log_request_summary(source: &dyn EventSource, backend: &dyn CalendarBackend) {
+    for summary in [source.request_summary(), backend.request_summary()].into_iter().flatten() {
+        tracing::info!("{}", summary);
+    }
+}
+
+/// Start the run's journal, or with --resume pick up the earlier run's
+fn open_journal(
+    command: &str,
+    source: &dyn EventSource,
+    calendar_id: &str,
+    journal: Option<&Path>,
+    resume: Option<&Path>,
+) -> Result<Journal> {
+    match resume {
+        Some(path) => {
+            let journal = Journal::resume(path, command, calendar_id)?;
+            tracing::info!("Resuming run {} from {}", journal.id, path.display());
+            Ok(journal)
+        }
+        None => Ok(Journal::start(command, &source.name(), calendar_id, journal)),
+    }
+}
+
+/// Save the journal, print the run summary and list the events the run failed to write,
+/// failing the run if there were any or if they could not be recorded in the source
+fn finish_run(journal: &mut Journal, failures: &[EventFailure], write_back: Result<()>) -> Result<()> {
+    journal.finish()?;
+    let tally = journal.tally();
+    println!("\nRun {} (journal: {})", journal.id, journal.path().display());
+    print!("{}", tally);
+    if let Some(path) = &journal.backup {
+        println!("Deleted events were backed up to {} (see restore)", path.display());
+    }
+    print_failures(failures, &format!("re-run with --resume {}", journal.path().display()));
+    RunFailed::check(failures.len(), tally.done_this_run(), write_back.err())
+}
+
+/// Record the events a run wrote in its source. The calendar has been changed by then, so
+/// the run carries on to save its journal whether or not this works.
+async fn write_back(source: &dyn EventSource, synced: &[SyncedEvent], context: &'static str) -> Result<()> {
+    source.write_back(synced).await.context(context)
+}
+
+/// List the events a run failed to write
+fn print_failures(failures: &[EventFailure], retry: &str) {
+    if failures.is_empty() {
+        return;
+    }
+    println!("\nFailed:");
+    for failure in failures {
+        println!("  {}", failure);
+    }
+    println!("\nTo retry only the failed changes, {}\n", retry);
+}
+
+/// A run that finished but failed to write some (or all) of its changes, or to record
+/// them in the source
+#[derive(Debug)]
+pub struct RunFailed {
+    pub failed: usize,
+    pub done: usize,
+    /// Why the changes that were written could not be recorded in the source
+    pub write_back: Option<String>,
+}
+
+impl RunFailed {
+    /// Fail the run if any change failed or the source was not updated
+    fn check(failed: usize, done: usize, write_back: Option<anyhow::Error>) -> Result<()> {
+        if failed == 0 && write_back.is_none() {
+            return Ok(());
+        }
+        let write_back = write_back.map(|e| format!("{:#}", e));
+        Err(RunFailed { failed, done, write_back }.into())
+    }
+
+    /// 2 if some changes were written (including when only recording them in the source
+    /// failed), 3 if none were
+    pub fn exit_code(&self) -> u8 {
+        if self.done == 0 && self.write_back.is_none() {
+            3
+        } else {
+            2
+        }
+    }
+}
+
+impl fmt::Display for RunFailed {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        if self.failed > 0 && self.done == 0 {
+            write!(f, "All {} changes failed", self.failed)?;
+        } else if self.failed > 0 {
+            write!(f, "{} of {} changes failed", self.failed, self.failed + self.done)?;
+        }
+        match &self.write_back {
+            Some(error) if self.failed > 0 => write!(f, "; {}", error),
+            Some(error) => write!(f, "{}", error),
+            None => Ok(()),
+        }
+    }
+}
+
+impl std::error::Error for RunFailed {}
+
+/// The process exit code for a failed run: 2 or 3 for partial or total failure to write
+/// (see `RunFailed`), 1 for anything else
+pub fn exit_code(error: &anyhow::Error) -> u8 {
+    error.downcast_ref::<RunFailed>().map_or(1, RunFailed::exit_code)
+}
+
+/// Import (or with --delete, remove) a source's events
+pub async fn run_import(
+    source: &dyn EventSource,
+    options: &RunOptions,
+    args: &ImportArgs,
+) -> Result<()> {
+    let events = load_events(source, options, &args.filter).await?;
+
+    if args.delete {
+        let backend = connect(options, args.send_updates).await?;
+        let matches = calendar::find_matching_events(&backend, args.calendar_id(), &events).await?;
+
+        if args.dry_run {
+            println!("\n{} events would be DELETED:", matches.len());
+            println!("{}", "=".repeat(80));
+            println!("{:<40} {:<12} {:<30}", "TITLE", "DATE", "GCAL LOCATION");
+            println!("{}", "-".repeat(80));
+            for (_, gcal) in &matches {
+                println!("{:<40} {:<12} {:<30}",
+                    truncate(&gcal.title, 38),
+                    gcal.date.format("%Y-%m-%d"),
+                    gcal.location.as_deref().map(|l| truncate(l, 28)).unwrap_or_default(),
+                );
+            }
+            if args.stats {
+                print_stats(&events);
+            }
+            println!();
+            return Ok(());
+        }
+
+        let mut journal = open_journal("delete", source, args.calendar_id(), args.journal.as_deref(), args.resume.as_deref())?;
+        let planned: Vec<(usize, &FoundCalendarEvent)> = matches
+            .iter()
+            .filter_map(|(_, gcal)| Some((journal.plan(Operation::delete(gcal))?, gcal)))
+            .collect();
+        let doomed = planned.iter().map(|(_, g)| g.event.clone()).collect();
+        journal.backup = backup::before_delete(args.calendar_id(), "import --delete", doomed)?;
+        journal.save()?;
+
+        let event_ids: Vec<String> = planned.iter().map(|(_, g)| g.id.clone()).collect();
+        let results = calendar::delete_events(&backend, args.calendar_id(), &event_ids, args.jobs()).await;
+        let mut failures = Vec::new();
+        for ((index, gcal), result) in planned.iter().zip(results) {
+            journal.record(*index, outcome(&result, |_| None));
+            if let Err(error) = result {
+                failures.push(EventFailure { title: gcal.title.clone(), error });
+            }
+        }
+        tracing::info!("Successfully deleted {} events", planned.len() - failures.len());
+        log_request_summary(source, &backend);
+        return finish_run(&mut journal, &failures, Ok(()));
+    }
+
+    if args.dry_run {
+        tracing::info!("Dry run mode - not creating events");
+        print_events(&events, &options.convert);
+        if args.stats {
+            print_stats(&events);
+        }
+        println!();
+        return Ok(());
+    }
+
+    if args.stats {
+        print_stats(&events);
+    }
+
+    let backend = connect(options, args.send_updates).await?;
+    let mut journal = open_journal("import", source, args.calendar_id(), args.journal.as_deref(), args.resume.as_deref())?;
+    import_events(source, &backend, &mut journal, events, &options.convert, args.jobs()).await
+}
+
+/// Create events in the journal's calendar, journaling how each one went, and record them
+/// in the source
+async fn import_events(
+    source: &dyn EventSource,
+    backend: &dyn CalendarBackend,
+    journal: &mut Journal,
+    events: Vec<CalendarEvent>,
+    options: &ConvertOptions,
+    jobs: usize,
+) -> Result<()> {
+    let (slots, events): (Vec<usize>, Vec<CalendarEvent>) = events
+        .into_iter()
+        .filter_map(|event| Some((journal.plan(Operation::create(&event))?, event)))
+        .unzip();
+    journal.save()?;
+
+    let calendar_id = journal.calendar_id.clone();
+    let results = calendar::create_events(backend, &calendar_id, &events, options, jobs).await;
+    let mut synced = Vec::new();
+    let mut failures = Vec::new();
+    for ((index, event), result) in slots.into_iter().zip(&events).zip(results) {
+        journal.record(index, outcome(&result, |created| created.id.clone()));
+        match result {
+            Ok(created) => synced.extend(SyncedEvent::new(event, &created)),
+            Err(error) => failures.push(EventFailure { title: event.title.clone(), error }),
+        }
+    }
+    journal.save()?;
+
+    tracing::info!("Successfully created {} events", events.len() - failures.len());
+    let recorded = write_back(source, &synced, "Events were created in Google Calendar but not recorded in the source").await;
+    log_request_summary(source, backend);
+    finish_run(journal, &failures, recorded)
+}
+
+/// Bring the calendar in line with a source's events
+pub async fn run_sync(
+    source: &dyn EventSource,
+    options: &RunOptions,
+    args: &SyncArgs,
+) -> Result<()> {
+    check_prune(args.prune, &args.filter)?;
+    let events = load_events(source, options, &args.filter).await?;
+
+    // Compare against everything in the filter window, falling back to the span of the events
+    let min_date = args.filter.start_date.or_else(|| events.iter().map(|e| e.start_date).min());
+    let max_date = args.filter.end_date.or_else(|| events.iter().map(|e| e.start_date).max());
+    let (Some(min_date), Some(max_date)) = (min_date, max_date) else {
+        tracing::info!("No events to sync");
+        return Ok(());
+    };
+
+    let backend = connect(options, args.send_updates).await?;
+    let existing = calendar::list_events_in_range(&backend, args.calendar_id(), min_date, max_date).await?;
+    tracing::info!("Found {} events in Google Calendar within date range", existing.len());
+
+    let plan = sync::plan_sync(&events, &existing, args.prune, &options.convert)?;
+    print_sync_plan(&plan, &options.convert);
+
+    if args.dry_run {
+        tracing::info!("Dry run mode - not changing Google Calendar");
+        return Ok(());
+    }
+    if plan.is_noop() {
+        tracing::info!("Calendar is already up to date");
+        return Ok(());
+    }
+
+    let mut journal = open_journal("sync", source, args.calendar_id(), args.journal.as_deref(), args.resume.as_deref())?;
+    sync_events(source, &backend, &mut journal, plan, &options.convert, args.jobs()).await
+}
+
+/// Apply a sync plan to the journal's calendar, journaling how each operation went, and
+/// record the created and updated events in the source
+async fn sync_events(
+    source: &dyn EventSource,
+    backend: &dyn CalendarBackend,
+    journal: &mut Journal,
+    mut plan: sync::SyncPlan,
+    options: &ConvertOptions,
+    jobs: usize,
+) -> Result<()> {
+    let calendar_id = journal.calendar_id.clone();
+    let slots = journal_plan(journal, &mut plan);
+    let doomed = plan.delete.iter().map(|found| found.event.clone()).collect();
+    journal.backup = backup::before_delete(&calendar_id, "sync --prune", doomed)?;
+    journal.save()?;
+
+    let summary = sync::apply_plan(backend, &calendar_id, &plan, options, jobs).await;
+    for (index, outcome) in slots.into_iter().zip(summary.outcomes) {
+        journal.record(index, outcome);
+    }
+    journal.save()?;
+
+    tracing::info!("Sync complete: {} created, {} updated, {} deleted, {} unchanged",
+        summary.created, summary.updated, summary.deleted, plan.unchanged.len());
+    let recorded = write_back(source, &summary.synced, "Google Calendar was synced but the changes were not recorded in the source").await;
+    log_request_summary(source, backend);
+    finish_run(journal, &summary.failures, recorded)
+}
+
+/// Pruning deletes the stamped events that a sync's events don't account for, so rows the
+/// filter left out would be deleted from the calendar even though the source still has them
+fn check_prune(prune: bool, filter: &FilterArgs) -> Result<()> {
+    anyhow::ensure!(
+        !prune || !filter.skips_rows(),
+        "--prune can't be combined with --purchased: the events of unpurchased rows would be deleted"
+    );
+    Ok(())
+}
+
+/// Add a sync plan's operations to the journal, dropping any that a resumed journal records
+/// as done. Returns the journal index of each operation left, in plan order.
+fn journal_plan(journal: &mut Journal, plan: &mut sync::SyncPlan) -> Vec<usize> {
+    let mut slots = Vec::new();
+    let mut keep = |index: Option<usize>| index.map(|i| slots.push(i)).is_some();
+    plan.create.retain(|event| keep(journal.plan(Operation::create(event))));
+    plan.update.retain(|(event, found)| keep(journal.plan(Operation::update(event, found))));
+    plan.delete.retain(|found| keep(journal.plan(Operation::delete(found))));
+    slots
+}
+
+/// Reverse a past run's creates and deletes
+pub async fn run_undo(options: &RunOptions, args: &UndoArgs) -> Result<()> {
+    let mut run = journal::find_run(args.run.as_deref())?;
+    history::print_undo_plan(&run);
+    if !run.operations.iter().any(Operation::is_undoable) {
+        tracing::info!("Nothing left to undo in run {}", run.id);
+        return Ok(());
+    }
+    if args.dry_run {
+        tracing::info!("Dry run mode - not changing Google Calendar");
+        return Ok(());
+    }
+
+    let backend = connect(options, args.send_updates).await?;
+    let (undone, failures) = history::undo(&backend, &mut run, args.jobs()).await;
+    run.save()?;
+    tracing::info!("Undid {} changes from run {}", undone, run.id);
+    if let Some(summary) = backend.request_summary() {
+        tracing::info!("{}", summary);
+    }
+    print_failures(&failures, &format!("re-run undo {}", run.id));
+    RunFailed::check(failures.len(), undone, None)
+}
+
+/// Save a calendar's events in a date range to a backup file
+pub async fn run_backup(options: &RunOptions, args: &BackupArgs) -> Result<()> {
+    let start_date = config::require(args.start_date, "start-date")?;
+    let end_date = config::require(args.end_date, "end-date")?;
+    anyhow::ensure!(start_date <= end_date, "--start-date must not be after --end-date");
+
+    let backend = connect(options, None).await?;
+    let events = calendar::list_events_in_range(&backend, args.calendar_id(), start_date, end_date).await?;
+    let count = events.len();
+    let backup = Backup::new(args.calendar_id(), "backup", Some((start_date, end_date)), events);
+    let path = backup.save(args.output.as_deref())?;
+    tracing::info!("Backed up {} events from {} to {}", count, args.calendar_id(), path.display());
+    Ok(())
+}
+
+/// Re-create the events of a backup that are no longer in the calendar
+pub async fn run_restore(options: &RunOptions, args: &RestoreArgs) -> Result<()> {
+    let backup = Backup::load(&args.from)?;
+    let calendar_id = args.calendar_id.as_deref().unwrap_or(&backup.calendar_id);
+    let backend = connect(options, args.send_updates).await?;
+    let existing = calendar::list_events_in_range(&backend, calendar_id, backup.start_date, backup.end_date).await?;
+    let missing = backup.missing_from(&existing);
+
+    println!("\n{} of {} backed-up events ({}, {} to {}) are missing from '{}':",
+        missing.len(), backup.events.len(), backup.reason, backup.start_date, backup.end_date, calendar_id);
+    println!("{}", "=".repeat(80));
+    println!("{:<40} {:<12} {:<25}", "TITLE", "DATE", "LOCATION");
+    println!("{}", "-".repeat(80));
+    for event in &missing {
+        println!("{:<40} {:<12} {:<25}",
+            truncate(event.summary.as_deref().unwrap_or_default(), 38),
+            calendar::extract_event_date(event).map(|d| d.to_string()).unwrap_or_default(),
+            event.location.as_deref().map(|l| truncate(l, 23)).unwrap_or_default(),
+        );
+    }
+    println!();
+    if args.dry_run {
+        tracing::info!("Dry run mode - not changing Google Calendar");
+        return Ok(());
+    }
+    if missing.is_empty() {
+        tracing::info!("Nothing to restore");
+        return Ok(());
+    }
+
+    let mut journal = Journal::start("restore", &format!("backup {}", args.from.display()), calendar_id, None);
+    let slots: Vec<usize> = missing.iter().filter_map(|event| journal.plan(Operation::recreate(event))).collect();
+    journal.save()?;
+
+    let titles: Vec<&str> = missing.iter().map(|e| e.summary.as_deref().unwrap_or_default()).collect();
+    let events = missing.iter().map(|event| Ok(calendar::reinsertable(event))).collect();
+    let results = calendar::insert_events(&backend, calendar_id, &titles, events, args.jobs()).await;
+    let mut failures = Vec::new();
+    for ((index, title), result) in slots.into_iter().zip(titles).zip(results) {
+        journal.record(index, outcome(&result, |created| created.id.clone()));
+        if let Err(error) = result {
+            failures.push(EventFailure { title: title.to_string(), error });
+        }
+    }
+    tracing::info!("Restored {} events", missing.len() - failures.len());
+    if let Some(summary) = backend.request_summary() {
+        tracing::info!("{}", summary);
+    }
+    finish_run(&mut journal, &failures, Ok(()))
+}
+
+/// Write a source's events to an iCalendar file. Needs no Google Calendar access.
+pub async fn run_export_ics(
+    source: &dyn EventSource,
+    options: &RunOptions,
+    args: &ExportArgs,
+) -> Result<()> {
+    let events = load_events(source, options, &args.filter).await?;
+    ics::write_ics(&args.output, &events, options.convert.dst_policy)?;
+    tracing::info!("Exported {} events to {}", events.len(), args.output.display());
+    Ok(())
+}
+
+pub fn truncate(s: &str, max_len: usize) -> String {
+    if s.chars().count() <= max_len {
+        s.to_string()
+    } else {
+        let truncated: String = s.chars().take(max_len.saturating_sub(3)).collect();
+        format!("{}...", truncated)
+    }
+}
+
+fn filter_events(
+    events: Vec<CalendarEvent>,
+    start_date: Option<NaiveDate>,
+    end_date: Option<NaiveDate>,
+    purchased_only: bool,
+) -> Vec<CalendarEvent> {
+    let mut filtered: Vec<CalendarEvent> = events
+        .into_iter()
+        .filter(|e| e.occurs_between(start_date, end_date) && (!purchased_only || e.purchased))
+        .collect();
+    
+    // Sort by date and time
+    filtered.sort_by(|a, b| {
+        a.start_date.cmp(&b.start_date)
+            .then_with(|| a.start_time.cmp(&b.start_time))
+    });
+    
+    filtered
+}
+
+fn print_events(events: &[CalendarEvent], options: &ConvertOptions) {
+    println!("\n{:<40} {:<12} {:<8} {:<12} {:<8} {:<25}", 
+        "summary", "start.date", "start", "end.date", "end", "location");
+    println!("{}", "-".repeat(105));
+    for event in events {
+        // Line 1: summary, dates, times, location
+        println!("{:<40} {:<12} {:<8} {:<12} {:<8} {:<25}",
+            truncate(&event.title, 38),
+            event.start_date.format("%Y-%m-%d"),
+            event.start_time.map(|t| t.format("%H:%M").to_string()).unwrap_or_else(|| "all-day".to_string()),
+            event.end_date.format("%Y-%m-%d"),
+            event.end_time.map(|t| t.format("%H:%M").to_string()).unwrap_or_else(|| "all-day".to_string()),
+            event.location.as_deref().map(|l| truncate(l, 23)).unwrap_or_default(),
+        );
+        print_event_settings(event, options);
+        // Line 2: description (indented)
+        if let Some(desc) = &event.description {
+            let desc_preview = truncate(desc.replace('\n', " | ").as_str(), 100);
+            println!("  description: {}", desc_preview);
+        }
+    }
+}
+
+/// Indented lines for the settings a dry run would write besides times and place
+fn print_event_settings(event: &CalendarEvent, options: &ConvertOptions) {
+    if let Some(recurrence) = &event.recurrence {
+        println!("  repeats: {}", describe_recurrence(recurrence));
+    }
+    if let Some(reminders) = &event.reminders {
+        println!("  reminders: {}", describe_reminders(reminders));
+    }
+    if !event.attendees.is_empty() {
+        let invites: Vec<String> = event
+            .attendees
+            .iter()
+            .map(|a| if a.optional { format!("{} (optional)", a.email) } else { a.email.clone() })
+            .collect();
+        println!("  invites: {}", invites.join(", "));
+    }
+    if let Some(color_id) = options.color_for(event) {
+        println!("  color: {}", calendar::describe_color(color_id));
+    }
+}
+
+/// e.g. "FREQ=WEEKLY;BYDAY=TU (except 2026-03-10, 2026-03-17)"
+fn describe_recurrence(recurrence: &Recurrence) -> String {
+    if recurrence.exdates.is_empty() {
+        return recurrence.rule.clone();
+    }
+    let exdates: Vec<String> = recurrence.exdates.iter().map(|d| d.to_string()).collect();
+    format!("{} (except {})", recurrence.rule, exdates.join(", "))
+}
+
+/// e.g. "popup 2h before, email 1d before"
+fn describe_reminders(reminders: &Reminders) -> String {
+    if reminders.0.is_empty() {
+        return "none".to_string();
+    }
+    let parts: Vec<String> = reminders
+        .0
+        .iter()
+        .map(|r| {
+            let before = match r.minutes {
+                0 => "at start".to_string(),
+                m if m % 1440 == 0 => format!("{}d before", m / 1440),
+                m if m % 60 == 0 => format!("{}h before", m / 60),
+                m => format!("{}m before", m),
+            };
+            format!("{} {}", r.method.as_str(), before)
+        })
+        .collect();
+    parts.join(", ")
+}
+
+#[allow(clippy::print_literal, clippy::unnecessary_sort_by)]
+fn print_stats(events: &[CalendarEvent]) {
+    println!("\n{}", "=".repeat(60));
+    println!("STATISTICS");
+    println!("{}", "=".repeat(60));
+    
+    // Total events and purchased
+    let total_purchased = events.iter().filter(|e| e.purchased).count();
+    println!("\nTotal Events: {} ({} purchased)", events.len(), total_purchased);
+    
+    // Events by venue (total, purchased)
+    let mut by_venue: HashMap<String, (usize, usize)> = HashMap::new();
+    for event in events {
+        let venue = event.location.clone().unwrap_or_else(|| "(No venue)".to_string());
+        let entry = by_venue.entry(venue).or_insert((0, 0));
+        entry.0 += 1;
+        if event.purchased {
+            entry.1 += 1;
+        }
+    }
+    
+    println!("\nEvents by Venue:");
+    println!("{:<6} {:<6} {}", "Total", "Purch", "Venue");
+    println!("{:-<50}", "");
+    let mut venue_counts: Vec<_> = by_venue.into_iter().collect();
+    venue_counts.sort_by(|a, b| b.1.0.cmp(&a.1.0)); // Sort by total count descending
+    for (venue, (total, purchased)) in venue_counts {
+        println!("  {:>4} {:>6}  {}", total, purchased, venue);
+    }
+    
+    // Events by organization (total, purchased)
+    let mut by_org: HashMap<String, (usize, usize)> = HashMap::new();
+    for event in events {
+        let org = event.organization.clone().unwrap_or_else(|| "(No organization)".to_string());
+        let entry = by_org.entry(org).or_insert((0, 0));
+        entry.0 += 1;
+        if event.purchased {
+            entry.1 += 1;
+        }
+    }
+    
+    println!("\nEvents by Organization:");
+    println!("{:<6} {:<6} {}", "Total", "Purch", "Organization");
+    println!("{:-<50}", "");
+    let mut org_counts: Vec<_> = by_org.into_iter().collect();
+    org_counts.sort_by(|a, b| b.1.0.cmp(&a.1.0)); // Sort by total count descending
+    for (org, (total, purchased)) in org_counts {
+        println!("  {:>4} {:>6}  {}", total, purchased, org);
+    }
+    
+    println!();
+}
+
+fn print_sync_plan(plan: &sync::SyncPlan, options: &ConvertOptions) {
+    println!("\nSync plan: {} to create, {} to update, {} unchanged, {} to delete",
+        plan.create.len(), plan.update.len(), plan.unchanged.len(), plan.delete.len());
+    println!("{}", "=".repeat(80));
+    println!("{:<10} {:<40} {:<12} {:<15}", "ACTION", "TITLE", "DATE", "START");
+    println!("{}", "-".repeat(80));
+    for event in &plan.create {
+        println!("{:<10} {:<40} {:<12} {:<15}",
+            "create",
+            truncate(&event.title, 38),
+            event.start_date.format("%Y-%m-%d"),
+            event.start_time.map(|t| t.format("%H:%M").to_string()).unwrap_or_else(|| "all-day".to_string()),
+        );
+        print_event_settings(event, options);
+    }
+    for (event, _) in &plan.update {
+        println!("{:<10} {:<40} {:<12} {:<15}",
+            "update",
+            truncate(&event.title, 38),
+            event.start_date.format("%Y-%m-%d"),
+            event.start_time.map(|t| t.format("%H:%M").to_string()).unwrap_or_else(|| "all-day".to_string()),
+        );
+        print_event_settings(event, options);
+    }
+    for gcal in &plan.delete {
+        println!("{:<10} {:<40} {:<12} {:<15}",
+            "delete",
+            truncate(&gcal.title, 38),
+            gcal.date.format("%Y-%m-%d"),
+            "",
+        );
+    }
+    println!();
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::backend::{concert, MemoryCalendar};
+    use crate::journal::Status;
+    use async_trait::async_trait;
+
+    /// A source whose rows can't be written back to
+    struct ReadOnlySource;
+
+    #[async_trait]
+    impl EventSource for ReadOnlySource {
+        fn name(&self) -> String {
+            "csv:season.csv".to_string()
+        }
+
+        async fn fetch(&self, _options: &FetchOptions) -> Result<SourceEvents> {
+            Ok(SourceEvents::default())
+        }
+
+        async fn write_back(&self, synced: &[SyncedEvent]) -> Result<()> {
+            anyhow::ensure!(synced.is_empty(), "Coda returned HTTP 503");
+            Ok(())
+        }
+    }
+
+    #[test]
+    fn test_prune_rejects_row_filters() {
+        let mut filter = FilterArgs {
+            start_date: NaiveDate::from_ymd_opt(2026, 3, 1),
+            end_date: NaiveDate::from_ymd_opt(2026, 3, 31),
+            purchased: false,
+        };
+        assert!(check_prune(true, &filter).is_ok());
+        filter.purchased = true;
+        assert!(check_prune(false, &filter).is_ok());
+        let error = check_prune(true, &filter).unwrap_err();
+        assert!(error.to_string().contains("--purchased"));
+    }
+
+    #[tokio::test]
+    async fn test_resumed_run_that_fails_entirely_is_a_total_failure() {
+        let path = std::env::temp_dir().join(format!("calendar-sync-resume-{}.json", std::process::id()));
+        let events = vec![concert("Mahler 9", 14, Some("1")), concert("Piano Recital", 15, Some("2"))];
+        let mut journal = Journal::start("import", "csv:season.csv", "primary", Some(&path));
+        for event in &events {
+            journal.plan(Operation::create(event));
+        }
+        journal.record(0, Ok(Some("evt1".to_string())));
+        journal.record(1, Err("HTTP 500".to_string()));
+        journal.save().unwrap();
+
+        // Only the failed event is tried again, and it fails again
+        let backend = MemoryCalendar::new().failing_on("Piano Recital");
+        let mut resumed = Journal::resume(&path, "import", "primary").unwrap();
+        let error = import_events(&ReadOnlySource, &backend, &mut resumed, events, &ConvertOptions::default(), 1)
+            .await
+            .unwrap_err();
+        std::fs::remove_file(&path).unwrap();
+        assert_eq!(exit_code(&error), 3);
+        assert_eq!(error.to_string(), "All 1 changes failed");
+        assert_eq!(resumed.tally().done(), 1);
+    }
+
+    #[tokio::test]
+    async fn test_failed_write_back_still_journals_the_run() {
+        let dir = std::env::temp_dir().join(format!("calendar-sync-pipeline-{}", std::process::id()));
+        let backend = MemoryCalendar::new();
+        let events = vec![concert("Mahler 9", 14, Some("1")), concert("Piano Recital", 15, Some("2"))];
+
+        let path = dir.join("import.json");
+        let mut journal = Journal::start("import", "csv:season.csv", "primary", Some(&path));
+        let error = import_events(&ReadOnlySource, &backend, &mut journal, events.clone(), &ConvertOptions::default(), 2)
+            .await
+            .unwrap_err();
+        assert_eq!(exit_code(&error), 2);
+        assert!(format!("{}", error).contains("HTTP 503"));
+        let saved = Journal::load(&path).unwrap();
+        assert!(saved.operations.iter().all(|op| op.status == Status::Done && op.event_id.is_some()));
+
+        // The sync journal is saved too, so a resumed sync would not create them again
+        let mut moved = events[1].clone();
+        moved.location = Some("Herbst Theatre".to_string());
+        let new = concert("Opening Night", 16, Some("3"));
+        let plan = sync::plan_sync(&[events[0].clone(), moved, new], &backend.events("primary"), false, &ConvertOptions::default()).unwrap();
+        let path = dir.join("sync.json");
+        let mut journal = Journal::start("sync", "csv:season.csv", "primary", Some(&path));
+        let error = sync_events(&ReadOnlySource, &backend, &mut journal, plan, &ConvertOptions::default(), 2)
+            .await
+            .unwrap_err();
+        assert_eq!(exit_code(&error), 2);
+        let saved = Journal::load(&path).unwrap();
+        let statuses: Vec<(&str, Status)> = saved.operations.iter().map(|op| (op.title.as_str(), op.status)).collect();
+        assert_eq!(statuses, [("Opening Night", Status::Done), ("Piano Recital", Status::Done)]);
+        std::fs::remove_dir_all(dir).unwrap();
+    }
+}
@@ -0,0 +1,470 @@
+use anyhow::{Context, Result};
+use chrono::{Datelike, Duration, Months, NaiveDate, NaiveDateTime, NaiveTime, TimeZone, Weekday};
+use chrono_tz::Tz;
+use serde::{Deserialize, Serialize};
+
+/// Upper bound on recurrence periods walked for one rule, in case a rule never matches
+const MAX_PERIODS: u32 = 100_000;
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Frequency {
+    Daily,
+    Weekly,
+    Monthly,
+    Yearly,
+}
+
+/// The supported subset of an RFC 5545 RRULE: FREQ (daily to yearly), INTERVAL, COUNT, UNTIL,
+/// BYDAY, BYMONTHDAY and BYMONTH. Occurrences keep the start's wall-clock time.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct RRule {
+    pub freq: Frequency,
+    pub interval: u32,
+    pub count: Option<u32>,
+    /// Last allowed occurrence start, in the event's local time
+    pub until: Option<NaiveDateTime>,
+    /// Weekdays, optionally with an ordinal within the month/year (e.g. 2TU, -1FR)
+    pub by_day: Vec<(Option<i32>, Weekday)>,
+    pub by_month_day: Vec<i32>,
+    pub by_month: Vec<u32>,
+}
+
+impl RRule {
+    /// Parse an RRULE value such as `FREQ=WEEKLY;BYDAY=TU,TH;UNTIL=20261231`. `parse_until`
+    /// turns the UNTIL value into local time (it may be a date, or a UTC date-time).
+    pub fn parse(value: &str, parse_until: impl Fn(&str) -> Result<NaiveDateTime>) -> Result<Self> {
+        let mut freq = None;
+        let mut rule = RRule {
+            freq: Frequency::Daily,
+            interval: 1,
+            count: None,
+            until: None,
+            by_day: Vec::new(),
+            by_month_day: Vec::new(),
+            by_month: Vec::new(),
+        };
+
+        for part in value.split(';').filter(|p| !p.is_empty()) {
+            let (key, val) = part
+                .split_once('=')
+                .with_context(|| format!("Invalid RRULE part '{}'", part))?;
+            match key.to_ascii_uppercase().as_str() {
+                "FREQ" => {
+                    freq = Some(match val.to_ascii_uppercase().as_str() {
+                        "DAILY" => Frequency::Daily,
+                        "WEEKLY" => Frequency::Weekly,
+                        "MONTHLY" => Frequency::Monthly,
+                        "YEARLY" => Frequency::Yearly,
+                        other => anyhow::bail!("Unsupported RRULE frequency {}", other),
+                    })
+                }
+                "INTERVAL" => rule.interval = val.parse().with_context(|| format!("Invalid INTERVAL '{}'", val))?,
+                "COUNT" => rule.count = Some(val.parse().with_context(|| format!("Invalid COUNT '{}'", val))?),
+                "UNTIL" => rule.until = Some(parse_until(val)?),
+                "BYDAY" => {
+                    rule.by_day = val.split(',').map(parse_by_day).collect::<Result<_>>()?;
+                }
+                "BYMONTHDAY" => {
+                    rule.by_month_day = val
+                        .split(',')
+                        .map(|d| d.parse().with_context(|| format!("Invalid BYMONTHDAY '{}'", d)))
+                        .collect::<Result<_>>()?;
+                }
+                "BYMONTH" => {
+                    rule.by_month = val
+                        .split(',')
+                        .map(|m| m.parse().with_context(|| format!("Invalid BYMONTH '{}'", m)))
+                        .collect::<Result<_>>()?;
+                }
+                // Weeks always start on Monday here, which only matters for WEEKLY rules with
+                // INTERVAL > 1 and a different WKST
+                "WKST" => {}
+                other => anyhow::bail!("Unsupported RRULE part {}", other),
+            }
+        }
+
+        rule.freq = freq.context("RRULE is missing FREQ")?;
+        anyhow::ensure!(rule.interval > 0, "RRULE INTERVAL must be positive");
+        Ok(rule)
+    }
+
+    /// Occurrence starts from `start` (the first occurrence) up to and including `window_end`.
+    /// Occurrences before `window_start` still count towards COUNT but are not returned.
+    pub fn expand(&self, start: NaiveDateTime, window_start: NaiveDate, window_end: NaiveDate) -> Vec<NaiveDateTime> {
+        let time = start.time();
+        let mut occurrences = Vec::new();
+        let mut seen = 0;
+
+        for period in 0..MAX_PERIODS {
+            let Some(dates) = self.period_dates(start.date(), period) else {
+                break;
+            };
+            let Some(first) = dates.first().copied().or_else(|| self.period_start(start.date(), period)) else {
+                break;
+            };
+            if first > window_end || self.until.is_some_and(|u| first.and_time(time) > u) {
+                break;
+            }
+
+            for date in dates {
+                let occurrence = date.and_time(time);
+                if occurrence < start {
+                    continue;
+                }
+                if date > window_end
+                    || self.until.is_some_and(|u| occurrence > u)
+                    || self.count.is_some_and(|c| seen >= c)
+                {
+                    return occurrences;
+                }
+                seen += 1;
+                if date >= window_start {
+                    occurrences.push(occurrence);
+                }
+            }
+        }
+        occurrences
+    }
+
+    /// First day of the `period`-th period (day, week, month or year) after `start`
+    fn period_start(&self, start: NaiveDate, period: u32) -> Option<NaiveDate> {
+        let n = period.checked_mul(self.interval)?;
+        match self.freq {
+            Frequency::Daily => start.checked_add_signed(Duration::days(n as i64)),
+            Frequency::Weekly => {
+                let monday = start - Duration::days(start.weekday().num_days_from_monday() as i64);
+                monday.checked_add_signed(Duration::weeks(n as i64))
+            }
+            Frequency::Monthly => start.with_day(1)?.checked_add_months(Months::new(n)),
+            Frequency::Yearly => NaiveDate::from_ymd_opt(start.year() + n as i32, 1, 1),
+        }
+    }
+
+    /// Candidate dates in the `period`-th period, sorted, before COUNT/UNTIL are applied
+    fn period_dates(&self, start: NaiveDate, period: u32) -> Option<Vec<NaiveDate>> {
+        let first = self.period_start(start, period)?;
+        let mut dates: Vec<NaiveDate> = match self.freq {
+            Frequency::Daily => vec![first],
+            Frequency::Weekly => {
+                if self.by_day.is_empty() {
+                    vec![first + Duration::days(start.weekday().num_days_from_monday() as i64)]
+                } else {
+                    (0..7).map(|d| first + Duration::days(d)).collect()
+                }
+            }
+            Frequency::Monthly => self.month_dates(first.year(), first.month(), start),
+            Frequency::Yearly => {
+                let months: Vec<u32> = if !self.by_month.is_empty() {
+                    self.by_month.clone()
+                } else if self.by_day.is_empty() && self.by_month_day.is_empty() {
+                    vec![start.month()]
+                } else if self.by_month_day.is_empty() {
+                    // BYDAY without BYMONTH: ordinals count within the whole year
+                    return Some(self.filter(year_dates(first.year())));
+                } else {
+                    (1..=12).collect()
+                };
+                months
+                    .into_iter()
+                    .flat_map(|m| self.month_dates(first.year(), m, start))
+                    .collect()
+            }
+        };
+
+        dates = self.filter(dates);
+        dates.sort();
+        dates.dedup();
+        Some(dates)
+    }
+
+    /// Dates in one month matching BYMONTHDAY/BYDAY, or the start's day of month
+    fn month_dates(&self, year: i32, month: u32, start: NaiveDate) -> Vec<NaiveDate> {
+        let days = month_days(year, month);
+        if self.by_month_day.is_empty() && self.by_day.is_empty() {
+            return days.into_iter().filter(|d| d.day() == start.day()).collect();
+        }
+        days
+    }
+
+    /// Apply BYMONTH, BYMONTHDAY and BYDAY to a set of candidate dates
+    fn filter(&self, dates: Vec<NaiveDate>) -> Vec<NaiveDate> {
+        dates
+            .into_iter()
+            .filter(|d| self.by_month.is_empty() || self.by_month.contains(&d.month()))
+            .filter(|d| {
+                self.by_month_day.is_empty()
+                    || self.by_month_day.iter().any(|&md| {
+                        // Negative days count back from the end of the month (-1 is the last day)
+                        md == d.day() as i32 || md == d.day() as i32 - days_in_month(d) - 1
+                    })
+            })
+            .filter(|d| {
+                self.by_day.is_empty()
+                    || self.by_day.iter().any(|&(ordinal, weekday)| {
+                        d.weekday() == weekday
+                            && ordinal.is_none_or(|n| self.ordinals(*d).contains(&n))
+                    })
+            })
+            .collect()
+    }
+
+    /// Position of `date` among same-weekday dates in its month (or year, for yearly rules
+    /// without BYMONTH), counted from the start (1, 2, ...) and from the end (-1, -2, ...)
+    fn ordinals(&self, date: NaiveDate) -> [i32; 2] {
+        let scope = if self.freq == Frequency::Yearly && self.by_month.is_empty() {
+            year_dates(date.year())
+        } else {
+            month_days(date.year(), date.month())
+        };
+        let same_weekday: Vec<NaiveDate> = scope.into_iter().filter(|d| d.weekday() == date.weekday()).collect();
+        let index = same_weekday.iter().position(|d| *d == date).unwrap_or(0) as i32;
+        [index + 1, index - same_weekday.len() as i32]
+    }
+}
+
+/// How a source event repeats: an RRULE plus the dates whose occurrence is skipped (EXDATE).
+/// Occurrences happen at the event's start time, in the event's time zone.
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+pub struct Recurrence {
+    /// RRULE value without the "RRULE:" prefix, e.g. "FREQ=WEEKLY;BYDAY=TU;UNTIL=20261215"
+    pub rule: String,
+    pub exdates: Vec<NaiveDate>,
+}
+
+impl Recurrence {
+    /// Parse a recurrence column ("FREQ=WEEKLY;BYDAY=TU", with or without the "RRULE:" prefix)
+    /// and an optional list of skipped dates separated by commas, semicolons or spaces
+    pub fn parse(rule: &str, exdates: Option<&str>) -> Result<Self> {
+        let rule = rule.trim();
+        let rule = rule
+            .get(..6)
+            .filter(|prefix| prefix.eq_ignore_ascii_case("RRULE:"))
+            .map_or(rule, |_| &rule[6..])
+            .trim()
+            .to_ascii_uppercase();
+        let recurrence = Recurrence {
+            rule,
+            exdates: exdates
+                .unwrap_or_default()
+                .split(|c: char| c == ',' || c == ';' || c.is_whitespace())
+                .filter(|d| !d.is_empty())
+                .map(|d| {
+                    NaiveDate::parse_from_str(d, "%Y-%m-%d")
+                        .or_else(|_| NaiveDate::parse_from_str(d, "%Y%m%d"))
+                        .with_context(|| format!("Invalid exdate '{}' (expected YYYY-MM-DD)", d))
+                })
+                .collect::<Result<_>>()?,
+        };
+        recurrence.rrule(chrono_tz::UTC)?;
+        Ok(recurrence)
+    }
+
+    /// The parsed rule, with a UTC UNTIL converted to local time in `tz`
+    pub fn rrule(&self, tz: Tz) -> Result<RRule> {
+        RRule::parse(&self.rule, |until| {
+            let (until, utc) = match until.strip_suffix('Z') {
+                Some(until) => (until, true),
+                None => (until, false),
+            };
+            if let Ok(date) = NaiveDate::parse_from_str(until, "%Y%m%d") {
+                // A date UNTIL includes every occurrence on that day
+                return Ok(date.and_time(NaiveTime::from_hms_opt(23, 59, 59).unwrap()));
+            }
+            let datetime = NaiveDateTime::parse_from_str(until, "%Y%m%dT%H%M%S")
+                .with_context(|| format!("Invalid UNTIL '{}'", until))?;
+            Ok(if utc { tz.from_utc_datetime(&datetime).naive_local() } else { datetime })
+        })
+        .with_context(|| format!("Invalid recurrence rule '{}'", self.rule))
+    }
+
+    /// Occurrence starts on dates in `[window_start, window_end]`, without the skipped dates
+    pub fn occurrences(
+        &self,
+        start: NaiveDateTime,
+        tz: Tz,
+        window_start: NaiveDate,
+        window_end: NaiveDate,
+    ) -> Vec<NaiveDateTime> {
+        let Ok(rule) = self.rrule(tz) else {
+            return Vec::new();
+        };
+        rule.expand(start, window_start, window_end)
+            .into_iter()
+            .filter(|at| !self.exdates.contains(&at.date()))
+            .collect()
+    }
+
+    /// RRULE and EXDATE content lines, as used by Google Calendar's `recurrence` field and in
+    /// iCalendar files. `start_time` is `None` for all-day events. For timed events a date
+    /// UNTIL is rewritten as the end of that day in UTC, which RFC 5545 requires.
+    pub fn to_lines(&self, start_time: Option<NaiveTime>, tz: Tz) -> Vec<String> {
+        let rule: Vec<String> = self
+            .rule
+            .split(';')
+            .map(|part| match (part.strip_prefix("UNTIL="), start_time) {
+                (Some(until), Some(_)) if until.len() == 8 => {
+                    let end_of_day = NaiveDate::parse_from_str(until, "%Y%m%d")
+                        .ok()
+                        .and_then(|d| d.and_hms_opt(23, 59, 59))
+                        .and_then(|dt| tz.from_local_datetime(&dt).latest());
+                    match end_of_day {
+                        Some(dt) => format!("UNTIL={}", dt.naive_utc().format("%Y%m%dT%H%M%SZ")),
+                        None => part.to_string(),
+                    }
+                }
+                _ => part.to_string(),
+            })
+            .collect();
+        let mut lines = vec![format!("RRULE:{}", rule.join(";"))];
+
+        if !self.exdates.is_empty() {
+            lines.push(match start_time {
+                Some(time) => {
+                    let values: Vec<String> = self
+                        .exdates
+                        .iter()
+                        .map(|d| d.and_time(time).format("%Y%m%dT%H%M%S").to_string())
+                        .collect();
+                    format!("EXDATE;TZID={}:{}", tz.name(), values.join(","))
+                }
+                None => {
+                    let values: Vec<String> = self.exdates.iter().map(|d| d.format("%Y%m%d").to_string()).collect();
+                    format!("EXDATE;VALUE=DATE:{}", values.join(","))
+                }
+            });
+        }
+        lines
+    }
+}
+
+fn parse_by_day(value: &str) -> Result<(Option<i32>, Weekday)> {
+    let value = value.trim().to_ascii_uppercase();
+    anyhow::ensure!(value.len() >= 2, "Invalid BYDAY '{}'", value);
+    let (ordinal, day) = value.split_at(value.len() - 2);
+    let weekday = match day {
+        "MO" => Weekday::Mon,
+        "TU" => Weekday::Tue,
+        "WE" => Weekday::Wed,
+        "TH" => Weekday::Thu,
+        "FR" => Weekday::Fri,
+        "SA" => Weekday::Sat,
+        "SU" => Weekday::Sun,
+        _ => anyhow::bail!("Invalid BYDAY '{}'", value),
+    };
+    let ordinal = match ordinal {
+        "" => None,
+        n => Some(n.trim_start_matches('+').parse().with_context(|| format!("Invalid BYDAY '{}'", value))?),
+    };
+    Ok((ordinal, weekday))
+}
+
+fn month_days(year: i32, month: u32) -> Vec<NaiveDate> {
+    let Some(first) = NaiveDate::from_ymd_opt(year, month, 1) else {
+        return Vec::new();
+    };
+    first.iter_days().take_while(|d| d.month() == month).collect()
+}
+
+fn year_dates(year: i32) -> Vec<NaiveDate> {
+    let Some(first) = NaiveDate::from_ymd_opt(year, 1, 1) else {
+        return Vec::new();
+    };
+    first.iter_days().take_while(|d| d.year() == year).collect()
+}
+
+fn days_in_month(date: &NaiveDate) -> i32 {
+    month_days(date.year(), date.month()).len() as i32
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn rule(value: &str) -> RRule {
+        RRule::parse(value, |until| {
+            Ok(NaiveDate::parse_from_str(until, "%Y%m%d")?.and_hms_opt(23, 59, 59).unwrap())
+        })
+        .unwrap()
+    }
+
+    fn at(date: &str, time: &str) -> NaiveDateTime {
+        NaiveDateTime::parse_from_str(&format!("{} {}", date, time), "%Y-%m-%d %H:%M").unwrap()
+    }
+
+    fn dates(occurrences: Vec<NaiveDateTime>) -> Vec<String> {
+        occurrences.iter().map(|o| o.format("%Y-%m-%d").to_string()).collect()
+    }
+
+    #[test]
+    fn test_weekly_by_day_with_count() {
+        let start = at("2026-03-03", "19:30"); // a Tuesday
+        let occurrences = rule("FREQ=WEEKLY;BYDAY=TU,TH;COUNT=5").expand(start, NaiveDate::MIN, NaiveDate::MAX);
+        assert_eq!(
+            dates(occurrences.clone()),
+            ["2026-03-03", "2026-03-05", "2026-03-10", "2026-03-12", "2026-03-17"]
+        );
+        assert!(occurrences.iter().all(|o| o.time() == start.time()));
+    }
+
+    #[test]
+    fn test_monthly_ordinal_weekdays_until() {
+        let start = at("2026-01-13", "20:00"); // second Tuesday
+        let occurrences = rule("FREQ=MONTHLY;BYDAY=2TU;UNTIL=20260414").expand(start, NaiveDate::MIN, NaiveDate::MAX);
+        assert_eq!(dates(occurrences), ["2026-01-13", "2026-02-10", "2026-03-10", "2026-04-14"]);
+
+        let start = at("2026-01-30", "20:00");
+        let occurrences = rule("FREQ=MONTHLY;BYDAY=-1FR;COUNT=3").expand(start, NaiveDate::MIN, NaiveDate::MAX);
+        assert_eq!(dates(occurrences), ["2026-01-30", "2026-02-27", "2026-03-27"]);
+    }
+
+    #[test]
+    fn test_last_day_of_month_and_window() {
+        let start = at("2026-01-31", "09:00");
+        let window_start = NaiveDate::from_ymd_opt(2026, 2, 1).unwrap();
+        let window_end = NaiveDate::from_ymd_opt(2026, 4, 30).unwrap();
+        let occurrences = rule("FREQ=MONTHLY;BYMONTHDAY=-1").expand(start, window_start, window_end);
+        assert_eq!(dates(occurrences), ["2026-02-28", "2026-03-31", "2026-04-30"]);
+
+        // Occurrences before the window still use up COUNT
+        let occurrences = rule("FREQ=DAILY;INTERVAL=10;COUNT=3").expand(start, window_start, window_end);
+        assert_eq!(dates(occurrences), ["2026-02-10", "2026-02-20"]);
+    }
+
+    #[test]
+    fn test_parse_rejects_unsupported_parts() {
+        let parse = |value| RRule::parse(value, |_| unreachable!());
+        assert!(parse("FREQ=HOURLY").is_err());
+        assert!(parse("FREQ=WEEKLY;BYSETPOS=1").is_err());
+        assert!(parse("BYDAY=MO").is_err());
+        assert!(parse("FREQ=YEARLY;WKST=SU").is_ok());
+    }
+
+    #[test]
+    fn test_recurrence_column_to_lines() {
+        let recurrence = Recurrence::parse("rrule:freq=weekly;byday=tu;until=20260331", Some("2026-03-10, 2026-03-17")).unwrap();
+        assert_eq!(recurrence.rule, "FREQ=WEEKLY;BYDAY=TU;UNTIL=20260331");
+
+        // Timed: UNTIL becomes the end of that day in UTC, EXDATEs carry the start time
+        let lines = recurrence.to_lines(NaiveTime::from_hms_opt(19, 30, 0), chrono_tz::America::Los_Angeles);
+        assert_eq!(
+            lines,
+            [
+                "RRULE:FREQ=WEEKLY;BYDAY=TU;UNTIL=20260401T065959Z",
+                "EXDATE;TZID=America/Los_Angeles:20260310T193000,20260317T193000",
+            ]
+        );
+
+        let lines = recurrence.to_lines(None, chrono_tz::America::Los_Angeles);
+        assert_eq!(
+            lines,
+            ["RRULE:FREQ=WEEKLY;BYDAY=TU;UNTIL=20260331", "EXDATE;VALUE=DATE:20260310,20260317"]
+        );
+
+        let start = at("2026-03-03", "19:30");
+        let occurrences = recurrence.occurrences(start, chrono_tz::America::Los_Angeles, NaiveDate::MIN, NaiveDate::MAX);
+        assert_eq!(dates(occurrences), ["2026-03-03", "2026-03-24", "2026-03-31"]);
+
+        assert!(Recurrence::parse("FREQ=FORTNIGHTLY", None).is_err());
+        assert!(Recurrence::parse("FREQ=DAILY", Some("March 3")).is_err());
+    }
+}
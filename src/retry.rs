@@ -0,0 +1,417 @@
+//! Retries and pacing for API requests, shared by the Google Calendar and Coda clients.
+//!
+//! Requests that fail with 429, a 5xx status or a connection error are retried with
+//! exponential backoff and jitter, waiting for the server's `Retry-After` instead when it sends
+//! one. A `RateLimiter` spaces requests out so large imports stay under Google's quota.
+
+use std::collections::hash_map::RandomState;
+use std::hash::{BuildHasher, Hasher};
+use std::sync::atomic::{AtomicU64, AtomicUsize, Ordering};
+use std::sync::Mutex;
+use std::time::Duration;
+
+use google_calendar3::client::Retry;
+use google_calendar3::hyper;
+use tokio::time::Instant;
+
+/// Google Calendar allows 600 requests a minute per user by default; stay well below it
+pub const DEFAULT_RATE_LIMIT: f64 = 5.0;
+pub const DEFAULT_MAX_RETRIES: u32 = 5;
+
+/// The longest `Retry-After` we are willing to wait for
+const MAX_RETRY_AFTER: Duration = Duration::from_secs(300);
+
+/// `--max-retries` and `--rate-limit`
+#[derive(Debug, Clone, Copy)]
+pub struct RequestOptions {
+    pub retry: RetryPolicy,
+    /// Google Calendar requests per second
+    pub rate_limit: f64,
+}
+
+impl Default for RequestOptions {
+    fn default() -> Self {
+        Self { retry: RetryPolicy::default(), rate_limit: DEFAULT_RATE_LIMIT }
+    }
+}
+
+/// How often and how patiently to retry a failed request
+#[derive(Debug, Clone, Copy)]
+pub struct RetryPolicy {
+    /// Retries after the first attempt; 0 disables retrying
+    pub max_retries: u32,
+    /// Delay before the first retry; doubled for each one after
+    pub base_delay: Duration,
+    /// Upper bound on the backoff delay (a server's `Retry-After` may exceed it)
+    pub max_delay: Duration,
+}
+
+impl Default for RetryPolicy {
+    fn default() -> Self {
+        Self {
+            max_retries: DEFAULT_MAX_RETRIES,
+            base_delay: Duration::from_millis(500),
+            max_delay: Duration::from_secs(30),
+        }
+    }
+}
+
+impl RetryPolicy {
+    /// How long to wait before retry number `attempt` (1-based). A server-given `Retry-After`
+    /// wins; otherwise the exponential backoff ceiling is scaled by a random factor
+    /// ("full jitter") so parallel clients don't retry in lockstep.
+    pub fn delay(&self, attempt: u32, retry_after: Option<Duration>) -> Duration {
+        if let Some(retry_after) = retry_after {
+            return retry_after.min(MAX_RETRY_AFTER);
+        }
+        let ceiling = self
+            .base_delay
+            .saturating_mul(2u32.saturating_pow(attempt.saturating_sub(1)))
+            .min(self.max_delay);
+        ceiling.mul_f64(0.5 + jitter() / 2.0)
+    }
+}
+
+/// A random fraction in [0, 1), without pulling in a random number crate: every
+/// `RandomState` is freshly keyed, so hashing nothing still gives an unpredictable value
+fn jitter() -> f64 {
+    (RandomState::new().build_hasher().finish() >> 11) as f64 / (1u64 << 53) as f64
+}
+
+/// Whether a response status is worth retrying: rate limiting or a server-side failure
+pub fn is_retryable_status(status: u16) -> bool {
+    status == 429 || (500..600).contains(&status)
+}
+
+/// Google reports quota exhaustion as 403 with a `rateLimitExceeded` or
+/// `userRateLimitExceeded` reason rather than 429
+fn is_google_rate_limit(error: Option<&serde_json::Value>) -> bool {
+    let Some(errors) = error.and_then(|e| e.pointer("/error/errors")).and_then(|e| e.as_array()) else {
+        return false;
+    };
+    errors.iter().any(|e| {
+        matches!(
+            e.get("reason").and_then(|r| r.as_str()),
+            Some("rateLimitExceeded" | "userRateLimitExceeded")
+        )
+    })
+}
+
+/// Whether a response is worth retrying, given its status and (JSON error) body
+pub fn is_retryable_response(status: u16, body: &str) -> bool {
+    is_retryable_status(status)
+        || (status == 403 && is_google_rate_limit(serde_json::from_str(body).ok().as_ref()))
+}
+
+/// Parse a `Retry-After` header: either seconds or an HTTP date
+pub fn parse_retry_after(value: &str) -> Option<Duration> {
+    let value = value.trim();
+    if let Ok(seconds) = value.parse::<u64>() {
+        return Some(Duration::from_secs(seconds));
+    }
+    let when = chrono::DateTime::parse_from_rfc2822(value).ok()?;
+    Some((when.with_timezone(&chrono::Utc) - chrono::Utc::now()).to_std().unwrap_or_default())
+}
+
+/// Spaces requests at least `1 / requests_per_second` apart
+#[derive(Debug)]
+pub struct RateLimiter {
+    interval: Duration,
+    next: Mutex<Instant>,
+}
+
+impl RateLimiter {
+    pub fn new(requests_per_second: f64) -> Self {
+        Self {
+            interval: Duration::from_secs_f64(1.0 / requests_per_second),
+            next: Mutex::new(Instant::now()),
+        }
+    }
+
+    /// Wait for the next free slot; returns how long that took
+    pub async fn acquire(&self) -> Duration {
+        let wait = self.reserve(Duration::ZERO);
+        if !wait.is_zero() {
+            tokio::time::sleep(wait).await;
+        }
+        wait
+    }
+
+    /// Book the first free slot at least `after` from now, returning how much longer than
+    /// `after` the request has to wait for it
+    pub fn reserve(&self, after: Duration) -> Duration {
+        let mut next = self.next.lock().unwrap();
+        let earliest = Instant::now() + after;
+        let wait = next.saturating_duration_since(earliest);
+        *next = earliest.max(*next) + self.interval;
+        wait
+    }
+}
+
+/// Retries and rate limiting for every request to one API, with counts for the run summary
+#[derive(Debug)]
+pub struct RequestPolicy {
+    /// API name used in log messages, e.g. "Google Calendar"
+    api: &'static str,
+    retry: RetryPolicy,
+    limiter: Option<RateLimiter>,
+    retries: AtomicUsize,
+    throttled: AtomicUsize,
+    throttled_ms: AtomicU64,
+}
+
+impl RequestPolicy {
+    pub fn new(api: &'static str, retry: RetryPolicy, requests_per_second: Option<f64>) -> Self {
+        Self {
+            api,
+            retry,
+            limiter: requests_per_second.map(RateLimiter::new),
+            retries: AtomicUsize::new(0),
+            throttled: AtomicUsize::new(0),
+            throttled_ms: AtomicU64::new(0),
+        }
+    }
+
+    /// Wait for the rate limiter, if there is one. Call before each request.
+    pub async fn throttle(&self) {
+        let Some(limiter) = &self.limiter else {
+            return;
+        };
+        let waited = limiter.acquire().await;
+        self.count_throttled(waited);
+    }
+
+    /// For retries that the generated Google client sleeps through itself: book a rate
+    /// limiter slot for a request re-sent after `delay`, and return the delay that reaches it
+    fn throttle_after(&self, delay: Duration) -> Duration {
+        let Some(limiter) = &self.limiter else {
+            return delay;
+        };
+        let waited = limiter.reserve(delay);
+        self.count_throttled(waited);
+        delay + waited
+    }
+
+    fn count_throttled(&self, waited: Duration) {
+        if !waited.is_zero() {
+            tracing::debug!("{}: throttled for {:.2}s", self.api, waited.as_secs_f64());
+            self.throttled.fetch_add(1, Ordering::Relaxed);
+            self.throttled_ms.fetch_add(waited.as_millis() as u64, Ordering::Relaxed);
+        }
+    }
+
+    /// Decide whether attempt `attempt` (1-based) may be retried after `reason`, logging and
+    /// counting the retry if so
+    pub fn retry_after(&self, attempt: u32, reason: &str, retry_after: Option<Duration>) -> Option<Duration> {
+        if attempt > self.retry.max_retries {
+            return None;
+        }
+        let delay = self.retry.delay(attempt, retry_after);
+        tracing::warn!(
+            "{}: {}; retrying in {:.1}s (retry {}/{})",
+            self.api,
+            reason,
+            delay.as_secs_f64(),
+            attempt,
+            self.retry.max_retries
+        );
+        self.retries.fetch_add(1, Ordering::Relaxed);
+        Some(delay)
+    }
+
+    /// Send a reqwest request, retrying failures the policy allows. `build` is called once per
+    /// attempt. The final response is returned whatever its status.
+    pub async fn send(
+        &self,
+        build: impl Fn() -> reqwest::RequestBuilder,
+    ) -> reqwest::Result<reqwest::Response> {
+        let mut attempt = 0;
+        loop {
+            attempt += 1;
+            self.throttle().await;
+            let (reason, retry_after) = match build().send().await {
+                Ok(response) if is_retryable_status(response.status().as_u16()) => {
+                    let retry_after = response
+                        .headers()
+                        .get(reqwest::header::RETRY_AFTER)
+                        .and_then(|v| v.to_str().ok())
+                        .and_then(parse_retry_after);
+                    match self.retry_after(attempt, &format!("HTTP {}", response.status()), retry_after) {
+                        Some(delay) => {
+                            tokio::time::sleep(delay).await;
+                            continue;
+                        }
+                        None => return Ok(response),
+                    }
+                }
+                Ok(response) => return Ok(response),
+                Err(e) if e.is_connect() || e.is_timeout() || e.is_request() => (e, None),
+                Err(e) => return Err(e),
+            };
+            match self.retry_after(attempt, &reason.to_string(), retry_after) {
+                Some(delay) => tokio::time::sleep(delay).await,
+                None => return Err(reason),
+            }
+        }
+    }
+
+    /// A delegate that makes a generated Google API call retry by this policy; pass it to the
+    /// call's `.delegate(...)`. Use a fresh one per call.
+    pub fn delegate(&self) -> GoogleRetry<'_> {
+        GoogleRetry { policy: self, attempt: 0 }
+    }
+
+    /// E.g. "3 retries, throttled 12 times (4.5s)", or `None` if neither happened
+    pub fn summary(&self) -> Option<String> {
+        let retries = self.retries.load(Ordering::Relaxed);
+        let throttled = self.throttled.load(Ordering::Relaxed);
+        let mut parts = Vec::new();
+        if retries > 0 {
+            parts.push(format!("{} {}", retries, if retries == 1 { "retry" } else { "retries" }));
+        }
+        if throttled > 0 {
+            let seconds = self.throttled_ms.load(Ordering::Relaxed) as f64 / 1000.0;
+            parts.push(format!("throttled {} times ({:.1}s)", throttled, seconds));
+        }
+        (!parts.is_empty()).then(|| format!("{}: {}", self.api, parts.join(", ")))
+    }
+}
+
+/// Retry hook for the generated Google API calls, which sleep and re-send when their
+/// delegate answers `Retry::After`. The delay it answers includes the wait for the rate
+/// limiter, so retries are throttled like first attempts.
+pub struct GoogleRetry<'a> {
+    policy: &'a RequestPolicy,
+    attempt: u32,
+}
+
+impl google_calendar3::Delegate for GoogleRetry<'_> {
+    fn http_error(&mut self, err: &hyper::Error) -> Retry {
+        self.attempt += 1;
+        match self.policy.retry_after(self.attempt, &err.to_string(), None) {
+            Some(delay) => Retry::After(self.policy.throttle_after(delay)),
+            None => Retry::Abort,
+        }
+    }
+
+    fn http_failure(&mut self, response: &hyper::Response<hyper::body::Body>, err: Option<serde_json::Value>) -> Retry {
+        let status = response.status().as_u16();
+        let rate_limited = status == 403 && is_google_rate_limit(err.as_ref());
+        if !is_retryable_status(status) && !rate_limited {
+            return Retry::Abort;
+        }
+        let retry_after = response
+            .headers()
+            .get(hyper::header::RETRY_AFTER)
+            .and_then(|v| v.to_str().ok())
+            .and_then(parse_retry_after);
+        self.attempt += 1;
+        match self.policy.retry_after(self.attempt, &format!("HTTP {}", response.status()), retry_after) {
+            Some(delay) => Retry::After(self.policy.throttle_after(delay)),
+            None => Retry::Abort,
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_delay_backs_off_and_honors_retry_after() {
+        let policy = RetryPolicy::default();
+        for attempt in 1..=8 {
+            let ceiling = (policy.base_delay * 2u32.pow(attempt - 1)).min(policy.max_delay);
+            let delay = policy.delay(attempt, None);
+            assert!(delay >= ceiling / 2 && delay <= ceiling, "attempt {}: {:?}", attempt, delay);
+        }
+        assert_eq!(policy.delay(1, Some(Duration::from_secs(7))), Duration::from_secs(7));
+        assert_eq!(policy.delay(1, Some(Duration::from_secs(3600))), MAX_RETRY_AFTER);
+    }
+
+    #[test]
+    fn test_parse_retry_after_and_statuses() {
+        assert_eq!(parse_retry_after(" 12 "), Some(Duration::from_secs(12)));
+        assert_eq!(parse_retry_after("Wed, 21 Oct 2015 07:28:00 GMT"), Some(Duration::ZERO));
+        assert_eq!(parse_retry_after("soon"), None);
+
+        assert!(is_retryable_status(429) && is_retryable_status(503));
+        assert!(!is_retryable_status(404) && !is_retryable_status(403));
+        let quota = serde_json::json!({"error": {"code": 403, "errors": [{"reason": "rateLimitExceeded"}]}});
+        let forbidden = serde_json::json!({"error": {"code": 403, "errors": [{"reason": "forbidden"}]}});
+        assert!(is_google_rate_limit(Some(&quota)));
+        assert!(!is_google_rate_limit(Some(&forbidden)));
+        assert!(is_retryable_response(403, &quota.to_string()));
+        assert!(!is_retryable_response(403, &forbidden.to_string()) && !is_retryable_response(403, "<html>"));
+    }
+
+    #[tokio::test]
+    async fn test_rate_limiter_spaces_requests() {
+        let limiter = RateLimiter::new(100.0);
+        let start = Instant::now();
+        for _ in 0..5 {
+            limiter.acquire().await;
+        }
+        assert!(start.elapsed() >= Duration::from_millis(40));
+    }
+
+    #[tokio::test]
+    async fn test_google_retries_wait_for_the_rate_limiter() {
+        use google_calendar3::Delegate;
+
+        let policy = RequestPolicy::new("Test", RetryPolicy::default(), Some(10.0));
+        policy.throttle().await;
+        let limited = || {
+            hyper::Response::builder()
+                .status(429)
+                .header(hyper::header::RETRY_AFTER, "0")
+                .body(hyper::body::Body::empty())
+                .unwrap()
+        };
+
+        // Each retry waits for its own slot, 100ms after the one before
+        let mut delegate = policy.delegate();
+        for slot in 1..=2 {
+            match delegate.http_failure(&limited(), None) {
+                Retry::After(delay) => {
+                    let expected = Duration::from_millis(100 * slot);
+                    assert!(delay > expected - Duration::from_millis(20) && delay <= expected, "{:?}", delay);
+                }
+                Retry::Abort => panic!("429 should be retried"),
+            }
+        }
+        assert!(policy.summary().unwrap().contains("throttled 2 times"));
+    }
+
+    #[tokio::test]
+    async fn test_send_retries_until_success() {
+        use tokio::io::{AsyncReadExt, AsyncWriteExt};
+
+        let listener = tokio::net::TcpListener::bind("127.0.0.1:0").await.unwrap();
+        let url = format!("http://{}/rows", listener.local_addr().unwrap());
+        tokio::spawn(async move {
+            let responses = [
+                "HTTP/1.1 429 Too Many Requests\r\nRetry-After: 0\r\nContent-Length: 0\r\nConnection: close\r\n\r\n",
+                "HTTP/1.1 503 Service Unavailable\r\nContent-Length: 0\r\nConnection: close\r\n\r\n",
+                "HTTP/1.1 200 OK\r\nContent-Length: 2\r\nConnection: close\r\n\r\n{}",
+            ];
+            for response in responses {
+                let (mut socket, _) = listener.accept().await.unwrap();
+                let mut buf = vec![0u8; 8192];
+                let _request = socket.read(&mut buf).await.unwrap();
+                socket.write_all(response.as_bytes()).await.unwrap();
+            }
+        });
+
+        let fast = RetryPolicy { base_delay: Duration::from_millis(1), ..Default::default() };
+        let policy = RequestPolicy::new("Test", fast, None);
+        let client = reqwest::Client::new();
+        let response = policy.send(|| client.get(&url)).await.unwrap();
+        assert_eq!(response.status(), 200);
+        assert_eq!(policy.summary().as_deref(), Some("Test: 2 retries"));
+
+        let policy = RequestPolicy::new("Test", RetryPolicy { max_retries: 0, ..fast }, None);
+        assert!(policy.send(|| client.get(&url)).await.is_err());
+        assert!(policy.summary().is_none());
+    }
+}
@@ -0,0 +1,279 @@
+use anyhow::{Context, Result};
+use async_trait::async_trait;
+use chrono_tz::Tz;
+use csv::StringRecord;
+use serde::Deserialize;
+
+use crate::calendar::{self, AuthPaths};
+use crate::csv_parser::{self, CsvHeaders};
+use crate::event::ContentKeys;
+use crate::mapping::CsvMapping;
+use crate::source::{EventSource, FetchOptions, RowDiagnostic, SourceEvents};
+
+const SHEETS_API_BASE: &str = "https://sheets.googleapis.com";
+pub const SHEETS_SCOPE: &str = "https://www.googleapis.com/auth/spreadsheets.readonly";
+
+#[derive(Debug, Deserialize)]
+struct Spreadsheet {
+    properties: SpreadsheetProperties,
+}
+
+#[derive(Debug, Deserialize)]
+struct SpreadsheetProperties {
+    #[serde(rename = "timeZone")]
+    time_zone: Option<String>,
+}
+
+#[derive(Debug, Deserialize)]
+struct ValueRange {
+    #[serde(default)]
+    values: Vec<Vec<serde_json::Value>>,
+}
+
+pub struct SheetsClient {
+    client: reqwest::Client,
+    access_token: String,
+    base_url: String,
+}
+
+impl SheetsClient {
+    /// Create a client for the Sheets v4 API. The base URL can be overridden with
+    /// `GOOGLE_SHEETS_API_BASE` (e.g. to point at a local mock server).
+    pub fn new(access_token: String) -> Self {
+        let base_url = std::env::var("GOOGLE_SHEETS_API_BASE")
+            .unwrap_or_else(|_| SHEETS_API_BASE.to_string());
+        Self::with_base_url(access_token, base_url)
+    }
+
+    pub fn with_base_url(access_token: String, base_url: String) -> Self {
+        Self {
+            client: reqwest::Client::new(),
+            access_token,
+            base_url,
+        }
+    }
+
+    fn url(&self, segments: &[&str]) -> Result<reqwest::Url> {
+        let mut url = reqwest::Url::parse(&self.base_url)
+            .with_context(|| format!("Invalid Sheets API base URL: {}", self.base_url))?;
+        url.path_segments_mut()
+            .map_err(|_| anyhow::anyhow!("Invalid Sheets API base URL: {}", self.base_url))?
+            .pop_if_empty()
+            .extend(["v4", "spreadsheets"])
+            .extend(segments);
+        Ok(url)
+    }
+
+    async fn get<T: serde::de::DeserializeOwned>(&self, url: reqwest::Url) -> Result<T> {
+        let response = self
+            .client
+            .get(url)
+            .bearer_auth(&self.access_token)
+            .send()
+            .await
+            .context("Failed to fetch from Google Sheets")?;
+
+        if !response.status().is_success() {
+            let status = response.status();
+            let body = response.text().await.unwrap_or_default();
+            anyhow::bail!("Sheets API error ({}): {}", status, body);
+        }
+
+        response.json().await.context("Failed to parse Sheets response")
+    }
+
+    /// The spreadsheet's own time zone setting (File > Settings), if it has one
+    pub async fn get_time_zone(&self, spreadsheet_id: &str) -> Result<Option<Tz>> {
+        let mut url = self.url(&[spreadsheet_id])?;
+        url.query_pairs_mut().append_pair("fields", "properties.timeZone");
+        let spreadsheet: Spreadsheet = self.get(url).await?;
+        Ok(spreadsheet.properties.time_zone.and_then(|tz| tz.parse().ok()))
+    }
+
+    /// Read a range (e.g. "Sheet1" or "Season!A1:H200") as rows of display strings
+    pub async fn get_values(&self, spreadsheet_id: &str, range: &str) -> Result<Vec<Vec<String>>> {
+        let url = self.url(&[spreadsheet_id, "values", range])?;
+        let value_range: ValueRange = self.get(url).await?;
+
+        Ok(value_range
+            .values
+            .into_iter()
+            .map(|row| {
+                row.into_iter()
+                    .map(|v| match v {
+                        serde_json::Value::String(s) => s,
+                        serde_json::Value::Null => String::new(),
+                        other => other.to_string(),
+                    })
+                    .collect()
+            })
+            .collect())
+    }
+
+    /// Read events from a range whose first row is a header, mapping columns the same way as
+    /// CSV files. Rows without a `timezone` value are interpreted in `default_tz`.
+    pub async fn fetch_events(
+        &self,
+        spreadsheet_id: &str,
+        range: &str,
+        mapping: &CsvMapping,
+        default_tz: Tz,
+    ) -> Result<SourceEvents> {
+        let rows = self.get_values(spreadsheet_id, range).await?;
+        let source = source_name(spreadsheet_id, range);
+        let mut fetched = SourceEvents::default();
+
+        let mut rows = rows.into_iter();
+        let Some(header_row) = rows.next() else {
+            return Ok(fetched);
+        };
+        let headers = CsvHeaders::new(&header_row.iter().collect());
+        headers.check(mapping)?;
+        let mut keys = ContentKeys::default();
+
+        // The API omits trailing empty cells, which read as missing values
+        for (index, row) in rows.enumerate() {
+            let row_num = index + 1;
+            if row.iter().all(|cell| cell.trim().is_empty()) {
+                continue;
+            }
+
+            match csv_parser::parse_row(&headers, &StringRecord::from(row), row_num, mapping, default_tz, &mut keys) {
+                Ok(mut event) => {
+                    event.source = Some(source.clone());
+                    fetched.events.push(event);
+                }
+                Err(e) => fetched.diagnostics.push(RowDiagnostic {
+                    row: row_num,
+                    message: format!("{:#}", e),
+                }),
+            }
+        }
+
+        Ok(fetched)
+    }
+}
+
+/// Events read from a range in a Google Sheets spreadsheet
+pub struct SheetsSource {
+    client: SheetsClient,
+    spreadsheet_id: String,
+    range: String,
+    mapping: CsvMapping,
+}
+
+impl SheetsSource {
+    pub fn new(client: SheetsClient, spreadsheet_id: String, range: String, mapping: CsvMapping) -> Self {
+        Self { client, spreadsheet_id, range, mapping }
+    }
+}
+
+#[async_trait]
+impl EventSource for SheetsSource {
+    fn name(&self) -> String {
+        source_name(&self.spreadsheet_id, &self.range)
+    }
+
+    async fn fetch(&self, options: &FetchOptions) -> Result<SourceEvents> {
+        // Only ask for the spreadsheet's time zone when --timezone doesn't settle it
+        let sheet_tz = match options.timezone {
+            Some(_) => None,
+            None => self.client.get_time_zone(&self.spreadsheet_id).await?,
+        };
+        self.client
+            .fetch_events(&self.spreadsheet_id, &self.range, &self.mapping, options.default_timezone(sheet_tz))
+            .await
+    }
+}
+
+/// Only the sheet name is part of the identity, so widening the cell range keeps event IDs stable
+fn source_name(spreadsheet_id: &str, range: &str) -> String {
+    let sheet = range.split('!').next().unwrap_or(range).trim_matches('\'');
+    format!("sheets:{}/{}", spreadsheet_id, sheet)
+}
+
+/// Get an access token for the Sheets API using the same OAuth flow as Google Calendar
+pub async fn get_access_token(paths: &AuthPaths) -> Result<String> {
+    let auth = calendar::create_authenticator(paths).await?;
+    let token = auth
+        .token(&[SHEETS_SCOPE])
+        .await
+        .context("Failed to get Google Sheets access token")?;
+    token
+        .token()
+        .map(str::to_string)
+        .context("Google returned an empty Sheets access token")
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use tokio::io::{AsyncReadExt, AsyncWriteExt};
+    use tokio::net::TcpListener;
+
+    /// Serve canned JSON responses, one per connection, and hand back the raw request texts
+    async fn mock_server(bodies: Vec<&'static str>) -> (String, tokio::task::JoinHandle<Vec<String>>) {
+        let listener = TcpListener::bind("127.0.0.1:0").await.unwrap();
+        let base_url = format!("http://{}", listener.local_addr().unwrap());
+        let handle = tokio::spawn(async move {
+            let mut requests = Vec::new();
+            for body in bodies {
+                let (mut socket, _) = listener.accept().await.unwrap();
+                let mut buf = vec![0u8; 8192];
+                let n = socket.read(&mut buf).await.unwrap();
+                let response = format!(
+                    "HTTP/1.1 200 OK\r\nContent-Type: application/json\r\nContent-Length: {}\r\nConnection: close\r\n\r\n{}",
+                    body.len(),
+                    body
+                );
+                socket.write_all(response.as_bytes()).await.unwrap();
+                requests.push(String::from_utf8_lossy(&buf[..n]).into_owned());
+            }
+            requests
+        });
+        (base_url, handle)
+    }
+
+    #[tokio::test]
+    async fn test_fetch_events_maps_header_row() {
+        let (base_url, requests) = mock_server(vec![
+            r#"{"properties": {"timeZone": "America/New_York"}}"#,
+            r#"{
+                "range": "Season!A1:F4",
+                "majorDimension": "ROWS",
+                "values": [
+                    ["Title", "Location", "Start Date", "Start Time", "End Time", "Description"],
+                    ["Mahler 9", "Davies Hall", "3/14/2026", "7:30 PM", "10:00 PM"],
+                    [],
+                    ["Broken", "", "not a date"]
+                ]
+            }"#,
+        ])
+        .await;
+
+        let client = SheetsClient::with_base_url("test-token".to_string(), base_url);
+        let source = SheetsSource::new(
+            client,
+            "sheet123".to_string(),
+            "Season!A1:F".to_string(),
+            CsvMapping::default(),
+        );
+        let fetched = source.fetch(&FetchOptions::default()).await.unwrap();
+
+        let requests = requests.await.unwrap();
+        assert!(requests[0].starts_with("GET /v4/spreadsheets/sheet123?fields=properties.timeZone "));
+        assert!(requests[1].starts_with("GET /v4/spreadsheets/sheet123/values/Season!A1:F "));
+        assert!(requests[1].to_lowercase().contains("authorization: bearer test-token"));
+
+        assert_eq!(fetched.events.len(), 1);
+        let event = &fetched.events[0];
+        assert_eq!(event.title, "Mahler 9");
+        assert_eq!(event.location.as_deref(), Some("Davies Hall"));
+        assert_eq!(event.start_time.unwrap().format("%H:%M").to_string(), "19:30");
+        assert_eq!(event.source.as_deref(), Some("sheets:sheet123/Season"));
+        assert_eq!(event.timezone, Some(chrono_tz::America::New_York));
+
+        assert_eq!(fetched.diagnostics.len(), 1);
+        assert_eq!(fetched.diagnostics[0].row, 3);
+    }
+}
@@ -0,0 +1,105 @@
+use anyhow::Result;
+use async_trait::async_trait;
+use chrono::NaiveDate;
+use chrono_tz::Tz;
+use google_calendar3::api::Event;
+use std::fmt;
+use std::path::Path;
+
+use crate::event::{CalendarEvent, Reminders, DEFAULT_TIMEZONE};
+
+/// A problem with a single source row. The row is skipped but the import continues.
+#[derive(Debug, Clone)]
+pub struct RowDiagnostic {
+    /// 1-based position of the row in the source
+    pub row: usize,
+    pub message: String,
+}
+
+impl fmt::Display for RowDiagnostic {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "row {}: {}", self.row, self.message)
+    }
+}
+
+/// Everything read from a source: the events that parsed plus diagnostics for those that did not
+#[derive(Debug, Default)]
+pub struct SourceEvents {
+    pub events: Vec<CalendarEvent>,
+    pub diagnostics: Vec<RowDiagnostic>,
+}
+
+/// A source row as it now exists in Google Calendar, for sources that record where their rows went
+#[derive(Debug, Clone)]
+pub struct SyncedEvent {
+    /// The row's stable ID within the source (`CalendarEvent::source_id`)
+    pub source_id: String,
+    pub event_id: String,
+    /// Link to the event in the Google Calendar web UI
+    pub html_link: Option<String>,
+}
+
+impl SyncedEvent {
+    /// Pair a source event with the calendar event it was written to; `None` for events
+    /// without a source ID, which can't be traced back to a row
+    pub fn new(event: &CalendarEvent, written: &Event) -> Option<Self> {
+        Some(Self {
+            source_id: event.source_id.clone()?,
+            event_id: written.id.clone()?,
+            html_link: written.html_link.clone(),
+        })
+    }
+}
+
+/// How a file source tells itself apart in its name: the file's absolute path with symlinks
+/// resolved, so files of the same name in different folders never claim each other's events
+pub fn file_identity(path: &Path) -> String {
+    std::fs::canonicalize(path).unwrap_or_else(|_| path.to_path_buf()).display().to_string()
+}
+
+/// Settings that apply to every source for a run
+#[derive(Debug, Clone, Default)]
+pub struct FetchOptions {
+    /// Time zone from `--timezone`, used for rows that don't name their own
+    pub timezone: Option<Tz>,
+    /// Date window from `--start-date`/`--end-date`. Sources still return events outside it
+    /// (they are filtered later); it only bounds how far recurring events are expanded.
+    pub start_date: Option<NaiveDate>,
+    pub end_date: Option<NaiveDate>,
+    /// `--purchased`: sources that can filter server-side may skip rows that are not
+    /// purchased (the pipeline filters again either way)
+    pub purchased_only: bool,
+    /// Reminders from `--reminders` for events whose source sets none (filled in by the
+    /// pipeline after fetching, so sources can ignore it)
+    pub reminders: Option<Reminders>,
+}
+
+impl FetchOptions {
+    /// Time zone for rows without a `timezone` value: `--timezone` if given, else the
+    /// source's own default (e.g. the spreadsheet's time zone), else `DEFAULT_TIMEZONE`
+    pub fn default_timezone(&self, source_default: Option<Tz>) -> Tz {
+        self.timezone.or(source_default).unwrap_or(DEFAULT_TIMEZONE)
+    }
+}
+
+/// Somewhere calendar events can be read from (CSV file, Coda table, ...)
+#[async_trait]
+pub trait EventSource: Send + Sync {
+    /// Source name stamped onto every event, e.g. "csv:/home/sam/events.csv" or
+    /// "coda:<doc>/<table>"
+    fn name(&self) -> String;
+
+    /// Read all events from the source
+    async fn fetch(&self, options: &FetchOptions) -> Result<SourceEvents>;
+
+    /// Record the calendar events that rows were just created or updated as. Sources that
+    /// can't store anything (files, or tables without write-back columns) ignore this.
+    async fn write_back(&self, _synced: &[SyncedEvent]) -> Result<()> {
+        Ok(())
+    }
+
+    /// Retries and throttling of the source's API requests so far, e.g. "Coda: 2 retries"
+    fn request_summary(&self) -> Option<String> {
+        None
+    }
+}
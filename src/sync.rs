@@ -0,0 +1,506 @@
+use anyhow::{Context, Result};
+use chrono::NaiveDate;
+use futures::StreamExt;
+use google_calendar3::api::{Event, EventDateTime};
+use std::collections::HashSet;
+
+use crate::backend::CalendarBackend;
+use crate::calendar::{self, ConvertOptions, EventFailure, FoundCalendarEvent};
+use crate::event::CalendarEvent;
+use crate::journal::{self, Outcome};
+use crate::source::SyncedEvent;
+
+/// What needs to happen to bring a calendar in line with the source events
+#[derive(Debug, Default)]
+pub struct SyncPlan {
+    pub create: Vec<CalendarEvent>,
+    pub update: Vec<(CalendarEvent, FoundCalendarEvent)>,
+    pub unchanged: Vec<(CalendarEvent, FoundCalendarEvent)>,
+    pub delete: Vec<FoundCalendarEvent>,
+}
+
+impl SyncPlan {
+    /// Returns true if applying the plan would not touch the calendar
+    pub fn is_noop(&self) -> bool {
+        self.create.is_empty() && self.update.is_empty() && self.delete.is_empty()
+    }
+}
+
+/// Counts of the operations actually performed by `apply_plan`
+#[derive(Debug, Default)]
+pub struct SyncSummary {
+    pub created: usize,
+    pub updated: usize,
+    pub deleted: usize,
+    /// Created and updated events, for sources that record their calendar event
+    pub synced: Vec<SyncedEvent>,
+    /// Events that could not be written; the others were still applied
+    pub failures: Vec<EventFailure>,
+    /// How each planned operation went, in plan order: creates, updates, then deletes
+    pub outcomes: Vec<Outcome>,
+}
+
+/// Compare source events against the Google Calendar events in the same range.
+///
+/// Events are matched by their source ID stamp first, then by title (case-insensitive)
+/// and start date, the same rules `find_matching_events` uses. Each calendar event is
+/// matched at most once. When `prune` is set, unmatched calendar events stamped by one
+/// of the same sources are scheduled for deletion; events created by hand or by other
+/// sources are never pruned.
+pub fn plan_sync(
+    events: &[CalendarEvent],
+    existing: &[Event],
+    prune: bool,
+    options: &ConvertOptions,
+) -> Result<SyncPlan> {
+    let mut plan = SyncPlan::default();
+    let mut claimed = vec![false; existing.len()];
+    let mut matched: Vec<Option<usize>> = vec![None; events.len()];
+
+    // Two passes so a source ID match is never stolen by an earlier title/date match
+    let matchers: [fn(&CalendarEvent, &Event) -> bool; 2] =
+        [calendar::matches_by_source_key, calendar::matches_by_title_and_date];
+    for matcher in matchers {
+        for (event, slot) in events.iter().zip(matched.iter_mut()) {
+            if slot.is_some() {
+                continue;
+            }
+            let found = existing
+                .iter()
+                .enumerate()
+                .position(|(i, gcal)| !claimed[i] && gcal.id.is_some() && matcher(event, gcal));
+            if let Some(i) = found {
+                claimed[i] = true;
+                *slot = Some(i);
+            }
+        }
+    }
+
+    for (event, slot) in events.iter().zip(&matched) {
+        match slot {
+            Some(i) => {
+                let gcal = &existing[*i];
+                let found = to_found(gcal);
+                if needs_update(&calendar::convert_to_google_event(event, options)?, gcal) {
+                    plan.update.push((event.clone(), found));
+                } else {
+                    plan.unchanged.push((event.clone(), found));
+                }
+            }
+            None => plan.create.push(event.clone()),
+        }
+    }
+
+    if prune {
+        let sources: HashSet<&str> = events.iter().filter_map(|e| e.source.as_deref()).collect();
+        plan.delete = existing
+            .iter()
+            .zip(&claimed)
+            // Changed occurrences go with their series, so only whole events are pruned
+            .filter(|(gcal, claimed)| !**claimed && gcal.id.is_some() && !calendar::is_instance(gcal))
+            .filter(|(gcal, _)| {
+                calendar::source_key(gcal).is_some_and(|(source, _)| sources.contains(source))
+            })
+            .map(|(gcal, _)| to_found(gcal))
+            .collect();
+    }
+
+    Ok(plan)
+}
+
+/// Execute a sync plan against the calendar. Events that fail are listed in the summary;
+/// the rest of the plan is still applied.
+pub async fn apply_plan(
+    backend: &dyn CalendarBackend,
+    calendar_id: &str,
+    plan: &SyncPlan,
+    options: &ConvertOptions,
+    jobs: usize,
+) -> SyncSummary {
+    let mut summary = SyncSummary::default();
+
+    let created = calendar::create_events(backend, calendar_id, &plan.create, options, jobs).await;
+    for (event, result) in plan.create.iter().zip(created) {
+        summary.outcomes.push(journal::outcome(&result, |created| created.id.clone()));
+        match result {
+            Ok(created) => {
+                summary.created += 1;
+                summary.synced.extend(SyncedEvent::new(event, &created));
+            }
+            Err(error) => summary.failures.push(EventFailure { title: event.title.clone(), error }),
+        }
+    }
+
+    let mut patched = std::pin::pin!(calendar::run_jobs(&plan.update, jobs, |(event, found)| async move {
+        let mut google_event = calendar::convert_to_google_event(event, options)?;
+        // Patching leaves fields out of the request alone, so a series that no longer repeats
+        // has its rule cleared explicitly
+        if found.series && google_event.recurrence.is_none() {
+            google_event.recurrence = Some(Vec::new());
+        }
+        backend.patch_event(calendar_id, &found.id, google_event).await
+    }));
+    let mut updates = plan.update.iter();
+    while let Some(result) = patched.next().await {
+        let Some((event, _)) = updates.next() else { break };
+        let result = result.with_context(|| format!("Failed to update event: {}", event.title));
+        summary.outcomes.push(journal::outcome(&result, |_| None));
+        match result {
+            Ok(patched) => {
+                tracing::info!("Updated event: {}", event.title);
+                summary.updated += 1;
+                summary.synced.extend(SyncedEvent::new(event, &patched));
+            }
+            Err(error) => {
+                tracing::warn!("{:#}", error);
+                summary.failures.push(EventFailure { title: event.title.clone(), error });
+            }
+        }
+    }
+
+    let event_ids: Vec<String> = plan.delete.iter().map(|f| f.id.clone()).collect();
+    let deleted = calendar::delete_events(backend, calendar_id, &event_ids, jobs).await;
+    for (found, result) in plan.delete.iter().zip(deleted) {
+        summary.outcomes.push(journal::outcome(&result, |_| None));
+        match result {
+            Ok(()) => summary.deleted += 1,
+            Err(error) => summary.failures.push(EventFailure { title: found.title.clone(), error }),
+        }
+    }
+
+    summary
+}
+
+/// A listed event as a `FoundCalendarEvent`
+pub fn to_found(gcal: &Event) -> FoundCalendarEvent {
+    FoundCalendarEvent {
+        id: gcal.id.clone().unwrap_or_default(),
+        title: gcal.summary.clone().unwrap_or_default(),
+        date: calendar::extract_event_date(gcal).unwrap_or(NaiveDate::MIN),
+        location: gcal.location.clone(),
+        series: calendar::is_series(gcal),
+        event: gcal.clone(),
+    }
+}
+
+/// Returns true if any field we manage differs between the desired and existing event
+fn needs_update(desired: &Event, existing: &Event) -> bool {
+    desired.summary != existing.summary
+        || non_empty(&desired.description) != non_empty(&existing.description)
+        || non_empty(&desired.location) != non_empty(&existing.location)
+        || !same_time(&desired.start, &existing.start)
+        || !same_time(&desired.end, &existing.end)
+        || desired.recurrence.as_deref().unwrap_or_default() != existing.recurrence.as_deref().unwrap_or_default()
+        || calendar::source_key(desired) != calendar::source_key(existing)
+        || (desired.reminders.is_some() && reminder_key(desired) != reminder_key(existing))
+        || (desired.color_id.is_some() && desired.color_id != existing.color_id)
+        || (desired.attendees.is_some() && attendee_key(desired) != attendee_key(existing))
+}
+
+/// Invited guests as sorted (lowercase email, optional) pairs, leaving out the organizer
+fn attendee_key(event: &Event) -> Vec<(String, bool)> {
+    let mut attendees: Vec<(String, bool)> = event
+        .attendees
+        .iter()
+        .flatten()
+        .filter(|a| a.organizer != Some(true))
+        .map(|a| (a.email.as_deref().unwrap_or_default().to_lowercase(), a.optional.unwrap_or(false)))
+        .collect();
+    attendees.sort();
+    attendees
+}
+
+/// Reminder settings in a comparable form: whether defaults apply, and the sorted overrides
+fn reminder_key(event: &Event) -> (bool, Vec<(String, i32)>) {
+    let Some(reminders) = &event.reminders else {
+        return (true, Vec::new());
+    };
+    let mut overrides: Vec<(String, i32)> = reminders
+        .overrides
+        .iter()
+        .flatten()
+        .map(|r| (r.method.clone().unwrap_or_default(), r.minutes.unwrap_or_default()))
+        .collect();
+    overrides.sort();
+    (reminders.use_default.unwrap_or(false), overrides)
+}
+
+fn non_empty(s: &Option<String>) -> Option<&str> {
+    s.as_deref().filter(|s| !s.is_empty())
+}
+
+/// Compare start/end values by date or instant, and by time zone label when both have one
+fn same_time(a: &Option<EventDateTime>, b: &Option<EventDateTime>) -> bool {
+    match (a, b) {
+        (Some(a), Some(b)) => {
+            a.date == b.date
+                && a.date_time == b.date_time
+                && (a.time_zone.is_none() || b.time_zone.is_none() || a.time_zone == b.time_zone)
+        }
+        (None, None) => true,
+        _ => false,
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::backend::{listed_event, MemoryCalendar};
+    use chrono::NaiveTime;
+    use google_calendar3::api::EventAttendee;
+
+    fn source_event(title: &str, date: &str, time: Option<&str>) -> CalendarEvent {
+        let date = NaiveDate::parse_from_str(date, "%Y-%m-%d").unwrap();
+        let time = time.map(|t| NaiveTime::parse_from_str(t, "%H:%M").unwrap());
+        CalendarEvent {
+            title: title.to_string(),
+            description: None,
+            location: Some("Davies Hall".to_string()),
+            organization: None,
+            purchased: false,
+            start_date: date,
+            start_time: time,
+            end_date: date,
+            end_time: time.map(|t| t + chrono::Duration::hours(2)),
+            timezone: None,
+            source: Some("csv:season.csv".to_string()),
+            source_id: Some(title.to_lowercase().replace(' ', "-")),
+            recurrence: None,
+            reminders: None,
+            attendees: Vec::new(),
+        }
+    }
+
+    fn make_plan(events: &[CalendarEvent], existing: &[Event], prune: bool) -> SyncPlan {
+        plan_sync(events, existing, prune, &ConvertOptions::default()).unwrap()
+    }
+
+    fn to_google(event: &CalendarEvent) -> Event {
+        calendar::convert_to_google_event(event, &ConvertOptions::default()).unwrap()
+    }
+
+    #[test]
+    fn test_plan_sync_detects_unchanged_and_updates() {
+        let concert = source_event("Mahler 9", "2026-03-14", Some("19:30"));
+        let recital = source_event("Piano Recital", "2026-03-15", Some("15:00"));
+        let mut moved = recital.clone();
+        moved.start_time = NaiveTime::from_hms_opt(16, 0, 0);
+        moved.end_time = NaiveTime::from_hms_opt(18, 0, 0);
+
+        let existing = vec![listed_event("a", &concert), listed_event("b", &recital)];
+        let plan = make_plan(&[concert, moved], &existing, false);
+
+        assert_eq!(plan.unchanged.len(), 1);
+        assert_eq!(plan.update.len(), 1);
+        assert_eq!(plan.update[0].1.id, "b");
+        assert!(plan.create.is_empty());
+        assert!(plan.delete.is_empty());
+    }
+
+    #[test]
+    fn test_plan_sync_creates_and_prunes() {
+        let kept = source_event("Mahler 9", "2026-03-14", Some("19:30"));
+        let new = source_event("Opening Night", "2026-03-16", None);
+        let stale = source_event("Cancelled Gala", "2026-03-15", Some("18:00"));
+        let mut by_hand = source_event("Dinner", "2026-03-15", Some("18:00"));
+        by_hand.source = None;
+
+        let existing = vec![
+            listed_event("a", &kept),
+            listed_event("b", &stale),
+            listed_event("c", &by_hand),
+        ];
+
+        let plan = make_plan(&[kept.clone(), new.clone()], &existing, false);
+        assert_eq!(plan.create.len(), 1);
+        assert!(plan.delete.is_empty());
+
+        let plan = make_plan(&[kept, new], &existing, true);
+        assert_eq!(plan.delete.len(), 1);
+        assert_eq!(plan.delete[0].id, "b");
+    }
+
+    #[test]
+    fn test_plan_sync_matches_renamed_event_by_source_id() {
+        let original = source_event("Mahler 9", "2026-03-14", Some("19:30"));
+        let mut renamed = original.clone();
+        renamed.title = "Mahler: Symphony No. 9".to_string();
+        renamed.start_date = NaiveDate::from_ymd_opt(2026, 3, 21).unwrap();
+        renamed.end_date = renamed.start_date;
+
+        let existing = vec![listed_event("a", &original)];
+        let plan = make_plan(&[renamed], &existing, true);
+
+        assert_eq!(plan.update.len(), 1);
+        assert_eq!(plan.update[0].1.id, "a");
+        assert!(plan.create.is_empty());
+        assert!(plan.delete.is_empty());
+    }
+
+    #[test]
+    fn test_plan_sync_stamps_legacy_events() {
+        let event = source_event("Matinee", "2026-03-14", Some("14:00"));
+        let mut legacy = listed_event("a", &event);
+        legacy.extended_properties = None;
+
+        let plan = make_plan(&[event], &[legacy], false);
+        assert_eq!(plan.update.len(), 1);
+    }
+
+    #[tokio::test]
+    async fn test_apply_plan_only_touches_changed_events() {
+        let concert = source_event("Mahler 9", "2026-03-14", Some("19:30"));
+        let recital = source_event("Piano Recital", "2026-03-15", Some("15:00"));
+        let stale = source_event("Cancelled Gala", "2026-03-15", Some("18:00"));
+        let new = source_event("Opening Night", "2026-03-16", None);
+        let mut moved = recital.clone();
+        moved.location = Some("Herbst Theatre".to_string());
+
+        let backend = MemoryCalendar::new()
+            .with_event("primary", to_google(&concert))
+            .with_event("primary", to_google(&recital))
+            .with_event("primary", to_google(&stale));
+        let existing = backend.events("primary");
+
+        let events = vec![concert, moved, new];
+        let plan = make_plan(&events, &existing, true);
+        let summary = apply_plan(&backend, "primary", &plan, &ConvertOptions::default(), 1).await;
+        assert_eq!((summary.created, summary.updated, summary.deleted), (1, 1, 1));
+        assert_eq!(backend.calls().len(), 3);
+        let synced: Vec<&str> = summary.synced.iter().map(|s| s.source_id.as_str()).collect();
+        assert_eq!(synced, ["opening-night", "piano-recital"]);
+
+        // A second run against the updated calendar has nothing left to do
+        let plan = make_plan(&events, &backend.events("primary"), true);
+        assert!(plan.is_noop());
+        assert_eq!(plan.unchanged.len(), 3);
+        assert!(backend
+            .events("primary")
+            .iter()
+            .any(|e| e.location.as_deref() == Some("Herbst Theatre")));
+    }
+
+    #[test]
+    fn test_plan_sync_matches_each_calendar_event_once() {
+        let event = source_event("Matinee", "2026-03-14", Some("14:00"));
+        let existing = vec![listed_event("a", &event)];
+        let plan = make_plan(&[event.clone(), event], &existing, false);
+        assert_eq!(plan.unchanged.len(), 1);
+        assert_eq!(plan.create.len(), 1);
+    }
+
+    fn weekly(event: &CalendarEvent) -> CalendarEvent {
+        let mut event = event.clone();
+        event.recurrence = Some(crate::recurrence::Recurrence::parse("FREQ=WEEKLY", None).unwrap());
+        event
+    }
+
+    #[test]
+    fn test_plan_sync_distinguishes_series_and_instances() {
+        let single = source_event("Rehearsal", "2026-03-02", Some("18:00"));
+        let series = weekly(&single);
+
+        // A stamped series and one of its occurrences that was moved by hand
+        let mut moved = listed_event("a_20260309", &single);
+        moved.recurring_event_id = Some("a".to_string());
+        let existing = vec![listed_event("a", &series), moved];
+        let mut unstamped = series.clone();
+        unstamped.source = None;
+
+        let plan = make_plan(&[series], &existing, true);
+        assert_eq!(plan.unchanged.len(), 1);
+        assert!(plan.unchanged[0].1.series);
+        assert!(plan.delete.is_empty());
+
+        // Without the stamp, a repeating row only matches a series by title and date
+        let plan = make_plan(&[unstamped], &[listed_event("b", &single)], false);
+        assert_eq!(plan.create.len(), 1);
+    }
+
+    #[tokio::test]
+    async fn test_apply_plan_updates_recurrence() {
+        let single = source_event("Rehearsal", "2026-03-02", Some("18:00"));
+        let series = weekly(&single);
+        let backend = MemoryCalendar::new().with_event("primary", to_google(&single));
+
+        let plan = make_plan(&[series], &backend.events("primary"), false);
+        assert_eq!(plan.update.len(), 1);
+        apply_plan(&backend, "primary", &plan, &ConvertOptions::default(), 1).await;
+        assert_eq!(
+            backend.events("primary")[0].recurrence.as_deref(),
+            Some(&["RRULE:FREQ=WEEKLY".to_string()][..])
+        );
+
+        // Turning the series back into a single event clears the rule
+        let plan = make_plan(std::slice::from_ref(&single), &backend.events("primary"), false);
+        assert_eq!(plan.update.len(), 1);
+        apply_plan(&backend, "primary", &plan, &ConvertOptions::default(), 1).await;
+        assert!(!calendar::is_series(&backend.events("primary")[0]));
+        assert!(make_plan(&[single], &backend.events("primary"), false).is_noop());
+    }
+
+    #[test]
+    fn test_plan_sync_updates_changed_reminders() {
+        let event = source_event("Mahler 9", "2026-03-14", Some("19:30"));
+        let mut with_reminders = event.clone();
+        with_reminders.reminders = Some("popup:120,email:1440".parse().unwrap());
+
+        // Events that set no reminders leave whatever the calendar has alone
+        let existing = vec![listed_event("a", &with_reminders)];
+        assert!(make_plan(std::slice::from_ref(&event), &existing, false).is_noop());
+
+        let mut reordered = event.clone();
+        reordered.reminders = Some("email:1440,popup:120".parse().unwrap());
+        assert!(make_plan(&[reordered], &existing, false).is_noop());
+
+        let existing = vec![listed_event("a", &event)];
+        let plan = make_plan(&[with_reminders], &existing, false);
+        assert_eq!(plan.update.len(), 1);
+    }
+
+    #[test]
+    fn test_plan_sync_updates_changed_attendees() {
+        let mut event = source_event("Mahler 9", "2026-03-14", Some("19:30"));
+        event.attendees = crate::event::parse_attendees("sam@example.com, optional:alex@example.org").unwrap();
+
+        // Google lists the organizer among the attendees and may change email case
+        let mut existing = listed_event("a", &event);
+        let attendees = existing.attendees.as_mut().unwrap();
+        attendees[0].email = Some("Sam@Example.com".to_string());
+        attendees.push(EventAttendee {
+            email: Some("me@example.com".to_string()),
+            organizer: Some(true),
+            ..Default::default()
+        });
+        assert!(make_plan(std::slice::from_ref(&event), &[existing.clone()], false).is_noop());
+
+        let mut required = event.clone();
+        required.attendees[1].optional = false;
+        assert_eq!(make_plan(&[required], &[existing], false).update.len(), 1);
+    }
+
+    #[tokio::test]
+    async fn test_apply_plan_collects_failures_and_continues() {
+        let events: Vec<CalendarEvent> = ["Mahler 9", "Piano Recital", "Opening Night", "Brahms 4"]
+            .iter()
+            .enumerate()
+            .map(|(i, title)| source_event(title, &format!("2026-03-{}", 14 + i), Some("19:30")))
+            .collect();
+        let stale = source_event("Cancelled Gala", "2026-03-15", Some("18:00"));
+        let backend = MemoryCalendar::new()
+            .with_event("primary", listed_event("gala", &stale))
+            .failing_on("Piano Recital")
+            .failing_on("gala");
+
+        let plan = make_plan(&events, &backend.events("primary"), true);
+        let summary = apply_plan(&backend, "primary", &plan, &ConvertOptions::default(), 3).await;
+        assert_eq!((summary.created, summary.deleted), (3, 0));
+        let failed: Vec<&str> = summary.failures.iter().map(|f| f.title.as_str()).collect();
+        assert_eq!(failed, ["Piano Recital", "Cancelled Gala"]);
+        // Results come back in plan order whatever order the requests finish in
+        let synced: Vec<&str> = summary.synced.iter().map(|s| s.source_id.as_str()).collect();
+        assert_eq!(synced, ["mahler-9", "opening-night", "brahms-4"]);
+        let outcomes: Vec<bool> = summary.outcomes.iter().map(Result::is_ok).collect();
+        assert_eq!(outcomes, [true, false, true, true, false]);
+    }
+}